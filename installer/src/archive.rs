@@ -0,0 +1,136 @@
+use anyhow::{anyhow, Result};
+use chrono::{Local, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+pub const ARCHIVE_URL: &str = "https://archive.archlinux.org";
+
+/// The first day the archive has snapshots of the repositories for
+const FIRST_SNAPSHOT: &str = "2013-08-31";
+
+/// Installs the packages as they were on a given day, from the
+/// [Arch Linux Archive](https://wiki.archlinux.org/title/Arch_Linux_Archive).
+/// The mirrorlist points at the snapshot instead of what reflector finds.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveSnapshot {
+    /// YYYY-MM-DD
+    pub date: String,
+    /// Leaves the new system's mirrorlist on the snapshot after the install.
+    /// Otherwise reflector replaces it once everything is installed.
+    #[serde(default)]
+    pub keep_pinned: bool,
+    /// Another server with the same layout, eg. a local copy of the archive.
+    #[serde(default = "default_url")]
+    pub url: String,
+}
+
+fn default_url() -> String {
+    ARCHIVE_URL.to_string()
+}
+
+impl ArchiveSnapshot {
+    /// Takes the date as YYYY-MM-DD or YYYY/MM/DD
+    pub fn new(date: &str, keep_pinned: bool) -> Result<ArchiveSnapshot> {
+        let snapshot = ArchiveSnapshot {
+            date: parse_date(date)?.format("%Y-%m-%d").to_string(),
+            keep_pinned,
+            url: default_url(),
+        };
+        snapshot.validate()?;
+        Ok(snapshot)
+    }
+
+    /// Checks that the archive can have a snapshot of that day.
+    pub fn validate(&self) -> Result<()> {
+        let date = parse_date(&self.date)?;
+        if date > Local::now().date_naive() {
+            return Err(anyhow!("{} is in the future", self.date));
+        }
+        if self.url == ARCHIVE_URL && date < parse_date(FIRST_SNAPSHOT)? {
+            return Err(anyhow!(
+                "The archive has no snapshots before {}",
+                FIRST_SNAPSHOT
+            ));
+        }
+        Ok(())
+    }
+
+    /// eg. https://archive.archlinux.org/repos/2024/05/01
+    pub fn repos_url(&self) -> Result<String> {
+        Ok(format!(
+            "{}/repos/{}",
+            self.url.trim_end_matches('/'),
+            parse_date(&self.date)?.format("%Y/%m/%d")
+        ))
+    }
+
+    /// A mirrorlist with the snapshot as the only server.
+    /// The header tells whoever reads it how to unpin.
+    pub fn mirrorlist(&self) -> Result<String> {
+        Ok(format!(
+            "# Pinned to the Arch Linux Archive snapshot of {} by 2lazy4arch.
+# To unpin, replace this file with a regular mirrorlist, eg. with reflector
+# or from mirrorlist.pacnew, then run pacman -Syyu.
+Server = {}/$repo/os/$arch
+",
+            self.date,
+            self.repos_url()?
+        ))
+    }
+}
+
+fn parse_date(date: &str) -> Result<NaiveDate> {
+    let date = date.trim();
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(date, "%Y/%m/%d"))
+        .map_err(|_| anyhow!("{} is not a date, expected YYYY-MM-DD", date))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mirrorlist_points_at_the_snapshot() {
+        let snapshot = ArchiveSnapshot::new("2024/05/01", false).unwrap();
+        assert_eq!(snapshot.date, "2024-05-01");
+        assert_eq!(
+            snapshot.repos_url().unwrap(),
+            "https://archive.archlinux.org/repos/2024/05/01"
+        );
+
+        let mirrorlist = snapshot.mirrorlist().unwrap();
+        assert!(mirrorlist.starts_with("# Pinned to the Arch Linux Archive snapshot of 2024-05-01"));
+        let servers = mirrorlist
+            .lines()
+            .filter(|x| !x.starts_with('#'))
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            servers,
+            ["Server = https://archive.archlinux.org/repos/2024/05/01/$repo/os/$arch"]
+        );
+    }
+
+    #[test]
+    fn other_servers() {
+        let snapshot = ArchiveSnapshot {
+            date: "2012-01-02".to_string(),
+            keep_pinned: true,
+            url: "http://archive.lan/".to_string(),
+        };
+        // a local copy can go back further than the archive
+        assert!(snapshot.validate().is_ok());
+        assert!(snapshot
+            .mirrorlist()
+            .unwrap()
+            .contains("\nServer = http://archive.lan/repos/2012/01/02/$repo/os/$arch\n"));
+    }
+
+    #[test]
+    fn rejects_dates_the_archive_does_not_have() {
+        assert!(ArchiveSnapshot::new("2013-08-30", false).is_err());
+        assert!(ArchiveSnapshot::new("2013-08-31", false).is_ok());
+        assert!(ArchiveSnapshot::new("9999-01-01", false).is_err());
+        assert!(ArchiveSnapshot::new("01.05.2024", false).is_err());
+        assert!(ArchiveSnapshot::new("2024-02-30", false).is_err());
+    }
+}
@@ -1,64 +1,211 @@
-use std::{fs::OpenOptions, io::Write};
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::Path,
+};
 
 use anyhow::{anyhow, Result};
-use shell_iface::{logger::Logger, Shell};
+use shell_iface::{logger::Logger, progress::Progress, Shell};
 
-use crate::utils::get_processor_make;
+use crate::{
+    kernel::Kernel,
+    offline::OfflineSource,
+    pacman::{CacheSharing, PACKAGE_CACHE},
+    pacman_error::PacmanError,
+    target::InstallTarget,
+    transaction::{pacstrap_download_sizes, TransactionProgress},
+    utils::get_processor_make,
+};
 
 /* This module contains all the utility fns for smaller base installation. */
 pub struct BaseInstaller<'a> {
     shell: Shell<'a>,
+    target: InstallTarget,
+    kernels: Vec<Kernel>,
+    copy_host_keyring: bool,
+    offline: Option<OfflineSource>,
+    cache_sharing: CacheSharing,
 }
 
 impl<'a> BaseInstaller<'a> {
     pub fn new<'b>(logger: &'b Logger) -> BaseInstaller<'b> {
         let shell = Shell::new("Base Installer", logger);
-        BaseInstaller { shell }
+        BaseInstaller {
+            shell,
+            target: InstallTarget::default(),
+            kernels: vec![Kernel::Linux],
+            copy_host_keyring: false,
+            offline: None,
+            cache_sharing: CacheSharing::Separate,
+        }
+    }
+
+    /// Sets where pacstrap and genfstab operate on.
+    pub fn set_target(&mut self, target: &InstallTarget) {
+        self.target = target.clone();
+    }
+
+    /// Sets the kernels pacstrap installs.
+    pub fn set_kernels(&mut self, kernels: &[Kernel]) {
+        self.kernels = kernels.to_vec();
+    }
+
+    /// Copies the live environment's keyring instead of starting a fresh one.
+    /// Needed when keys of extra repositories were imported,
+    /// otherwise their packages cannot be verified in the new system.
+    pub fn set_copy_host_keyring(&mut self, copy_host_keyring: bool) {
+        self.copy_host_keyring = copy_host_keyring;
+    }
+
+    /// Installs from a local directory with pacstrap using its pacman.conf.
+    pub fn set_offline(&mut self, offline: Option<&OfflineSource>) {
+        self.offline = offline.cloned();
+    }
+
+    /// Sets how pacstrap uses the live environment's package cache.
+    pub fn set_cache_sharing(&mut self, cache_sharing: CacheSharing) {
+        self.cache_sharing = cache_sharing;
+    }
+
+    /// Copies the live environment's packages into the new system's cache.
+    /// Packages already there are left alone.
+    pub fn copy_host_cache(&mut self) -> Result<()> {
+        let destination = self.target.root().path(PACKAGE_CACHE);
+        self.shell.log(&format!(
+            "Copying {} to {}",
+            PACKAGE_CACHE,
+            destination.display()
+        ));
+        fs::create_dir_all(&destination)?;
+
+        let mut copied = 0;
+        for entry in fs::read_dir(PACKAGE_CACHE)?.filter_map(|x| x.ok()) {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !path.is_file() || !name.contains(".pkg.tar") {
+                continue;
+            }
+            let target = destination.join(&name);
+            if Path::new(&target).exists() {
+                continue;
+            }
+            if let Err(e) = fs::copy(&path, &target) {
+                self.shell
+                    .log(&format!("Could not copy {}: {}", path.display(), e));
+                return Err(anyhow!("Could not copy the package cache"));
+            }
+            copied += 1;
+        }
+
+        self.shell.log(&format!("Copied {} packages", copied));
+        Ok(())
+    }
+
+    /// Everything pacstrap installs.
+    pub fn packages(&self) -> Vec<String> {
+        let mut packages = vec!["base".to_string()];
+        // containers share the host's kernel, they do not need one of their own.
+        if !self.target.is_directory() {
+            packages.extend(self.kernels.iter().map(|x| x.package().to_string()));
+            packages.push("linux-firmware".to_string());
+            if let Some(p) = get_processor_make() {
+                packages.push(format!("{}-ucode", p));
+            }
+        }
+        packages.extend(["neovim".to_string(), "reflector".to_string()]);
+        packages
     }
 
     /// Installs the base packages
     pub fn base_packages_install(&mut self) -> Result<()> {
-        self.shell.log(&"Installing base packages.");
+        self.shell.log("Installing base packages.");
 
-        let package_cmd = if let Some(p) = get_processor_make() {
-            format!("-K /mnt base linux linux-firmware {}-ucode neovim reflector", p)
-        } else {
-            String::from("-K /mnt base linux linux-firmware neovim reflector")
+        let root = self.target.root();
+        let keyring = if self.copy_host_keyring { "" } else { "-K " };
+        // -c reads the packages from the CacheDir of pacman.conf instead of the target's
+        let config = match (&self.offline, self.cache_sharing) {
+            (Some(_), _) => format!("-c -C {} ", OfflineSource::conf_path()),
+            (None, CacheSharing::Bind) => "-c ".to_string(),
+            (None, _) => String::new(),
         };
+        let packages = self.packages();
+        let package_cmd = format!(
+            "{}{}{} {}",
+            keyring,
+            config,
+            root,
+            packages.join(" ")
+        );
 
-        match self.shell.run_and_wait_with_args("pacstrap", &package_cmd) {
-            Ok(_) => Ok(()),
+        // an offline install downloads nothing
+        let sizes = match self.offline {
+            Some(_) => HashMap::new(),
+            None => pacstrap_download_sizes(&mut self.shell, &packages),
+        };
+        let mut progress = TransactionProgress::new(sizes);
+        let logger = self.shell.logger();
+        // pacman prints the names in conflicts and missing dependencies to stdout
+        let mut output = String::new();
+        let (status, stderr) = match self.shell.run_and_wait_with_args_reading_output(
+            "pacstrap",
+            &package_cmd,
+            |line| {
+                output.push_str(line);
+                output.push('\n');
+                if let Some(x) = progress.parse_line(line) {
+                    logger.progress(&x);
+                }
+            },
+        ) {
+            Ok(x) => x,
             Err(e) => {
                 self.shell.log(&format!(
                     "Failed to install base packages: ORIGINAL ERROR: {}",
                     e
                 ));
-                Err(anyhow!("Could not install base packages."))
+                return Err(anyhow!("Could not install base packages."));
+            }
+        };
+
+        if !status.success() {
+            self.shell
+                .log(&format!("Failed to install base packages: {}", status));
+            // eg. signature errors, common with stale ISOs, tell the user how to fix them
+            output.push_str(&stderr);
+            if let Some(error) = PacmanError::parse(&output) {
+                self.shell.log(&error.to_string());
+                return Err(anyhow::Error::new(error));
             }
+            return Err(anyhow!("Could not install base packages."));
         }
+        self.shell.progress(&Progress::Done);
+        Ok(())
     }
 
     /// Generates and Writes fstab configuration.
     pub fn genfstab(&mut self) -> Result<()> {
-        self.shell.log(&"Generating fstab.");
-        let output = self.shell.run_with_args("genfstab", "-U /mnt")?;
+        self.shell.log("Generating fstab.");
+        let root = self.target.root();
+        let output = self.shell.run_with_args("genfstab", &format!("-U {}", root))?;
 
+        let fstab_path = root.path("/etc/fstab");
         let mut fstab = match OpenOptions::new()
             .append(true)
             .create(true)
-            .open("/mnt/etc/fstab")
+            .open(&fstab_path)
         {
             Ok(x) => x,
             Err(e) => {
                 self.shell
-                    .log(&format!("Could not open /mnt/etc/fstab. {}", e));
+                    .log(&format!("Could not open {}. {}", fstab_path.display(), e));
                 return Err(anyhow!("Could not open fstab"));
             }
         };
 
         if let Err(e) = fstab.write(&output.stdout) {
             self.shell
-                .log(&format!("Could not write to /mnt/etc/fstab. {}", e));
+                .log(&format!("Could not write to {}. {}", fstab_path.display(), e));
             return Err(anyhow!("Could not write to fstab"));
         }
 
@@ -0,0 +1,255 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    os::unix::fs::{FileTypeExt, OpenOptionsExt},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use shell_iface::{logger::Logger, Shell};
+
+use crate::{
+    config::InstallConfig,
+    plan::{InstallContext, CREATE_USERS, SET_ROOT_PASSWORD},
+};
+
+/// Inside the new system, so it survives a reboot and goes away if root is formatted again
+pub const CHECKPOINT_PATH: &str = "/var/lib/2lazy4arch/checkpoint.json";
+/// Where a root partition is mounted to read its checkpoint
+const PEEK_DIR: &str = "/tmp/2lazy4arch-resume";
+
+/// How far an install got, and everything it was set up with, so it can be resumed.
+/// Has the passwords in it until the steps that set them are done. Only root can read it,
+/// it is left out of a packed rootfs and removed once the install succeeds.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub config: InstallConfig,
+    /// Names of the steps that are done, see plan::install_plan
+    pub completed: Vec<String>,
+}
+
+impl Checkpoint {
+    /// The install as it is set up in the context, with the steps done so far.
+    pub fn new(context: &InstallContext, completed: &[&str]) -> Result<Checkpoint> {
+        let settings = context.settings;
+        let mut config = InstallConfig::from_installer(
+            context.filesystem,
+            context.essentials,
+            context.pacman,
+            &settings.timezone,
+            &settings.locale,
+            &settings.encoding,
+            settings.swap_size,
+            &settings.username,
+            &settings.hostname,
+        )?;
+        config.root_password = settings.root_password.clone();
+        config.users.truncate(1);
+        config.users[0].password = settings.password.clone();
+        config.users.extend(context.essentials.extra_users.iter().cloned());
+        if completed.contains(&SET_ROOT_PASSWORD) {
+            config.root_password.clear();
+        }
+        if completed.contains(&CREATE_USERS) {
+            config.users.iter_mut().for_each(|x| x.password.clear());
+        }
+
+        Ok(Checkpoint {
+            config,
+            completed: completed.iter().map(|x| x.to_string()).collect(),
+        })
+    }
+
+    /// Writes it onto the new system, once there is one to write to.
+    pub fn save(&self, context: &InstallContext) -> Result<()> {
+        let path = match checkpoint_path(context) {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Created only readable by root, it is never readable by others even for a moment
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&path)?;
+        file.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+        Ok(())
+    }
+
+    /// Reads the checkpoint of an install, given its root partition or directory.
+    /// A partition is mounted read only while it is read.
+    pub fn find(root: &str, logger: &Logger) -> Result<Checkpoint> {
+        let root = root.trim();
+        if Path::new(root).is_dir() {
+            return Checkpoint::load(&Path::new(root).join(CHECKPOINT_PATH.trim_start_matches('/')));
+        }
+
+        let is_block_device = fs::metadata(root).is_ok_and(|x| x.file_type().is_block_device());
+        if !is_block_device {
+            return Err(anyhow!("{} is neither a partition nor a directory", root));
+        }
+
+        let mut shell = Shell::new("Checkpoint", logger);
+        shell.run_and_wait_with_args("mount", &format!("--mkdir -o ro {} {}", root, PEEK_DIR))?;
+        let checkpoint =
+            Checkpoint::load(&Path::new(PEEK_DIR).join(CHECKPOINT_PATH.trim_start_matches('/')));
+        shell.run_and_wait_with_args("umount", PEEK_DIR)?;
+        checkpoint
+    }
+
+    fn load(path: &Path) -> Result<Checkpoint> {
+        let content = fs::read_to_string(path).map_err(|e| {
+            anyhow!(
+                "There is nothing to resume, {} could not be read: {}",
+                path.display(),
+                e
+            )
+        })?;
+        serde_json::from_str(&content)
+            .map_err(|e| anyhow!("{} is not a valid checkpoint: {}", path.display(), e))
+    }
+}
+
+/// Inside chroot the new system is /. Before the partitions are mounted there is nowhere to
+/// write to, the steps until then are quick to do again.
+pub fn checkpoint_path(context: &InstallContext) -> Option<PathBuf> {
+    if context.essentials.in_chroot() {
+        return Some(PathBuf::from(CHECKPOINT_PATH));
+    }
+    if !context.target.is_directory() && !context.filesystem.is_mounted() {
+        return None;
+    }
+    Some(context.target.root().path(CHECKPOINT_PATH))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+    use crate::{
+        base_installer::BaseInstaller,
+        essentials::{Bootloader, Essentials, SuperUserUtility, User},
+        filesystem_tasks::Filesystem,
+        pacman::Pacman,
+        plan::InstallSettings,
+    };
+
+    fn settings() -> InstallSettings {
+        InstallSettings {
+            timezone: "Europe/Berlin".to_string(),
+            locale: "de_DE.UTF-8".to_string(),
+            encoding: "UTF-8".to_string(),
+            swap_size: 4,
+            username: "alice".to_string(),
+            password: "alice's".to_string(),
+            root_password: "root's".to_string(),
+            hostname: "workstation".to_string(),
+        }
+    }
+
+    #[test]
+    fn saves_and_finds_a_directory_install() {
+        let logger = Logger::new(false);
+        let root =
+            std::env::temp_dir().join(format!("2lazy4arch-checkpoint-{}", std::process::id()));
+        let mut filesystem = Filesystem::new(&logger);
+        filesystem.set_target_directory(root.to_str().unwrap(), None).unwrap();
+        let mut base_installer = BaseInstaller::new(&logger);
+        let mut essentials = Essentials::new(&logger, Bootloader::Grub, SuperUserUtility::Sudo);
+        essentials.extra_users = vec![User {
+            name: "bob".to_string(),
+            password: "bob's".to_string(),
+        }];
+        let mut pacman = Pacman::new(&logger);
+        let settings = settings();
+        let context = InstallContext::new(
+            &logger,
+            &mut filesystem,
+            &mut base_installer,
+            &mut essentials,
+            &mut pacman,
+            &settings,
+        );
+
+        let checkpoint = Checkpoint::new(&context, &["Set up the installers"]).unwrap();
+        let saved = checkpoint.save(&context);
+        let path = root.join(CHECKPOINT_PATH.trim_start_matches('/'));
+        let mode = fs::metadata(&path).map(|x| x.permissions().mode() & 0o777);
+        let found = Checkpoint::find(root.to_str().unwrap(), &logger);
+        let _ = fs::remove_dir_all(&root);
+
+        saved.unwrap();
+        assert_eq!(mode.unwrap(), 0o600);
+        let found = found.unwrap();
+        assert_eq!(found, checkpoint);
+        assert_eq!(found.completed, ["Set up the installers"]);
+        assert_eq!(found.config.root_password, "root's");
+        let passwords: Vec<&str> =
+            found.config.users.iter().map(|x| x.password.as_str()).collect();
+        assert_eq!(passwords, ["alice's", "bob's"]);
+    }
+
+    #[test]
+    fn passwords_are_dropped_once_set() {
+        let logger = Logger::new(false);
+        let root =
+            std::env::temp_dir().join(format!("2lazy4arch-passwords-{}", std::process::id()));
+        let mut filesystem = Filesystem::new(&logger);
+        filesystem.set_target_directory(root.to_str().unwrap(), None).unwrap();
+        let _ = fs::remove_dir_all(&root);
+        let mut base_installer = BaseInstaller::new(&logger);
+        let mut essentials = Essentials::new(&logger, Bootloader::Grub, SuperUserUtility::Sudo);
+        let mut pacman = Pacman::new(&logger);
+        let settings = settings();
+        let context = InstallContext::new(
+            &logger,
+            &mut filesystem,
+            &mut base_installer,
+            &mut essentials,
+            &mut pacman,
+            &settings,
+        );
+
+        let checkpoint = Checkpoint::new(&context, &[SET_ROOT_PASSWORD]).unwrap();
+        assert!(checkpoint.config.root_password.is_empty());
+        assert_eq!(checkpoint.config.users[0].password, "alice's");
+
+        let checkpoint = Checkpoint::new(&context, &[SET_ROOT_PASSWORD, CREATE_USERS]).unwrap();
+        assert!(checkpoint.config.users[0].password.is_empty());
+    }
+
+    #[test]
+    fn path_is_known_once_mounted() {
+        let logger = Logger::new(false);
+        let mut filesystem = Filesystem::new(&logger);
+        let mut base_installer = BaseInstaller::new(&logger);
+        let mut essentials = Essentials::new(&logger, Bootloader::Grub, SuperUserUtility::Sudo);
+        let mut pacman = Pacman::new(&logger);
+        let settings = settings();
+        let context = InstallContext::new(
+            &logger,
+            &mut filesystem,
+            &mut base_installer,
+            &mut essentials,
+            &mut pacman,
+            &settings,
+        );
+
+        assert_eq!(checkpoint_path(&context), None);
+
+        context.filesystem.mounted = true;
+        assert_eq!(
+            checkpoint_path(&context),
+            Some(PathBuf::from("/mnt/var/lib/2lazy4arch/checkpoint.json"))
+        );
+
+        context.essentials.is_chroot = true;
+        assert_eq!(checkpoint_path(&context), Some(PathBuf::from(CHECKPOINT_PATH)));
+    }
+}
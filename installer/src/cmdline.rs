@@ -0,0 +1,185 @@
+use serde::{Deserialize, Serialize};
+
+/// Kernel parameters shared by every boot entry.
+/// root= is not part of this, each bootloader finds the root on its own.
+/// Rendered the same way into systemd-boot entries and GRUB_CMDLINE_LINUX_DEFAULT.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KernelCmdline {
+    /// loglevel=3 quiet
+    pub quiet: bool,
+    pub splash: bool,
+    /// nvidia-drm.modeset=1, needed for wayland on the proprietary driver
+    pub nvidia_modeset: bool,
+    /// Swap device to resume from after hibernation, eg. UUID=...
+    pub resume: Option<String>,
+    /// Encrypted root, eg. UUID=...:cryptroot
+    /// Passed as cryptdevice= to the encrypt hook or as rd.luks.name= to sd-encrypt.
+    pub cryptdevice: Option<String>,
+    /// console=ttyS0, for headless machines and VMs
+    pub serial_console: bool,
+    /// Anything else, added as is
+    pub extra: Vec<String>,
+}
+
+/// Which hooks the initramfs is built with, they take the encrypted root differently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InitramfsHooks {
+    /// busybox based, encrypt reads cryptdevice=
+    Busybox,
+    /// the systemd hook, sd-encrypt reads rd.luks.name=
+    Systemd,
+}
+
+impl Default for KernelCmdline {
+    /// Same as what Arch ships in /etc/default/grub
+    fn default() -> Self {
+        KernelCmdline {
+            quiet: true,
+            splash: false,
+            nvidia_modeset: false,
+            resume: None,
+            cryptdevice: None,
+            serial_console: false,
+            extra: Vec::new(),
+        }
+    }
+}
+
+impl KernelCmdline {
+    /// Every parameter in the order they are written.
+    pub fn params(&self, hooks: InitramfsHooks) -> Vec<String> {
+        let mut params = Vec::new();
+
+        if let Some(cryptdevice) = &self.cryptdevice {
+            params.push(encrypted_root(cryptdevice, hooks));
+        }
+        if let Some(resume) = &self.resume {
+            params.push(format!("resume={}", resume));
+        }
+        if self.quiet {
+            params.push("loglevel=3".to_string());
+            params.push("quiet".to_string());
+        }
+        if self.splash {
+            params.push("splash".to_string());
+        }
+        if self.nvidia_modeset {
+            params.push("nvidia-drm.modeset=1".to_string());
+        }
+        if self.serial_console {
+            params.push("console=ttyS0".to_string());
+        }
+        params.extend(self.extra.iter().cloned());
+
+        params
+    }
+
+    /// Sets the free form parameters from a space separated string.
+    pub fn set_extra(&mut self, extra: &str) {
+        self.extra = extra.split_whitespace().map(|x| x.to_string()).collect();
+    }
+
+    pub fn render(&self, hooks: InitramfsHooks) -> String {
+        self.params(hooks).join(" ")
+    }
+}
+
+/// UUID=<uuid>:<name> becomes rd.luks.name=<uuid>=<name> for sd-encrypt.
+/// sd-encrypt only takes UUIDs, anything else is left as cryptdevice=.
+fn encrypted_root(cryptdevice: &str, hooks: InitramfsHooks) -> String {
+    if hooks == InitramfsHooks::Systemd {
+        let (device, name) = match cryptdevice.rsplit_once(':') {
+            Some((device, name)) => (device, Some(name)),
+            None => (cryptdevice, None),
+        };
+        if let Some(uuid) = device.strip_prefix("UUID=") {
+            return match name {
+                Some(name) => format!("rd.luks.name={}={}", uuid, name),
+                None => format!("rd.luks.uuid={}", uuid),
+            };
+        }
+    }
+    format!("cryptdevice={}", cryptdevice)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_is_what_arch_ships() {
+        let cmdline = KernelCmdline::default();
+        assert_eq!(cmdline.render(InitramfsHooks::Busybox), "loglevel=3 quiet");
+        assert_eq!(cmdline.render(InitramfsHooks::Systemd), "loglevel=3 quiet");
+
+        let verbose = KernelCmdline {
+            quiet: false,
+            ..KernelCmdline::default()
+        };
+        assert_eq!(verbose.render(InitramfsHooks::Busybox), "");
+    }
+
+    #[test]
+    fn params_are_in_order() {
+        let mut cmdline = KernelCmdline {
+            quiet: true,
+            splash: true,
+            nvidia_modeset: true,
+            resume: Some("UUID=4e5f".to_string()),
+            cryptdevice: Some("UUID=0a1b:cryptroot".to_string()),
+            serial_console: true,
+            extra: Vec::new(),
+        };
+        cmdline.set_extra("mitigations=off  iommu=pt");
+
+        assert_eq!(
+            cmdline.params(InitramfsHooks::Busybox),
+            [
+                "cryptdevice=UUID=0a1b:cryptroot",
+                "resume=UUID=4e5f",
+                "loglevel=3",
+                "quiet",
+                "splash",
+                "nvidia-drm.modeset=1",
+                "console=ttyS0",
+                "mitigations=off",
+                "iommu=pt",
+            ]
+        );
+        assert_eq!(
+            cmdline.render(InitramfsHooks::Systemd),
+            "rd.luks.name=0a1b=cryptroot resume=UUID=4e5f loglevel=3 quiet splash \
+nvidia-drm.modeset=1 console=ttyS0 mitigations=off iommu=pt"
+        );
+    }
+
+    #[test]
+    fn set_extra_splits_on_whitespace() {
+        let mut cmdline = KernelCmdline::default();
+        cmdline.set_extra("  nowatchdog\tmodule_blacklist=pcspkr \n");
+        assert_eq!(cmdline.extra, ["nowatchdog", "module_blacklist=pcspkr"]);
+
+        cmdline.set_extra("   ");
+        assert!(cmdline.extra.is_empty());
+    }
+
+    #[test]
+    fn sd_encrypt_takes_only_uuids() {
+        let root = |cryptdevice: &str| KernelCmdline {
+            quiet: false,
+            cryptdevice: Some(cryptdevice.to_string()),
+            ..KernelCmdline::default()
+        };
+
+        assert_eq!(root("UUID=0a1b").render(InitramfsHooks::Systemd), "rd.luks.uuid=0a1b");
+        assert_eq!(
+            root("/dev/sda2:cryptroot").render(InitramfsHooks::Systemd),
+            "cryptdevice=/dev/sda2:cryptroot"
+        );
+        assert_eq!(
+            root("UUID=0a1b").render(InitramfsHooks::Busybox),
+            "cryptdevice=UUID=0a1b"
+        );
+    }
+}
@@ -0,0 +1,653 @@
+use std::{collections::BTreeMap, fs, os::unix::fs::FileTypeExt, path::Path};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    archive::ArchiveSnapshot,
+    base_installer::BaseInstaller,
+    cmdline::KernelCmdline,
+    essentials::{Bootloader, Essentials, SuperUserUtility, User},
+    filesystem_tasks::Filesystem,
+    kernel::Kernel,
+    mirrorlist::MirrorOptions,
+    offline::OfflineSource,
+    pacman::{CacheSharing, Pacman},
+    pacman_conf::PacmanOptions,
+    secure_boot::SecureBoot,
+    sync_db::PackageStatus,
+    target::InstallTarget,
+    utils::DEFAULT_TARGET_ROOT,
+};
+
+const ZONEINFO_DIR: &str = "/usr/share/zoneinfo";
+/// Every locale glibc can generate, as locale.gen lines
+const SUPPORTED_LOCALES: &str = "/usr/share/i18n/SUPPORTED";
+
+/// Everything the TUI asks for, read from a file so the same install can be repeated
+/// without anyone at the keyboard. TOML or JSON, picked by the extension, eg.
+///
+/// ```toml
+/// locale = "en_US.UTF-8"
+/// timezone = "Europe/Berlin"
+/// hostname = "workstation"
+/// root_password = "hunter2"
+/// users = [{ name = "alice", password = "hunter2" }]
+/// packages = ["git", "neovim"]
+///
+/// [disk]
+/// kind = "wipe"
+/// device = "/dev/nvme0n1"
+/// ```
+///
+/// Passwords can be left out, eg. in a config exported from the TUI, but have to be
+/// filled in before an unattended install.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InstallConfig {
+    pub disk: DiskConfig,
+    #[serde(default)]
+    pub mirrors: MirrorOptions,
+    #[serde(default)]
+    pub pacman: PacmanOptions,
+    #[serde(default)]
+    pub offline: Option<OfflineSource>,
+    #[serde(default)]
+    pub snapshot: Option<ArchiveSnapshot>,
+    #[serde(default)]
+    pub cache_sharing: CacheSharing,
+    #[serde(default)]
+    pub prefetch: bool,
+    #[serde(default = "yes")]
+    pub refresh_keyring: bool,
+    /// eg. en_US.UTF-8
+    pub locale: String,
+    #[serde(default = "default_encoding")]
+    pub encoding: String,
+    /// As in zoneinfo, eg. Asia/Kolkata
+    pub timezone: String,
+    pub hostname: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub root_password: String,
+    /// The first one is the main user, the installer is copied to their home.
+    pub users: Vec<User>,
+    #[serde(default)]
+    pub bootloader: Bootloader,
+    /// Only used with systemd-boot.
+    #[serde(default)]
+    pub unified_kernel_images: bool,
+    #[serde(default)]
+    pub super_user_utility: SuperUserUtility,
+    /// Size of the swapfile in GB
+    #[serde(default = "default_swap")]
+    pub swap: usize,
+    /// The first one is booted by default.
+    #[serde(default = "default_kernels")]
+    pub kernels: Vec<Kernel>,
+    #[serde(default)]
+    pub kernel_cmdline: KernelCmdline,
+    #[serde(default)]
+    pub secure_boot: Option<SecureBoot>,
+    /// From the repositories, installed along with the essentials
+    #[serde(default)]
+    pub packages: Vec<String>,
+    #[serde(default)]
+    pub hooks: Hooks,
+}
+
+/// Where the new system goes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DiskConfig {
+    /// Partitions made beforehand, eg. /dev/sda1
+    Partitions {
+        boot: String,
+        root: String,
+        #[serde(default)]
+        home: Option<String>,
+        /// Leave off to keep another system's boot entries
+        #[serde(default)]
+        format_boot: bool,
+        #[serde(default)]
+        format_home: bool,
+        /// Other partitions by where they are mounted, eg. "/data" = "/dev/sdb1"
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        mounts: BTreeMap<String, String>,
+        /// Where the partitions are mounted during the install, /mnt if left out
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        target_root: Option<String>,
+    },
+    /// The whole disk is erased and partitioned, eg. /dev/nvme0n1
+    Wipe {
+        device: String,
+        /// As sfdisk takes them, eg. 1G or 512M
+        #[serde(default = "default_boot_size")]
+        boot_size: String,
+        /// The rest of the disk if not given. Needed for a home partition.
+        #[serde(default)]
+        root_size: Option<String>,
+        #[serde(default)]
+        home: bool,
+    },
+    /// A directory for containers, packed into a tarball if one is given.
+    Directory {
+        path: String,
+        #[serde(default)]
+        tarball: Option<String>,
+    },
+}
+
+/// Shell commands run at points of the install, in order. The first one that fails stops it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Hooks {
+    /// In the live environment, before the disks are touched
+    pub before: Vec<String>,
+    /// Inside the new system, once everything else is set up
+    pub chroot: Vec<String>,
+    /// In the live environment, after the install has finished
+    pub after: Vec<String>,
+}
+
+fn yes() -> bool {
+    true
+}
+
+fn default_encoding() -> String {
+    "UTF-8".to_string()
+}
+
+fn default_swap() -> usize {
+    16
+}
+
+fn default_kernels() -> Vec<Kernel> {
+    vec![Kernel::Linux]
+}
+
+fn default_boot_size() -> String {
+    "1G".to_string()
+}
+
+impl InstallConfig {
+    /// Reads a .toml or .json file.
+    pub fn load(path: &str) -> Result<InstallConfig> {
+        let content =
+            fs::read_to_string(path).map_err(|e| anyhow!("Could not read {}: {}", path, e))?;
+        match Path::new(path).extension().and_then(|x| x.to_str()) {
+            Some("toml") => {
+                toml::from_str(&content).map_err(|e| anyhow!("{} is not valid: {}", path, e))
+            }
+            Some("json") => {
+                serde_json::from_str(&content).map_err(|e| anyhow!("{} is not valid: {}", path, e))
+            }
+            _ => Err(anyhow!("{} has to be a .toml or .json file", path)),
+        }
+    }
+
+    /// Checks everything that can be checked before the install starts.
+    /// Fails with every problem found, not just the first.
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+
+        match &self.disk {
+            DiskConfig::Partitions {
+                boot,
+                root,
+                home,
+                mounts,
+                target_root,
+                ..
+            } => {
+                for partition in [Some(boot), Some(root), home.as_ref()]
+                    .into_iter()
+                    .flatten()
+                    .chain(mounts.values())
+                {
+                    if !is_block_device(partition) {
+                        problems.push(format!("{} is not a partition", partition));
+                    }
+                }
+                if let Some(target_root) = target_root {
+                    let trimmed = target_root.trim().trim_end_matches('/');
+                    if !trimmed.starts_with('/') || trimmed.is_empty() {
+                        problems.push(format!(
+                            "The target root {} must be an absolute path other than /",
+                            target_root
+                        ));
+                    }
+                }
+            }
+            DiskConfig::Wipe {
+                device,
+                root_size,
+                home,
+                ..
+            } => {
+                if !is_block_device(device) {
+                    problems.push(format!("{} is not a disk", device));
+                }
+                if *home && root_size.is_none() {
+                    problems.push("A home partition needs a root_size".to_string());
+                }
+            }
+            DiskConfig::Directory { path, .. } => {
+                if !path.starts_with('/') {
+                    problems.push(format!("{} is not an absolute path", path));
+                }
+            }
+        }
+
+        if let Err(e) = self.mirrors.validate() {
+            problems.push(e.to_string());
+        }
+        for repo in &self.pacman.repos {
+            if let Err(e) = repo.validate() {
+                problems.push(e.to_string());
+            }
+        }
+        if let Some(offline) = &self.offline {
+            if let Err(e) = offline.validate() {
+                problems.push(e.to_string());
+            }
+        }
+        if let Some(snapshot) = &self.snapshot {
+            if let Err(e) = snapshot.validate() {
+                problems.push(e.to_string());
+            }
+        }
+        if self.offline.is_some() && self.snapshot.is_some() {
+            problems.push("An install is either offline or from a snapshot, not both".to_string());
+        }
+
+        if !Path::new(ZONEINFO_DIR).join(&self.timezone).is_file() {
+            problems.push(format!("{} is not a timezone", self.timezone));
+        }
+        // the ISO has the list, a container might not
+        if let Ok(supported) = fs::read_to_string(SUPPORTED_LOCALES) {
+            let line = format!("{} {}", self.locale, self.encoding);
+            if !supported.lines().any(|x| x.trim() == line) {
+                problems.push(format!("{} is not a supported locale", line));
+            }
+        }
+        if self.hostname.trim().is_empty() {
+            problems.push("The hostname is empty".to_string());
+        }
+
+        if self.root_password.is_empty() {
+            problems.push("root has no password".to_string());
+        }
+        if self.users.is_empty() {
+            problems.push("There has to be at least one user".to_string());
+        }
+        for user in &self.users {
+            if !is_valid_username(&user.name) {
+                problems.push(format!("{} is not a valid username", user.name));
+            }
+            if user.password.is_empty() {
+                problems.push(format!("{} has no password", user.name));
+            }
+        }
+        if self.kernels.is_empty() {
+            problems.push("There has to be at least one kernel".to_string());
+        }
+        if self.unified_kernel_images && self.bootloader != Bootloader::SystemDBoot {
+            problems.push("Unified kernel images need systemd-boot".to_string());
+        }
+
+        if problems.is_empty() {
+            return Ok(());
+        }
+        Err(anyhow!(
+            "The config has problems:\n{}",
+            problems
+                .iter()
+                .map(|x| format!("  - {}", x))
+                .collect::<Vec<String>>()
+                .join("\n")
+        ))
+    }
+
+    /// Checks the packages against the live environment's sync databases.
+    /// Names only an extra repository might have are let through, it is not synced yet.
+    pub fn check_packages(&self, pacman: &mut Pacman) -> Result<()> {
+        if self.packages.is_empty() || self.offline.is_some() {
+            return Ok(());
+        }
+
+        let db = pacman.sync_db()?;
+        let mut problems = Vec::new();
+        for name in &self.packages {
+            match db.check(name) {
+                PackageStatus::Unknown { .. } if !self.pacman.repos.is_empty() => {}
+                PackageStatus::Unknown { suggestions } if suggestions.is_empty() => {
+                    problems.push(format!("{} was not found", name))
+                }
+                PackageStatus::Unknown { suggestions } => problems.push(format!(
+                    "{} was not found, did you mean {}?",
+                    name,
+                    suggestions.join(", ")
+                )),
+                _ => {}
+            }
+        }
+
+        if problems.is_empty() {
+            return Ok(());
+        }
+        Err(anyhow!("Some packages are not in the repositories:\n{}", problems.join("\n")))
+    }
+
+    /// The same install as the one set up in the TUI, passwords left out.
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_installer(
+        filesystem: &Filesystem,
+        essentials: &Essentials,
+        pacman: &Pacman,
+        timezone: &str,
+        locale: &str,
+        encoding: &str,
+        swap: usize,
+        username: &str,
+        hostname: &str,
+    ) -> Result<InstallConfig> {
+        let disk = match &filesystem.target {
+            InstallTarget::Directory { root, tarball } => DiskConfig::Directory {
+                path: root.to_string(),
+                tarball: tarball.clone(),
+            },
+            InstallTarget::Partitions { root } => DiskConfig::Partitions {
+                boot: filesystem
+                    .get_boot()
+                    .ok_or(anyhow!("The boot partition is not set"))?,
+                root: filesystem
+                    .get_root()
+                    .ok_or(anyhow!("The root partition is not set"))?,
+                home: filesystem.get_home(),
+                format_boot: filesystem.format_boot,
+                format_home: filesystem.format_home,
+                mounts: filesystem
+                    .partitions
+                    .iter()
+                    .filter(|(k, _)| !["boot", "root", "home"].contains(&k.as_str()))
+                    .map(|(k, v)| (format!("/{}", k), v.clone()))
+                    .collect(),
+                target_root: Some(root.to_string()).filter(|x| x != DEFAULT_TARGET_ROOT),
+            },
+        };
+
+        let users = std::iter::once(User {
+            name: username.to_string(),
+            password: String::new(),
+        })
+        .chain(essentials.extra_users.iter().map(|x| User {
+            name: x.name.clone(),
+            password: String::new(),
+        }))
+        .collect();
+
+        Ok(InstallConfig {
+            disk,
+            mirrors: pacman.mirrors.clone(),
+            pacman: pacman.options.clone(),
+            offline: pacman.offline.clone(),
+            snapshot: pacman.snapshot.clone(),
+            cache_sharing: pacman.cache_sharing,
+            prefetch: pacman.prefetch,
+            refresh_keyring: pacman.refresh_keyring,
+            locale: locale.to_string(),
+            encoding: encoding.to_string(),
+            timezone: timezone.to_string(),
+            hostname: hostname.to_string(),
+            root_password: String::new(),
+            users,
+            bootloader: essentials.bootloader,
+            unified_kernel_images: essentials.unified_kernel_images,
+            super_user_utility: essentials.super_user_utility,
+            swap,
+            kernels: essentials.kernels.clone(),
+            kernel_cmdline: essentials.kernel_cmdline.clone(),
+            secure_boot: essentials.secure_boot.clone(),
+            packages: essentials.extra_packages.clone(),
+            hooks: Hooks {
+                chroot: essentials.hooks.clone(),
+                ..Default::default()
+            },
+        })
+    }
+
+    /// Writes a .toml or .json file, the same formats load reads.
+    pub fn save(&self, path: &str) -> Result<()> {
+        let content = match Path::new(path).extension().and_then(|x| x.to_str()) {
+            Some("toml") => toml::to_string_pretty(self)?,
+            Some("json") => serde_json::to_string_pretty(self)?,
+            _ => return Err(anyhow!("{} has to be a .toml or .json file", path)),
+        };
+        fs::write(path, content).map_err(|e| anyhow!("Could not write {}: {}", path, e))
+    }
+
+    /// Sets the partitions or directory up.
+    /// Wiping a disk happens here, so validate first.
+    pub fn apply_disk(&self, filesystem: &mut Filesystem) -> Result<()> {
+        match &self.disk {
+            DiskConfig::Partitions {
+                boot,
+                root,
+                home,
+                format_boot,
+                format_home,
+                mounts,
+                target_root,
+            } => {
+                filesystem.set_target_root(target_root.as_deref().unwrap_or(DEFAULT_TARGET_ROOT))?;
+                filesystem.clear_mounts();
+                filesystem.set_boot(boot)?;
+                filesystem.set_root(root)?;
+                filesystem.set_home(home.as_deref())?;
+                for (mount_point, partition) in mounts {
+                    filesystem.set_mount_points(partition, mount_point.trim_start_matches('/'))?;
+                }
+                filesystem.format_boot = *format_boot;
+                filesystem.format_home = *format_home;
+            }
+            DiskConfig::Wipe {
+                device,
+                boot_size,
+                root_size,
+                home,
+            } => {
+                filesystem.wipe_and_partition(device, boot_size, root_size.as_deref(), *home)?;
+            }
+            DiskConfig::Directory { path, tarball } => {
+                filesystem.set_target_directory(path, tarball.as_deref())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets everything but the disks up, the same way the TUI would.
+    pub fn apply_settings(
+        &self,
+        base_installer: &mut BaseInstaller,
+        essentials: &mut Essentials,
+        pacman: &mut Pacman,
+    ) {
+        pacman.options = self.pacman.clone();
+        pacman.offline = self.offline.clone();
+        pacman.mirrors = self.mirrors.clone();
+        pacman.snapshot = self.snapshot.clone();
+        pacman.cache_sharing = self.cache_sharing;
+        pacman.prefetch = self.prefetch;
+        pacman.refresh_keyring = self.refresh_keyring;
+
+        essentials.bootloader = self.bootloader;
+        essentials.super_user_utility = self.super_user_utility;
+        essentials.kernels = self.kernels.clone();
+        essentials.kernel_cmdline = self.kernel_cmdline.clone();
+        essentials.secure_boot = self.secure_boot.clone();
+        essentials.unified_kernel_images = self.unified_kernel_images;
+        essentials.extra_packages = self.packages.clone();
+        essentials.extra_users = self.users.iter().skip(1).cloned().collect();
+        essentials.hooks = self.hooks.chroot.clone();
+
+        base_installer.set_kernels(&self.kernels);
+    }
+}
+
+fn is_block_device(path: &str) -> bool {
+    fs::metadata(path).is_ok_and(|x| x.file_type().is_block_device())
+}
+
+/// What useradd takes without --badname
+fn is_valid_username(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|x| x.is_ascii_lowercase() || x == '_')
+        && chars.all(|x| x.is_ascii_lowercase() || x.is_ascii_digit() || x == '_' || x == '-')
+        && name.len() <= 32
+        && name != "root"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shell_iface::logger::Logger;
+
+    const EXAMPLE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../examples/install.toml");
+
+    /// The example config, as JSON
+    const EXAMPLE_JSON: &str = r#"{
+        "locale": "en_US.UTF-8",
+        "encoding": "UTF-8",
+        "timezone": "Asia/Kolkata",
+        "hostname": "archbox",
+        "root_password": "changeme",
+        "users": [{ "name": "user", "password": "changeme" }],
+        "bootloader": "grub",
+        "super_user_utility": "sudo",
+        "swap": 8,
+        "kernels": ["linux", "linux-lts"],
+        "packages": ["git", "neovim"],
+        "disk": {
+            "kind": "wipe",
+            "device": "/dev/sda",
+            "boot_size": "1G",
+            "root_size": "64G",
+            "home": true
+        },
+        "mirrors": { "countries": ["India"], "https_only": true },
+        "pacman": { "color": true, "parallel_downloads": 5 },
+        "hooks": { "chroot": ["systemctl enable sshd"] }
+    }"#;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("2lazy4arch-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn loads_the_example_as_toml_and_json() {
+        let config = InstallConfig::load(EXAMPLE).unwrap();
+        assert_eq!(
+            config.disk,
+            DiskConfig::Wipe {
+                device: "/dev/sda".to_string(),
+                boot_size: "1G".to_string(),
+                root_size: Some("64G".to_string()),
+                home: true,
+            }
+        );
+        assert_eq!(config.users[0].name, "user");
+        assert_eq!(config.kernels, [Kernel::Linux, Kernel::Lts]);
+        assert_eq!(config.swap, 8);
+        assert_eq!(config.mirrors.countries, ["India"]);
+        assert_eq!(config.hooks.chroot, ["systemctl enable sshd"]);
+        assert!(config.refresh_keyring);
+
+        let json = temp_path("install.json");
+        fs::write(&json, EXAMPLE_JSON).unwrap();
+        let from_json = InstallConfig::load(json.to_str().unwrap());
+        let _ = fs::remove_file(&json);
+        assert_eq!(from_json.unwrap(), config);
+
+        assert!(InstallConfig::load("install.yaml").is_err());
+    }
+
+    #[test]
+    fn validate_reports_every_problem() {
+        let mut config = InstallConfig::load(EXAMPLE).unwrap();
+        config.disk = DiskConfig::Wipe {
+            device: "/dev/sda".to_string(),
+            boot_size: "1G".to_string(),
+            root_size: None,
+            home: true,
+        };
+        config.offline = Some(OfflineSource::Cache {
+            path: "/var/cache/pacman/pkg".to_string(),
+        });
+        config.snapshot = Some(ArchiveSnapshot {
+            date: "2024-01-01".to_string(),
+            keep_pinned: false,
+            url: "https://archive.archlinux.org".to_string(),
+        });
+        config.root_password.clear();
+        config.users[0].password.clear();
+
+        let e = config.validate().unwrap_err().to_string();
+        assert!(e.starts_with("The config has problems:\n"), "{}", e);
+        for problem in [
+            "  - A home partition needs a root_size",
+            "  - An install is either offline or from a snapshot, not both",
+            "  - root has no password",
+            "  - user has no password",
+        ] {
+            assert!(e.lines().any(|x| x == problem), "{} is missing from:\n{}", problem, e);
+        }
+    }
+
+    #[test]
+    fn exported_config_loads_back() {
+        let logger = Logger::new(false);
+        let directory = temp_path("target");
+        let mut filesystem = Filesystem::new(&logger);
+        filesystem
+            .set_target_directory(directory.to_str().unwrap(), Some("/tmp/dev.tar.zst"))
+            .unwrap();
+        let mut essentials =
+            Essentials::new(&logger, Bootloader::SystemDBoot, SuperUserUtility::Doas);
+        essentials.kernels = vec![Kernel::Zen];
+        essentials.extra_packages = vec!["git".to_string()];
+        essentials.hooks = vec!["systemctl enable sshd".to_string()];
+        let mut pacman = Pacman::new(&logger);
+        pacman.prefetch = true;
+
+        let config = InstallConfig::from_installer(
+            &filesystem,
+            &essentials,
+            &pacman,
+            "Europe/Berlin",
+            "de_DE.UTF-8",
+            "UTF-8",
+            4,
+            "alice",
+            "workstation",
+        )
+        .unwrap();
+        let _ = fs::remove_dir_all(&directory);
+        assert_eq!(
+            config.disk,
+            DiskConfig::Directory {
+                path: directory.to_str().unwrap().to_string(),
+                tarball: Some("/tmp/dev.tar.zst".to_string()),
+            }
+        );
+        assert!(config.root_password.is_empty() && config.users[0].password.is_empty());
+
+        for name in ["export.toml", "export.json"] {
+            let path = temp_path(name);
+            config.save(path.to_str().unwrap()).unwrap();
+            let loaded = InstallConfig::load(path.to_str().unwrap());
+            let _ = fs::remove_file(&path);
+            assert_eq!(loaded.unwrap(), config, "{}", name);
+        }
+    }
+}
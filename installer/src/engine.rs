@@ -0,0 +1,580 @@
+use std::{
+    fmt::Display,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use shell_iface::{
+    logger::{CommandFailure, Logger},
+    progress::Progress,
+    Shell,
+};
+
+use crate::utils::append_to_file;
+
+pub const LOG_FILE: &str = "log.txt";
+
+/// Named stages of the install, shown with every step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// pacman, mirrors and keyring of the live environment
+    Prepare,
+    Disks,
+    /// pacstrap and fstab
+    Base,
+    /// Everything inside the chroot
+    System,
+    Finish,
+}
+
+impl Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Phase::Prepare => "Prepare",
+            Phase::Disks => "Disks",
+            Phase::Base => "Base",
+            Phase::System => "System",
+            Phase::Finish => "Finish",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// What the engine does when a step fails.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnFailure {
+    /// Undo what was done and stop
+    #[default]
+    Stop,
+    /// The install is still usable without it, eg. copying the installer
+    Continue,
+}
+
+/// A unit of the install, run by the Engine.
+/// The functions take the context every step works on, eg. the installers.
+pub struct Step<C> {
+    pub name: &'static str,
+    pub phase: Phase,
+    /// The step is skipped when this is false, eg. swap in a container
+    pub when: fn(&C) -> bool,
+    /// Has to hold before the step runs, the error says what is missing
+    pub requires: Option<fn(&C) -> Result<()>>,
+    /// The step is skipped when this is true, eg. on a second run
+    pub done: Option<fn(&C) -> bool>,
+    pub run: fn(&mut C) -> Result<()>,
+    /// Reverts the step when a later one fails, eg. unmounting
+    pub undo: Option<fn(&mut C) -> Result<()>>,
+    pub on_failure: OnFailure,
+    /// Sets up the live session, eg. mounting. Run again on resume even if it was done before.
+    pub replay: bool,
+}
+
+impl<C> Step<C> {
+    /// Always runs, stops the install on failure, cannot be undone.
+    pub fn new(phase: Phase, name: &'static str, run: fn(&mut C) -> Result<()>) -> Step<C> {
+        Step {
+            name,
+            phase,
+            when: |_| true,
+            requires: None,
+            done: None,
+            run,
+            undo: None,
+            on_failure: OnFailure::Stop,
+            replay: false,
+        }
+    }
+
+    pub fn when(mut self, when: fn(&C) -> bool) -> Step<C> {
+        self.when = when;
+        self
+    }
+
+    pub fn requires(mut self, requires: fn(&C) -> Result<()>) -> Step<C> {
+        self.requires = Some(requires);
+        self
+    }
+
+    pub fn done(mut self, done: fn(&C) -> bool) -> Step<C> {
+        self.done = Some(done);
+        self
+    }
+
+    pub fn undo(mut self, undo: fn(&mut C) -> Result<()>) -> Step<C> {
+        self.undo = Some(undo);
+        self
+    }
+
+    pub fn on_failure(mut self, on_failure: OnFailure) -> Step<C> {
+        self.on_failure = on_failure;
+        self
+    }
+
+    pub fn replay(mut self) -> Step<C> {
+        self.replay = true;
+        self
+    }
+}
+
+/// What to do about a step that failed, asked for when an install has a way to ask.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recovery {
+    /// Run the step again, eg. after fixing things in a shell
+    Retry,
+    /// Go on without it, it is not saved as done
+    Skip,
+    /// Undo what was done and stop
+    Abort,
+}
+
+/// A step that failed, handed to whoever decides the Recovery.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StepFailure {
+    pub phase: Phase,
+    pub name: &'static str,
+    pub error: String,
+    /// The last program that failed while the step ran, if one did
+    pub command: Option<CommandFailure>,
+    /// The install still works without the step, see OnFailure::Continue
+    pub optional: bool,
+    /// Opens a shell in the new system, run with sh -c. Set by the installer.
+    pub shell: Option<String>,
+}
+
+/// Decides the Recovery for a failed step, with the context the steps work on
+pub type Recover<'r, C> = &'r mut dyn FnMut(&mut C, &StepFailure) -> Recovery;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StepStatus {
+    Succeeded,
+    /// when was false
+    Skipped,
+    /// Failed, and was skipped when asked what to do
+    SkippedAfterFailure(String),
+    /// done was true, or a resumed install did it before
+    AlreadyDone,
+    Failed(String),
+    /// Succeeded, then undone after a later step failed
+    RolledBack,
+}
+
+impl Display for StepStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StepStatus::Succeeded => write!(f, "done"),
+            StepStatus::Skipped => write!(f, "skipped"),
+            StepStatus::SkippedAfterFailure(e) => write!(f, "skipped after failing: {}", e),
+            StepStatus::AlreadyDone => write!(f, "already done"),
+            StepStatus::Failed(e) => write!(f, "failed: {}", e),
+            StepStatus::RolledBack => write!(f, "rolled back"),
+        }
+    }
+}
+
+/// What happened to a step and how long it took.
+#[derive(Debug, Clone)]
+pub struct StepRecord {
+    pub name: &'static str,
+    pub phase: Phase,
+    pub status: StepStatus,
+    pub duration: Duration,
+}
+
+/// Runs the steps in order. The one place that reports progress, logs failures
+/// and rolls back.
+pub struct Engine<'a> {
+    shell: Shell<'a>,
+    /// One per step of the last run, in the order of the steps
+    pub records: Vec<StepRecord>,
+}
+
+impl<'a> Engine<'a> {
+    pub fn new<'b>(logger: &'b Logger) -> Engine<'b> {
+        Engine {
+            shell: Shell::new("Engine", logger),
+            records: Vec::new(),
+        }
+    }
+
+    /// Stops at the first step that fails with OnFailure::Stop, after undoing the ones
+    /// that succeeded, last first. The error names the step.
+    pub fn run<C>(&mut self, steps: &[Step<C>], context: &mut C) -> Result<()> {
+        self.resume(steps, context, &[], |_, _| Ok(()), None)
+    }
+
+    /// Same as run, but the steps named in completed are not run again unless they replay.
+    /// After every step, on_done gets the names of the steps done so far, to save them.
+    /// It failing is logged and does not stop the install.
+    /// If there is a recover, it is asked what to do about every failed step instead.
+    pub fn resume<C, F>(
+        &mut self,
+        steps: &[Step<C>],
+        context: &mut C,
+        completed: &[String],
+        mut on_done: F,
+        mut recover: Option<Recover<C>>,
+    ) -> Result<()>
+    where
+        F: FnMut(&mut C, &[&'static str]) -> Result<()>,
+    {
+        self.records.clear();
+        let total = steps.len();
+        let mut done = Vec::new();
+        for (i, step) in steps.iter().enumerate() {
+            self.shell.progress(&Progress::Step {
+                phase: step.phase.to_string(),
+                name: step.name.to_string(),
+                current: i + 1,
+                total,
+            });
+            let start = Instant::now();
+
+            let resumed = !step.replay && completed.iter().any(|x| x == step.name);
+            let mut status = if !(step.when)(context) {
+                StepStatus::Skipped
+            } else if resumed || step.done.is_some_and(|done| done(context)) {
+                StepStatus::AlreadyDone
+            } else {
+                self.run_step(step, context)
+            };
+
+            let mut aborted = false;
+            while let StepStatus::Failed(e) = &status {
+                let recover = match recover.as_mut() {
+                    Some(x) => x,
+                    None => break,
+                };
+                let e = e.clone();
+                let failure = StepFailure {
+                    phase: step.phase,
+                    name: step.name,
+                    error: e.clone(),
+                    command: self.shell.logger().take_command_failure(),
+                    optional: step.on_failure == OnFailure::Continue,
+                    shell: None,
+                };
+                let recovery = recover(context, &failure);
+                self.shell
+                    .log(&format!("{}: {} failed, {:?}", step.phase, step.name, recovery));
+                match recovery {
+                    Recovery::Retry => {
+                        self.shell.progress(&Progress::Step {
+                            phase: step.phase.to_string(),
+                            name: step.name.to_string(),
+                            current: i + 1,
+                            total,
+                        });
+                        status = self.run_step(step, context);
+                    }
+                    Recovery::Skip => status = StepStatus::SkippedAfterFailure(e),
+                    Recovery::Abort => {
+                        aborted = true;
+                        break;
+                    }
+                }
+            }
+
+            self.shell.log(&format!("{}: {}: {}", step.phase, step.name, status));
+            self.shell.progress(&Progress::StepFinished {
+                current: i + 1,
+                total,
+                status: status.to_string(),
+                failed: matches!(
+                    status,
+                    StepStatus::Failed(_) | StepStatus::SkippedAfterFailure(_)
+                ),
+            });
+            self.records.push(StepRecord {
+                name: step.name,
+                phase: step.phase,
+                status: status.clone(),
+                duration: start.elapsed(),
+            });
+            if matches!(status, StepStatus::Succeeded | StepStatus::AlreadyDone) {
+                done.push(step.name);
+                if let Err(e) = on_done(context, &done) {
+                    self.shell.log(&format!("Could not save the progress: {}", e));
+                }
+            }
+
+            if let StepStatus::Failed(e) = status {
+                self.shell.logger().print(&format!("{} failed: {}", step.name, e));
+                let _ = append_to_file(LOG_FILE, &format!("{} failed: {}\n", step.name, e));
+                if aborted || step.on_failure == OnFailure::Stop {
+                    self.rollback(steps, context);
+                    let _ = append_to_file(LOG_FILE, &self.summary());
+                    return Err(anyhow!("{} failed: {}", step.name, e));
+                }
+            }
+        }
+
+        let _ = append_to_file(LOG_FILE, &self.summary());
+        Ok(())
+    }
+
+    /// Runs a step that is due, forgetting the programs that failed before it.
+    fn run_step<C>(&mut self, step: &Step<C>, context: &mut C) -> StepStatus {
+        self.shell.logger().take_command_failure();
+        let result = step
+            .requires
+            .map_or(Ok(()), |requires| requires(context))
+            .and_then(|_| (step.run)(context));
+        match result {
+            Ok(_) => StepStatus::Succeeded,
+            Err(e) => StepStatus::Failed(e.to_string()),
+        }
+    }
+
+    /// Undoes the steps that succeeded, last first. Errors are logged, the rest still run.
+    fn rollback<C>(&mut self, steps: &[Step<C>], context: &mut C) {
+        for i in (0..self.records.len()).rev() {
+            if self.records[i].status != StepStatus::Succeeded {
+                continue;
+            }
+            let undo = match steps[i].undo {
+                Some(x) => x,
+                None => continue,
+            };
+            self.shell.log(&format!("Undoing {}", steps[i].name));
+            match undo(context) {
+                Ok(_) => self.records[i].status = StepStatus::RolledBack,
+                Err(e) => self
+                    .shell
+                    .log(&format!("Undoing {} failed: {}", steps[i].name, e)),
+            }
+        }
+    }
+
+    /// A line per step with its status and time, for the log.
+    pub fn summary(&self) -> String {
+        self.records
+            .iter()
+            .map(|x| {
+                format!(
+                    "{}: {}: {} ({:.1}s)\n",
+                    x.phase,
+                    x.name,
+                    x.status,
+                    x.duration.as_secs_f64()
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What the steps did, in order
+    #[derive(Default)]
+    struct Counter {
+        ran: Vec<&'static str>,
+        undone: Vec<&'static str>,
+        /// How many more times flaky fails
+        flaky: usize,
+    }
+
+    fn fail(_: &mut Counter) -> Result<()> {
+        Err(anyhow!("broken"))
+    }
+
+    fn mount() -> Step<Counter> {
+        Step::<Counter>::new(Phase::Disks, "mount", |c| {
+            c.ran.push("mount");
+            Ok(())
+        })
+        .undo(|c| {
+            c.undone.push("mount");
+            Ok(())
+        })
+        .replay()
+    }
+
+    fn base() -> Step<Counter> {
+        Step::new(Phase::Base, "base", |c| {
+            c.ran.push("base");
+            Ok(())
+        })
+    }
+
+    fn users() -> Step<Counter> {
+        Step::<Counter>::new(Phase::System, "users", |c| {
+            c.ran.push("users");
+            Ok(())
+        })
+        .undo(|c| {
+            c.undone.push("users");
+            Ok(())
+        })
+    }
+
+    fn flaky() -> Step<Counter> {
+        Step::new(Phase::System, "flaky", |c| {
+            c.ran.push("flaky");
+            match c.flaky {
+                0 => Ok(()),
+                _ => {
+                    c.flaky -= 1;
+                    Err(anyhow!("flaked"))
+                }
+            }
+        })
+    }
+
+    fn statuses(engine: &Engine) -> Vec<StepStatus> {
+        engine.records.iter().map(|x| x.status.clone()).collect()
+    }
+
+    #[test]
+    fn failure_undoes_in_reverse() {
+        let logger = Logger::new(false);
+        let mut engine = Engine::new(&logger);
+        let mut counter = Counter::default();
+        let steps = [mount(), base(), users(), Step::new(Phase::Finish, "broken", fail)];
+
+        let e = engine.run(&steps, &mut counter).unwrap_err();
+        assert_eq!(e.to_string(), "broken failed: broken");
+        assert_eq!(counter.ran, ["mount", "base", "users"]);
+        assert_eq!(counter.undone, ["users", "mount"]);
+        assert_eq!(
+            statuses(&engine),
+            [
+                StepStatus::RolledBack,
+                StepStatus::Succeeded,
+                StepStatus::RolledBack,
+                StepStatus::Failed("broken".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn optional_failure_continues() {
+        let logger = Logger::new(false);
+        let mut engine = Engine::new(&logger);
+        let mut counter = Counter::default();
+        let steps = [
+            users(),
+            Step::new(Phase::Finish, "broken", fail).on_failure(OnFailure::Continue),
+            base(),
+        ];
+
+        engine.run(&steps, &mut counter).unwrap();
+        assert_eq!(counter.ran, ["users", "base"]);
+        assert!(counter.undone.is_empty());
+        assert_eq!(
+            statuses(&engine),
+            [
+                StepStatus::Succeeded,
+                StepStatus::Failed("broken".to_string()),
+                StepStatus::Succeeded,
+            ]
+        );
+    }
+
+    #[test]
+    fn when_and_done_skip_the_step() {
+        let logger = Logger::new(false);
+        let mut engine = Engine::new(&logger);
+        let mut counter = Counter::default();
+        let steps = [
+            mount().when(|_| false),
+            base().done(|c| c.ran.is_empty()),
+            users().done(|c| !c.ran.is_empty()),
+        ];
+
+        engine.run(&steps, &mut counter).unwrap();
+        assert_eq!(counter.ran, ["users"]);
+        assert_eq!(
+            statuses(&engine),
+            [StepStatus::Skipped, StepStatus::AlreadyDone, StepStatus::Succeeded]
+        );
+    }
+
+    #[test]
+    fn completed_steps_run_again_only_if_they_replay() {
+        let logger = Logger::new(false);
+        let mut engine = Engine::new(&logger);
+        let mut counter = Counter::default();
+        let steps = [mount(), base(), users()];
+        let completed = ["mount".to_string(), "base".to_string()];
+        let mut saved = Vec::new();
+
+        engine
+            .resume(
+                &steps,
+                &mut counter,
+                &completed,
+                |_, done| {
+                    saved.push(done.join(" "));
+                    Ok(())
+                },
+                None,
+            )
+            .unwrap();
+        assert_eq!(counter.ran, ["mount", "users"]);
+        assert_eq!(
+            statuses(&engine),
+            [StepStatus::Succeeded, StepStatus::AlreadyDone, StepStatus::Succeeded]
+        );
+        assert_eq!(saved, ["mount", "mount base", "mount base users"]);
+    }
+
+    #[test]
+    fn recovery_retries_skips_and_aborts() {
+        let logger = Logger::new(false);
+        let mut engine = Engine::new(&logger);
+        let mut counter = Counter {
+            flaky: 2,
+            ..Counter::default()
+        };
+        let steps = [
+            mount(),
+            flaky(),
+            Step::new(Phase::System, "skipped", fail),
+            users(),
+            Step::new(Phase::Finish, "aborted", fail),
+            base(),
+        ];
+        let mut asked = Vec::new();
+        let mut recover = |_: &mut Counter, failure: &StepFailure| {
+            asked.push(failure.name);
+            match failure.name {
+                "flaky" => Recovery::Retry,
+                "skipped" => Recovery::Skip,
+                _ => Recovery::Abort,
+            }
+        };
+
+        let e = engine
+            .resume(&steps, &mut counter, &[], |_, _| Ok(()), Some(&mut recover))
+            .unwrap_err();
+        assert_eq!(e.to_string(), "aborted failed: broken");
+        assert_eq!(asked, ["flaky", "flaky", "skipped", "aborted"]);
+        assert_eq!(counter.ran, ["mount", "flaky", "flaky", "flaky", "users"]);
+        assert_eq!(counter.undone, ["users", "mount"]);
+        assert_eq!(
+            statuses(&engine),
+            [
+                StepStatus::RolledBack,
+                StepStatus::Succeeded,
+                StepStatus::SkippedAfterFailure("broken".to_string()),
+                StepStatus::RolledBack,
+                StepStatus::Failed("broken".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn rollback_only_sees_the_last_run() {
+        let logger = Logger::new(false);
+        let mut engine = Engine::new(&logger);
+        let mut counter = Counter::default();
+
+        engine.run(&[base().when(|_| false)], &mut counter).unwrap();
+        let steps = [users(), Step::new(Phase::Finish, "broken", fail)];
+        assert!(engine.run(&steps, &mut counter).is_err());
+        assert_eq!(counter.undone, ["users"]);
+        assert_eq!(engine.records.len(), 2);
+    }
+}
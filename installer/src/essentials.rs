@@ -1,32 +1,89 @@
 use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
 use shell_iface::{logger::Logger, Shell};
-use std::{fs, os::unix};
+use std::{
+    fs::{self, File},
+    os::fd::AsRawFd,
+    path::PathBuf,
+};
 
 use crate::{
-    pacman::Pacman,
-    utils::{append_to_file, get_processor_make, get_uuid_root, write_to_file},
+    archive::ArchiveSnapshot,
+    cmdline::{InitramfsHooks, KernelCmdline},
+    kernel::Kernel,
+    mirrorlist::{MirrorOptions, REFLECTOR_CONF_PATH},
+    mkinitcpio::MkinitcpioConf,
+    offline::OfflineSource,
+    pacman::{CacheSharing, Pacman, PACKAGE_CACHE},
+    pacman_conf::{edit_pacman_conf, PacmanOptions},
+    secure_boot::{SbctlStatus, SecureBoot, SecureBootReport},
+    target::{InstallTarget, TargetRoot},
+    target_fs::{FileMode, RootFs, TargetFs},
+    utils::{find_uuid_root, get_processor_make},
 };
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Bootloader {
+    #[default]
+    #[serde(rename = "grub")]
     Grub,
+    #[serde(rename = "systemd-boot")]
     SystemDBoot,
 }
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum SuperUserUtility {
+    #[default]
     Sudo,
     Doas,
 }
 
+/// An account in wheel, with a home directory.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct User {
+    pub name: String,
+    /// Left out of exported configs
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub password: String,
+}
+
 /// Essentials basically installs arch to be a bootable/usable state.
 /// This is same as the install.sh
-/// Essentials must be the last to run before program exits. 
+/// Everything that runs between chroot and exit_chroot runs inside the new system.
 /// Reason in chroot function
 pub struct Essentials<'a> {
-    is_chroot: bool,
+    pub(crate) is_chroot: bool,
+    /// The live environment's / and working directory, kept open to leave the chroot.
+    outside_root: Option<(File, PathBuf)>,
+    target: InstallTarget,
+    /// Every configuration file is written through this.
+    /// Points at the target's root until chroot, then just /.
+    target_fs: Box<dyn TargetFs>,
     shell: Shell<'a>,
     pacman: Pacman<'a>,
     pub bootloader: Bootloader,
     pub super_user_utility: SuperUserUtility,
+    /// The first one is booted by default.
+    pub kernels: Vec<Kernel>,
+    pub kernel_cmdline: KernelCmdline,
+    /// Signs the boot chain with sbctl if set.
+    pub secure_boot: Option<SecureBoot>,
+    /// Boots unified kernel images instead of hand-written entries.
+    /// Only used with systemd-boot.
+    pub unified_kernel_images: bool,
+    /// Installed along with the essentials
+    pub extra_packages: Vec<String>,
+    /// Made after the main user
+    pub extra_users: Vec<User>,
+    /// Shell commands run inside the new system once everything else is set up
+    pub hooks: Vec<String>,
+    pacman_options: PacmanOptions,
+    offline: Option<OfflineSource>,
+    cache_sharing: CacheSharing,
+    /// Live environment paths chroot bind mounted at the same path in the target,
+    /// eg. file:// repositories
+    bind_mounts: Vec<String>,
 }
 
 impl<'a> Essentials<'a> {
@@ -43,41 +100,152 @@ impl<'a> Essentials<'a> {
 
         Essentials {
             is_chroot: false,
+            outside_root: None,
+            target: InstallTarget::default(),
+            target_fs: Box::new(RootFs::new(TargetRoot::default())),
             shell,
             pacman,
             bootloader,
             super_user_utility,
+            kernels: vec![Kernel::Linux],
+            kernel_cmdline: KernelCmdline::default(),
+            secure_boot: None,
+            unified_kernel_images: false,
+            extra_packages: Vec::new(),
+            extra_users: Vec::new(),
+            hooks: Vec::new(),
+            pacman_options: PacmanOptions::default(),
+            offline: None,
+            cache_sharing: CacheSharing::Separate,
+            bind_mounts: Vec::new(),
+        }
+    }
+
+    /// Sets the system to chroot into.
+    /// Containers skip the steps that only make sense on real hardware.
+    pub fn set_target(&mut self, target: &InstallTarget) {
+        self.target = target.clone();
+        if !self.is_chroot {
+            self.target_fs.set_root(target.root().clone());
+        }
+    }
+
+    /// Replaces where configuration files get written.
+    /// eg. a MemoryFs to run the file writing steps without a system to write to.
+    pub fn set_target_fs(&mut self, target_fs: Box<dyn TargetFs>) {
+        self.target_fs = target_fs;
+    }
+
+    /// Every configuration file written so far.
+    pub fn touched_files(&self) -> &[String] {
+        self.target_fs.touched()
+    }
+
+    /// Sets the pacman.conf options of the new system, extra repositories included.
+    pub fn set_pacman_options(&mut self, options: &PacmanOptions) {
+        self.pacman_options = options.clone();
+    }
+
+    /// Installs the packages from a local directory instead of the mirrors.
+    pub fn set_offline(&mut self, offline: Option<&OfflineSource>) {
+        self.offline = offline.cloned();
+        self.pacman.offline = offline.cloned();
+    }
+
+    /// Sets the mirrors used inside the chroot and by reflector.timer afterwards.
+    pub fn set_mirrors(&mut self, mirrors: &MirrorOptions) {
+        self.pacman.mirrors = mirrors.clone();
+    }
+
+    /// Installs the packages from the Arch Linux Archive snapshot.
+    pub fn set_snapshot(&mut self, snapshot: Option<&ArchiveSnapshot>) {
+        self.pacman.snapshot = snapshot.cloned();
+    }
+
+    /// Gives the new system a regular mirrorlist, unless the snapshot is to be kept.
+    /// Must be the last thing done in chroot, anything installed after this is no longer pinned.
+    pub fn unpin_mirrorlist(&mut self) -> Result<()> {
+        if !self.is_chroot {
+            self.shell
+                .log("Cannot unpin the mirrorlist. Not in chroot.");
+            return Err(anyhow!("Cannot unpin the mirrorlist. Not in chroot."));
         }
+
+        match &self.pacman.snapshot {
+            Some(snapshot) if !snapshot.keep_pinned => self.pacman.unpin(),
+            _ => Ok(()),
+        }
+    }
+
+    /// The live cache is mounted over the new system's while chrooted with CacheSharing::Bind
+    pub fn set_cache_sharing(&mut self, cache_sharing: CacheSharing) {
+        self.cache_sharing = cache_sharing;
+    }
+
+    pub fn target(&self) -> &InstallTarget {
+        &self.target
     }
 
     /// chroot into the system
     /// It is imperative that this should be called first before executing any other fns.
     /// Instead of calling arch-chroot, chroot is being called directly.
     /// Followed instructions from [here](https://wiki.archlinux.org/title/Chroot#Using_chroot)
-    /// Since I can't un-chroot once we're inside chroot,
-    /// we need to make sure this struct's functions are run at the very end.
-    /// This behavior is consistent with how chroot works in Unix-like systems: 
-    /// once a process is chrooted, it cannot simply "unchroot" itself.
-    /// So, either refactor the whole code to include forking process,
-    /// or just rely on this process to exit and 
-    /// thus send the user back to un-chrooted environment.
+    /// A process cannot simply "unchroot" itself, so a handle to the live environment's /
+    /// is kept open before entering. exit_chroot uses it to get back out.
     pub fn chroot(&mut self) -> Result<()> {
         self.shell.log("Entering chroot.");
+        let root = self.target.root().clone();
         self.shell
-            .run_with_args("mount", "-t proc /proc /mnt/proc/")?;
+            .run_with_args("mount", &format!("-t proc /proc {}/proc/", root))?;
         self.shell
-            .run_with_args("mount", "-t sysfs /sys /mnt/sys/")?;
+            .run_with_args("mount", &format!("-t sysfs /sys {}/sys/", root))?;
         self.shell
-            .run_with_args("mount", "-o bind /dev /mnt/dev/")?;
+            .run_with_args("mount", &format!("-o bind /dev {}/dev/", root))?;
         self.shell
-            .run_with_args("mount", "-o bind /run /mnt/run/")?;
-        self.shell.run_with_args(
-            "mount",
-            "-o bind /sys/firmware/efi/efivars /mnt/sys/firmware/efi/efivars/",
-        )?;
-        fs::copy("/etc/resolv.conf", "/mnt/etc/resolv.conf")?;
-        std::os::unix::fs::chroot("/mnt")?;
+            .run_with_args("mount", &format!("-o bind /run {}/run/", root))?;
+        if !self.target.is_directory() {
+            self.shell.run_with_args(
+                "mount",
+                &format!(
+                    "-o bind /sys/firmware/efi/efivars {}/sys/firmware/efi/efivars/",
+                    root
+                ),
+            )?;
+        }
+        fs::copy("/etc/resolv.conf", root.path("/etc/resolv.conf"))?;
+
+        // local repositories have to be at the same path inside the chroot
+        let mut local_paths = self
+            .pacman_options
+            .repos
+            .iter()
+            .flat_map(|x| x.local_paths())
+            .collect::<Vec<String>>();
+        if let Some(offline) = &self.offline {
+            for path in offline.mounts() {
+                if !local_paths.contains(&path) {
+                    local_paths.push(path);
+                }
+            }
+        }
+        // the live cache replaces the new system's, see CacheSharing::Bind
+        if let CacheSharing::Bind = self.cache_sharing {
+            local_paths.push(PACKAGE_CACHE.to_string());
+        }
+
+        for path in local_paths {
+            fs::create_dir_all(root.path(&path))?;
+            self.shell.run_with_args(
+                "mount",
+                &format!("-o bind \"{}\" \"{}\"", path, root.path(&path).display()),
+            )?;
+            self.bind_mounts.push(path);
+        }
+
+        self.outside_root = Some((File::open("/")?, std::env::current_dir()?));
+        std::os::unix::fs::chroot(root.as_path())?;
         std::env::set_current_dir("/")?;
+        self.target_fs.set_root(TargetRoot::host());
 
         self.shell.log("Entered chroot.");
 
@@ -90,6 +258,50 @@ impl<'a> Essentials<'a> {
         Ok(())
     }
 
+    pub fn in_chroot(&self) -> bool {
+        self.is_chroot
+    }
+
+    /// Leaves the chroot and goes back to the live environment.
+    /// Unmounts the API filesystems that were mounted by chroot.
+    /// Calling this when not in chroot does nothing.
+    pub fn exit_chroot(&mut self) -> Result<()> {
+        let (outside_root, outside_cwd) = match self.outside_root.take() {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+
+        self.shell.log("Exiting chroot.");
+        nix::unistd::fchdir(outside_root.as_raw_fd())?;
+        std::os::unix::fs::chroot(".")?;
+        std::env::set_current_dir(outside_cwd)?;
+        self.is_chroot = false;
+        let root = self.target.root().clone();
+        self.target_fs.set_root(root.clone());
+
+        let mut mounts = vec!["proc", "sys", "dev", "run"];
+        if !self.target.is_directory() {
+            mounts.push("sys/firmware/efi/efivars");
+        }
+        let bind_mounts = std::mem::take(&mut self.bind_mounts);
+        mounts.extend(bind_mounts.iter().map(|x| x.as_str()));
+        for mount in mounts.iter().rev() {
+            if let Err(e) = self
+                .shell
+                .run_with_args("umount", &root.path(mount).display().to_string())
+            {
+                self.shell.log(&format!(
+                    "Could not unmount {}: {}",
+                    root.path(mount).display(),
+                    e
+                ));
+            }
+        }
+
+        self.shell.log("Exited chroot.");
+        Ok(())
+    }
+
     /// Sets the swap size.
     /// Size is in GB
     /// Should be run in a multithreaded manner. There is no point in waiting for this to complete.
@@ -123,7 +335,11 @@ impl<'a> Essentials<'a> {
         self.shell.run_and_wait_with_args("swapon", "/swapfile")?;
 
         self.shell.log("Appending swap to fstab.");
-        append_to_file("/etc/fstab", "/swapfile none  swap defaults 0 0")
+        self.target_fs.append_if_absent(
+            "/etc/fstab",
+            "/swapfile none  swap defaults 0 0",
+            FileMode::CONFIG,
+        )
     }
 
     /// Sets the timezone.
@@ -137,11 +353,15 @@ impl<'a> Essentials<'a> {
         }
 
         self.shell.log("Synchronizing Timezones");
-        unix::fs::symlink(
-            format!("/usr/share/zoneinfo/{}", timezone),
+        self.target_fs.symlink(
+            &format!("/usr/share/zoneinfo/{}", timezone),
             "/etc/localtime",
         )?;
-        self.shell.run_and_wait_with_args("hwclock", "--systohc")?;
+
+        // containers have no hardware clock of their own.
+        if !self.target.is_directory() {
+            self.shell.run_and_wait_with_args("hwclock", "--systohc")?;
+        }
         Ok(())
     }
 
@@ -157,29 +377,36 @@ impl<'a> Essentials<'a> {
 
         self.shell.log("Appending locale to fstab.");
 
-        append_to_file("/etc/locale.gen", &format!("{} {}", locale, encoding))?;
+        self.target_fs.append_if_absent(
+            "/etc/locale.gen",
+            &format!("{} {}", locale, encoding),
+            FileMode::CONFIG,
+        )?;
         self.shell.run_and_wait("locale-gen")?;
-        append_to_file("/etc/locale.conf", &format!("LANG={}", locale))
+        self.target_fs.replace_line(
+            "/etc/locale.conf",
+            "LANG=",
+            &format!("LANG={}", locale),
+            FileMode::CONFIG,
+        )
     }
 
     /// Sets the hostname and the hosts configuration
+    /// Only writes files, so it works with or without chroot.
     pub fn set_hostname(&mut self, hostname: &str) -> Result<()> {
         self.shell.log("Setting hostname");
 
-        if !self.is_chroot {
-            self.shell.log("Setting hostname failed. Not in chroot.");
-            return Err(anyhow!("Setting hostname failed. Not in chroot."));
-        }
-
-        write_to_file("/etc/hostname", hostname)?;
+        self.target_fs
+            .write("/etc/hostname", &format!("{}\n", hostname), FileMode::CONFIG)?;
         self.shell.log("Setting hosts");
-        append_to_file(
-            "/etc/hosts",
-            &format!(
-                "127.0.0.1\tlocalhost\n::1\tlocalhost\n127.0.1.1\t{}.localdomain\t{}",
-                hostname, hostname
-            ),
-        )?;
+        for line in [
+            "127.0.0.1\tlocalhost".to_string(),
+            "::1\tlocalhost".to_string(),
+            format!("127.0.1.1\t{}.localdomain\t{}", hostname, hostname),
+        ] {
+            self.target_fs
+                .append_if_absent("/etc/hosts", &line, FileMode::CONFIG)?;
+        }
 
         Ok(())
     }
@@ -193,12 +420,47 @@ impl<'a> Essentials<'a> {
             return Err(anyhow!("Cannot run mkinitcpio. Not in chroot."));
         }
 
+        let cmdline = self.kernel_cmdline.clone();
+        self.edit_mkinitcpio_conf(|conf| add_cmdline_hooks(conf, &cmdline))?;
+
         self.shell.run_and_wait_with_args("mkinitcpio", "-P")?;
         self.shell.log("Completed mkinitcpio");
 
         Ok(())
     }
 
+    /// Changes /etc/mkinitcpio.conf, eg. to add the hooks and modules a step needs.
+    /// Takes effect on the next mkinitcpio run.
+    pub fn edit_mkinitcpio_conf<F: FnOnce(&mut MkinitcpioConf)>(&mut self, edit: F) -> Result<()> {
+        let content = match self.target_fs.read("/etc/mkinitcpio.conf")? {
+            Some(x) => x,
+            None => {
+                self.shell.log("/etc/mkinitcpio.conf does not exist.");
+                return Err(anyhow!("Could not open mkinitcpio.conf"));
+            }
+        };
+
+        let mut conf = MkinitcpioConf::parse(&content);
+        edit(&mut conf);
+        let rendered = conf.render();
+        if rendered == content {
+            return Ok(());
+        }
+
+        self.shell
+            .log(&format!("mkinitcpio HOOKS: {}", conf.hooks().join(" ")));
+        self.target_fs
+            .write("/etc/mkinitcpio.conf", &rendered, FileMode::CONFIG)
+    }
+
+    /// Which hooks mkinitcpio.conf builds the initramfs with, the cmdline is written to match.
+    fn initramfs_hooks(&self) -> Result<InitramfsHooks> {
+        Ok(match self.target_fs.read("/etc/mkinitcpio.conf")? {
+            Some(x) => MkinitcpioConf::parse(&x).initramfs_hooks(),
+            None => InitramfsHooks::Busybox,
+        })
+    }
+
     /// set up password
     pub fn set_password(&mut self, user: &str, password: &str) -> Result<()> {
         self.shell.log(&format!("Setting password for {}", user));
@@ -209,29 +471,15 @@ impl<'a> Essentials<'a> {
         }
 
         self.shell
-            .spawn_with_piped_input(&"chpasswd", &format!("{}:{}", user, password))?;
+            .spawn_with_piped_input("chpasswd", &format!("{}:{}", user, password))?;
         self.shell.log("Password set successfully.");
 
         Ok(())
     }
 
-    /// installs the required programs
-    pub fn install_essentials(
-        &mut self,
-        reflector_country: &str,
-        extra_programs: Option<Vec<&str>>,
-    ) -> Result<()> {
-        self.shell.log("Starting essentials package install");
-
-        if !self.is_chroot {
-            self.shell
-                .log("Cannot install essential packages. Not in chroot.");
-            return Err(anyhow!("Cannot install essential packages. Not in chroot."));
-        }
-
+    /// Packages install_essentials installs, extras left out.
+    pub fn packages(&self) -> Vec<String> {
         let mut essential_packages = vec![
-            "efibootmgr",
-            "os-prober",
             "ntfs-3g",
             "networkmanager",
             "network-manager-applet",
@@ -241,9 +489,6 @@ impl<'a> Essentials<'a> {
             "mtools",
             "dosfstools",
             "base-devel",
-            "linux-headers",
-            "bluez",
-            "bluez-utils",
             "pipewire",
             "pipewire-pulse",
             "pipewire-jack",
@@ -251,30 +496,65 @@ impl<'a> Essentials<'a> {
             "alsa-utils",
             "git",
             "cups",
-        ];
+        ]
+        .into_iter()
+        .map(|x| x.to_string())
+        .collect::<Vec<String>>();
 
-        if let Some(extras) = extra_programs {
-            essential_packages.extend(extras)
+        // only useful on a bootable system
+        if !self.target.is_directory() {
+            essential_packages.extend(
+                ["efibootmgr", "os-prober", "bluez", "bluez-utils"].map(|x| x.to_string()),
+            );
+            essential_packages.extend(self.kernels.iter().map(|x| x.headers()));
         }
 
-        essential_packages.push(match self.super_user_utility {
-            SuperUserUtility::Sudo => "sudo",
-            SuperUserUtility::Doas => "opendoas",
-        });
+        essential_packages.push(
+            match self.super_user_utility {
+                SuperUserUtility::Sudo => "sudo",
+                SuperUserUtility::Doas => "opendoas",
+            }
+            .to_string(),
+        );
 
-        if let Bootloader::Grub = self.bootloader {
-            essential_packages.push("grub");
+        if let (Bootloader::Grub, false) = (&self.bootloader, self.target.is_directory()) {
+            essential_packages.push("grub".to_string());
         }
 
-        self.pacman.run_reflector(reflector_country)?;
-        self.pacman.install(essential_packages)?;
+        essential_packages
+    }
+
+    /// installs the required programs
+    pub fn install_essentials(
+        &mut self,
+        extra_programs: Option<Vec<&str>>,
+    ) -> Result<()> {
+        self.shell.log("Starting essentials package install");
+
+        if !self.is_chroot {
+            self.shell
+                .log("Cannot install essential packages. Not in chroot.");
+            return Err(anyhow!("Cannot install essential packages. Not in chroot."));
+        }
+
+        let mut essential_packages = self.packages();
+        essential_packages.extend(self.extra_packages.iter().cloned());
+        if let Some(extras) = extra_programs {
+            essential_packages.extend(extras.iter().map(|x| x.to_string()))
+        }
+
+        self.pacman.run_reflector()?;
+        self.pacman
+            .install(essential_packages.iter().map(|x| x.as_str()).collect())?;
         self.shell.log("Completed essentials package install");
 
         self.shell.log("Enabling Services");
         self.shell
             .run_and_wait_with_args("systemctl", "enable NetworkManager")?;
-        self.shell
-            .run_and_wait_with_args("systemctl", "enable bluetooth")?;
+        if !self.target.is_directory() {
+            self.shell
+                .run_and_wait_with_args("systemctl", "enable bluetooth")?;
+        }
 
         self.shell.log("Completed enabling Services");
 
@@ -288,6 +568,55 @@ impl<'a> Essentials<'a> {
         }
     }
 
+    /// Applies the pacman.conf options to the new system.
+    /// pacstrap does not copy the live environment's pacman.conf.
+    pub fn configure_pacman(&mut self) -> Result<()> {
+        self.shell.log("Configuring pacman.conf");
+        let options = self.pacman_options.clone();
+        if let Err(e) = edit_pacman_conf(self.target_fs.as_mut(), |conf| conf.apply(&options)) {
+            self.shell
+                .log(&format!("Could not configure pacman.conf: {}", e));
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Writes reflector.conf with the mirror options and enables reflector.timer,
+    /// which keeps the mirrorlist fresh.
+    /// The timer is left off if it would undo the mirrorlist: when it is pinned to a snapshot,
+    /// or has servers given by hand, which reflector knows nothing about.
+    pub fn configure_reflector(&mut self) -> Result<()> {
+        self.shell.log("Configuring reflector");
+
+        if !self.is_chroot {
+            self.shell
+                .log("Cannot configure reflector. Not in chroot.");
+            return Err(anyhow!("Cannot configure reflector. Not in chroot."));
+        }
+
+        let mirrors = self.pacman.mirrors.clone();
+        self.target_fs.write(
+            REFLECTOR_CONF_PATH,
+            &mirrors.reflector_conf(),
+            FileMode::CONFIG,
+        )?;
+
+        if self.pacman.snapshot.as_ref().is_some_and(|x| x.keep_pinned) {
+            self.shell
+                .log("Mirrorlist is pinned, not enabling reflector.timer");
+            return Ok(());
+        }
+        if !mirrors.servers.is_empty() {
+            self.shell
+                .log("Mirrorlist has servers given by hand, not enabling reflector.timer");
+            return Ok(());
+        }
+
+        self.shell
+            .run_and_wait_with_args("systemctl", "enable reflector.timer")?;
+        Ok(())
+    }
+
     /// Installs and configures grub
     /// Shouldn't be called from outside
     /// Only one bootloader can be installed
@@ -303,10 +632,49 @@ impl<'a> Essentials<'a> {
             .log("os-prober is disabled, windows won't be recognized");
         self.shell
             .log("run grub-mkconfig again with edited grub file");
+        // shim lock would refuse to boot the kernels signed with our own keys
+        let secure_boot_args = match self.secure_boot {
+            Some(_) => " --modules=\"tpm\" --disable-shim-lock",
+            None => "",
+        };
         self.shell.run_and_wait_with_args(
             "grub-install",
-            "--target=x86_64-efi --efi-directory=/boot --bootloader-id=GRUB",
+            &format!(
+                "--target=x86_64-efi --efi-directory=/boot --bootloader-id=GRUB{}",
+                secure_boot_args
+            ),
+        )?;
+
+        // grub-mkconfig adds a default and a fallback entry for every kernel it finds.
+        // Keep them all in the main menu and boot the first selected kernel by default.
+        self.target_fs.replace_line(
+            "/etc/default/grub",
+            "GRUB_DISABLE_SUBMENU=",
+            "GRUB_DISABLE_SUBMENU=y",
+            FileMode::CONFIG,
+        )?;
+        if let Some(kernel) = self.kernels.first() {
+            self.target_fs.replace_line(
+                "/etc/default/grub",
+                "GRUB_TOP_LEVEL=",
+                &format!("GRUB_TOP_LEVEL=\"/boot{}\"", kernel.vmlinuz()),
+                FileMode::CONFIG,
+            )?;
+        }
+        self.write_grub_cmdline()
+    }
+
+    /// Sets GRUB_CMDLINE_LINUX_DEFAULT and regenerates grub.cfg
+    fn write_grub_cmdline(&mut self) -> Result<()> {
+        let cmdline = self.kernel_cmdline.render(self.initramfs_hooks()?);
+        self.shell.log(&format!("Kernel cmdline: {}", cmdline));
+        self.target_fs.replace_line(
+            "/etc/default/grub",
+            "GRUB_CMDLINE_LINUX_DEFAULT=",
+            &format!("GRUB_CMDLINE_LINUX_DEFAULT=\"{}\"", cmdline),
+            FileMode::CONFIG,
         )?;
+
         self.shell
             .run_and_wait_with_args("grub-mkconfig", "-o /boot/grub/grub.cfg")?;
         Ok(())
@@ -315,11 +683,10 @@ impl<'a> Essentials<'a> {
     /// Installs and configures systemd-boot
     /// Shouldn't be called from outside
     /// Only one bootloader can be installed
-    /// Does not support  Secure boot. TODO
+    /// With unified_kernel_images, only the presets are written here.
+    /// The images themselves are built by mkinitcpio, which has to run after this.
     fn install_systemdboot(&mut self) -> Result<()> {
         self.shell.log("Installing SystemD Boot as the Bootloader");
-        // TODO: Show this to user instead of logging.
-        self.shell.log("This mode does not support secure boot. If you have secure boot installed, you might want to set up [signing the bootloader](https://wiki.archlinux.org/title/Systemd-boot#Signing_for_Secure_Boot).");
 
         if !self.is_chroot {
             self.shell
@@ -331,59 +698,240 @@ impl<'a> Essentials<'a> {
 
         self.shell
             .run_and_wait_with_args("systemctl", "enable systemd-boot-update.service")?;
-        write_to_file(
+        // the id of an auto-discovered image is its file name
+        let default_entry = match (self.kernels.first(), self.unified_kernel_images) {
+            (Some(x), true) => x.uki().trim_start_matches("/EFI/Linux/").to_string(),
+            (Some(x), false) => format!("{}.conf", x.entry_name()),
+            (None, _) => return Err(anyhow!("No kernel selected.")),
+        };
+        self.target_fs.write(
             "/boot/loader/loader.conf",
-            "default  arch.conf
+            &format!(
+                "default  {}
 timeout  4
 console-mode max
-editor   no",
+editor   no
+",
+                default_entry
+            ),
+            FileMode::CONFIG,
         )?;
 
-        let uuid = get_uuid_root()?;
-        let default_conf;
-        let fallback_conf;
-
-        // bad code, idc.
-        // basically decides whether to load a ucode or not.
-        if let Some(processor) = get_processor_make() {
-            default_conf = format!(
-                "title   Arch Linux 
-linux   /vmlinuz-linux
-initrd  /{}-ucode.img
-initrd  /initramfs-linux.img
-options root=UUID={} rw",
-                processor, uuid
-            );
-            fallback_conf = format!(
-                "title   Arch Linux (fallback initramfs)
-linux   /vmlinuz-linux
-initrd  /{}-ucode.img
-initrd  /initramfs-linux-fallback.img
-options root=UUID={} rw",
-                processor, uuid
-            );
+        if self.unified_kernel_images {
+            self.write_uki_presets()
         } else {
-            default_conf = format!(
-                "title   Arch Linux 
-linux   /vmlinuz-linux
-initrd  /initramfs-linux.img
-options root=UUID={} rw",
-                uuid
+            self.write_loader_entries()
+        }
+    }
+
+    /// Points every kernel's mkinitcpio preset at /boot/EFI/Linux.
+    /// The cmdline comes from /etc/kernel/cmdline,
+    /// the microcode from the microcode hook in mkinitcpio.conf.
+    fn write_uki_presets(&mut self) -> Result<()> {
+        self.shell.log("Writing presets for unified kernel images");
+        self.write_uki_cmdline()?;
+        self.shell
+            .run_and_wait_with_args("mkdir", "-p /boot/EFI/Linux")?;
+
+        for kernel in self.kernels.clone() {
+            self.target_fs.write(
+                &kernel.preset(),
+                &format!(
+                    "# mkinitcpio preset file for the '{package}' package
+# builds unified kernel images, systemd-boot finds them on its own
+
+ALL_kver=\"/boot{vmlinuz}\"
+
+PRESETS=('default' 'fallback')
+
+default_uki=\"/boot{uki}\"
+
+fallback_uki=\"/boot{fallback_uki}\"
+fallback_options=\"-S autodetect\"
+",
+                    package = kernel.package(),
+                    vmlinuz = kernel.vmlinuz(),
+                    uki = kernel.uki(),
+                    fallback_uki = kernel.fallback_uki(),
+                ),
+                FileMode::CONFIG,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// The cmdline embedded into unified kernel images.
+    /// Without it mkinitcpio would embed the live environment's cmdline.
+    fn write_uki_cmdline(&mut self) -> Result<()> {
+        let output = self.shell.run_with_args("findmnt", "-no UUID /")?;
+        let uuid = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if uuid.is_empty() {
+            self.shell.log("findmnt did not return a UUID for /");
+            return Err(anyhow!("Could not find UUID"));
+        }
+
+        let hooks = self.initramfs_hooks()?;
+        let cmdline = format!("root=UUID={} rw {}", uuid, self.kernel_cmdline.render(hooks));
+        let cmdline = cmdline.trim();
+        self.shell.log(&format!("Kernel cmdline: {}", cmdline));
+        self.target_fs.write(
+            "/etc/kernel/cmdline",
+            &format!("{}\n", cmdline),
+            FileMode::CONFIG,
+        )
+    }
+
+    /// Writes a default and a fallback systemd-boot entry for every kernel.
+    fn write_loader_entries(&mut self) -> Result<()> {
+        let fstab = match self.target_fs.read("/etc/fstab")? {
+            Some(x) => x,
+            None => return Err(anyhow!("Could not open fstab")),
+        };
+        let uuid = find_uuid_root(&fstab)?;
+        let processor = get_processor_make();
+        let hooks = self.initramfs_hooks()?;
+        let options = format!("root=UUID={} rw {}", uuid, self.kernel_cmdline.render(hooks));
+        let options = options.trim();
+        self.shell.log(&format!("Kernel cmdline: {}", options));
+
+        for kernel in self.kernels.clone() {
+            let default_conf = loader_entry(
+                &kernel.title(),
+                &kernel,
+                &kernel.initramfs(),
+                processor.as_deref(),
+                options,
             );
-            fallback_conf = format!(
-                "title   Arch Linux (fallback initramfs)
-linux   /vmlinuz-linux
-initrd  /initramfs-linux-fallback.img
-options root=UUID={} rw",
-                uuid
+            let fallback_conf = loader_entry(
+                &format!("{} (fallback initramfs)", kernel.title()),
+                &kernel,
+                &kernel.fallback_initramfs(),
+                processor.as_deref(),
+                options,
             );
+
+            self.target_fs.write(
+                &format!("/boot/loader/entries/{}.conf", kernel.entry_name()),
+                &default_conf,
+                FileMode::CONFIG,
+            )?;
+            self.target_fs.write(
+                &format!("/boot/loader/entries/{}-fallback.conf", kernel.entry_name()),
+                &fallback_conf,
+                FileMode::CONFIG,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Creates Secure Boot keys, enrolls them if the firmware allows it
+    /// and signs the bootloader and every kernel.
+    /// The sbctl package ships /usr/share/libalpm/hooks/zz-sbctl.hook, which re-signs
+    /// them after updates, so no hook of our own is written.
+    /// Does nothing if secure_boot is not set.
+    /// Must be run after the bootloader is installed and mkinitcpio has run.
+    /// Not being in setup mode is not an error, the report tells the user how to finish.
+    pub fn setup_secure_boot(&mut self) -> Result<Option<SecureBootReport>> {
+        let secure_boot = match &self.secure_boot {
+            Some(x) => x.clone(),
+            None => return Ok(None),
+        };
+
+        self.shell.log("Setting up Secure Boot");
+
+        if !self.is_chroot {
+            self.shell.log("Cannot set up Secure Boot. Not in chroot.");
+            return Err(anyhow!("Cannot set up Secure Boot. Not in chroot."));
+        }
+
+        self.pacman.install(vec!["sbctl"])?;
+
+        let output = self.shell.run_with_args("sbctl", "status --json")?;
+        let status = SbctlStatus::parse(&String::from_utf8_lossy(&output.stdout))?;
+        self.shell.log(&format!("sbctl status: {:?}", status));
+
+        if !status.installed {
+            self.shell.run_and_wait_with_args("sbctl", "create-keys")?;
+        }
+
+        let mut report = SecureBootReport {
+            setup_mode: status.setup_mode,
+            microsoft_keys: secure_boot.microsoft_keys,
+            ..Default::default()
+        };
+
+        if status.setup_mode {
+            let status = self.shell.run_and_wait_with_args(
+                "sbctl",
+                if secure_boot.microsoft_keys {
+                    "enroll-keys -m"
+                } else {
+                    "enroll-keys"
+                },
+            )?;
+            if !status.success() {
+                self.shell.log("sbctl could not enroll the keys.");
+                return Err(anyhow!("Could not enroll Secure Boot keys."));
+            }
+            report.keys_enrolled = true;
+        } else {
+            self.shell
+                .log("Firmware is not in setup mode, keys will not be enrolled.");
+        }
+
+        // bootctl and systemd-boot-update copy the .signed file to the ESP on updates
+        if let Bootloader::SystemDBoot = self.bootloader {
+            self.sbctl_sign(
+                "-s -o /usr/lib/systemd/boot/efi/systemd-bootx64.efi.signed /usr/lib/systemd/boot/efi/systemd-bootx64.efi",
+            )?;
+            report
+                .signed
+                .push("/usr/lib/systemd/boot/efi/systemd-bootx64.efi.signed".to_string());
+        }
+
+        for file in self.boot_files_to_sign() {
+            self.sbctl_sign(&format!("-s {}", file))?;
+            report.signed.push(file);
         }
 
-        // write default entry
-        write_to_file("/boot/loader/entries/arch.conf", &default_conf)?;
+        self.shell.log("Completed setting up Secure Boot");
+        Ok(Some(report))
+    }
+
+    /// Every EFI binary the firmware loads on the way to the kernel, and the kernels.
+    /// Unified kernel images are signed as a whole, fallback included.
+    /// Otherwise the default and fallback entries boot the same kernel image,
+    /// initramfs images are not EFI binaries and cannot be signed.
+    fn boot_files_to_sign(&self) -> Vec<String> {
+        let mut files = match self.bootloader {
+            Bootloader::Grub => vec!["/boot/EFI/GRUB/grubx64.efi".to_string()],
+            Bootloader::SystemDBoot => vec![
+                "/boot/EFI/systemd/systemd-bootx64.efi".to_string(),
+                "/boot/EFI/BOOT/BOOTX64.EFI".to_string(),
+            ],
+        };
+        let unified_kernel_images = matches!(self.bootloader, Bootloader::SystemDBoot)
+            && self.unified_kernel_images;
+        for kernel in &self.kernels {
+            if unified_kernel_images {
+                files.push(format!("/boot{}", kernel.uki()));
+                files.push(format!("/boot{}", kernel.fallback_uki()));
+            } else {
+                files.push(format!("/boot{}", kernel.vmlinuz()));
+            }
+        }
+        files
+    }
 
-        // write default entry
-        write_to_file("/boot/loader/entries/arch-fallback.conf", &fallback_conf)?;
+    fn sbctl_sign(&mut self, args: &str) -> Result<()> {
+        let status = self
+            .shell
+            .run_and_wait_with_args("sbctl", &format!("sign {}", args))?;
+        if !status.success() {
+            self.shell.log(&format!("sbctl sign {} failed.", args));
+            return Err(anyhow!("Could not sign boot files for Secure Boot."));
+        }
         Ok(())
     }
 
@@ -397,10 +945,7 @@ options root=UUID={} rw",
             return Err(anyhow!("Cannot install essential packages. Not in chroot."));
         }
 
-        self.shell
-            .run_and_wait_with_args("useradd", &format!("-mG wheel {}", user))?;
-        self.set_password(user, password)?;
-        self.shell.log("Password set successfully.");
+        self.add_user(user, password)?;
 
         self.shell.log("Adding wheel to sudoers");
 
@@ -415,14 +960,228 @@ options root=UUID={} rw",
             }
             SuperUserUtility::Doas => {
                 self.pacman.install(vec!["opendoas"])?;
-                write_to_file(
+                // doas refuses to run if the config is writable by anyone but root
+                // and the file must end in a newline
+                self.target_fs.write(
                     "/etc/doas.conf",
-                    "permit setenv { XAUTHORITY LANG LC_ALL } persist :wheel as root",
+                    "permit setenv { XAUTHORITY LANG LC_ALL } persist :wheel as root\n",
+                    FileMode::ROOT_ONLY,
                 )?;
-                unix::fs::symlink("/usr/bin/doas", "/usr/bin/sudo")?;
+                self.target_fs.symlink("/usr/bin/doas", "/usr/bin/sudo")?;
             }
         }
 
         Ok(())
     }
+
+    /// Makes an account in wheel with a home directory.
+    pub fn add_user(&mut self, user: &str, password: &str) -> Result<()> {
+        if !self.is_chroot {
+            self.shell.log("Cannot add a user. Not in chroot.");
+            return Err(anyhow!("Cannot add a user. Not in chroot."));
+        }
+
+        self.shell
+            .run_and_wait_with_args("useradd", &format!("-mG wheel {}", user))?;
+        self.set_password(user, password)?;
+        self.shell.log("Password set successfully.");
+        Ok(())
+    }
+
+    /// Runs the hooks inside the new system, in order. Stops at the first that fails.
+    pub fn run_hooks(&mut self) -> Result<()> {
+        if !self.is_chroot {
+            self.shell.log("Cannot run hooks. Not in chroot.");
+            return Err(anyhow!("Cannot run hooks. Not in chroot."));
+        }
+
+        for hook in self.hooks.clone() {
+            self.shell.log(&format!("Running hook: {}", hook));
+            self.shell
+                .run_and_wait_with_args("sh", &format!("-c {}", shell_words::quote(&hook)))?;
+        }
+        Ok(())
+    }
+}
+
+/// A systemd-boot entry for kernel booting initramfs
+/// Loads the ucode first if the processor make is known.
+fn loader_entry(
+    title: &str,
+    kernel: &Kernel,
+    initramfs: &str,
+    processor: Option<&str>,
+    options: &str,
+) -> String {
+    let mut entry = format!("title   {}\nlinux   {}\n", title, kernel.vmlinuz());
+    if let Some(processor) = processor {
+        entry.push_str(&format!("initrd  /{}-ucode.img\n", processor));
+    }
+    entry.push_str(&format!("initrd  {}\noptions {}\n", initramfs, options));
+    entry
+}
+
+/// Adds the hooks the kernel cmdline depends on, to whichever hook set the config uses.
+/// The systemd hook resumes on its own, only the encrypted root needs sd-encrypt.
+fn add_cmdline_hooks(conf: &mut MkinitcpioConf, cmdline: &KernelCmdline) {
+    match conf.initramfs_hooks() {
+        InitramfsHooks::Systemd => {
+            if cmdline.cryptdevice.is_some() {
+                conf.add_hook("sd-encrypt");
+            }
+        }
+        InitramfsHooks::Busybox => {
+            if cmdline.cryptdevice.is_some() {
+                conf.add_hook("encrypt");
+            }
+            if cmdline.resume.is_some() {
+                conf.add_hook("resume");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{filesystem_tasks::Filesystem, pacman_conf::PACMAN_CONF_PATH, target_fs::MemoryFs};
+
+    /// Essentials that writes into memory instead of a new system
+    fn in_memory<'a>(logger: &'a Logger, files: &[(&str, &str)]) -> Essentials<'a> {
+        let mut essentials = Essentials::new(logger, Bootloader::Grub, SuperUserUtility::Sudo);
+        let target_fs = files
+            .iter()
+            .fold(MemoryFs::new(), |x, (path, content)| x.with_file(path, content));
+        essentials.set_target_fs(Box::new(target_fs));
+        essentials
+    }
+
+    fn encrypted() -> KernelCmdline {
+        KernelCmdline {
+            cryptdevice: Some("UUID=0a1b2c3d:cryptroot".to_string()),
+            resume: Some("UUID=4e5f".to_string()),
+            ..KernelCmdline::default()
+        }
+    }
+
+    #[test]
+    fn systemd_hooks_get_sd_encrypt_and_rd_luks() {
+        let mut conf = MkinitcpioConf::parse(
+            "HOOKS=(base systemd autodetect microcode modconf kms keyboard sd-vconsole block filesystems fsck)",
+        );
+        add_cmdline_hooks(&mut conf, &encrypted());
+
+        assert_eq!(
+            conf.hooks().join(" "),
+            "base systemd autodetect microcode modconf kms keyboard sd-vconsole block sd-encrypt filesystems fsck"
+        );
+        assert_eq!(
+            encrypted().render(conf.initramfs_hooks()),
+            "rd.luks.name=0a1b2c3d=cryptroot resume=UUID=4e5f loglevel=3 quiet"
+        );
+    }
+
+    #[test]
+    fn busybox_hooks_get_encrypt_and_cryptdevice() {
+        let mut conf = MkinitcpioConf::parse(
+            "HOOKS=(base udev autodetect microcode modconf kms keyboard keymap consolefont block filesystems fsck)",
+        );
+        add_cmdline_hooks(&mut conf, &encrypted());
+
+        assert_eq!(
+            conf.hooks().join(" "),
+            "base udev autodetect microcode modconf kms keyboard keymap consolefont block encrypt resume filesystems fsck"
+        );
+        assert_eq!(
+            encrypted().render(conf.initramfs_hooks()),
+            "cryptdevice=UUID=0a1b2c3d:cryptroot resume=UUID=4e5f loglevel=3 quiet"
+        );
+    }
+
+    fn content(essentials: &Essentials, path: &str) -> String {
+        essentials.target_fs.read(path).unwrap().unwrap_or_default()
+    }
+
+    #[test]
+    fn writes_hostname_and_hosts() {
+        let logger = Logger::new(false);
+        let mut essentials = in_memory(&logger, &[("/etc/hosts", "# Static table lookup\n")]);
+        essentials.set_hostname("archbox").unwrap();
+        essentials.set_hostname("archbox").unwrap();
+
+        assert_eq!(content(&essentials, "/etc/hostname"), "archbox\n");
+        assert_eq!(
+            content(&essentials, "/etc/hosts"),
+            "# Static table lookup\n127.0.0.1\tlocalhost\n::1\tlocalhost\n\
+127.0.1.1\tarchbox.localdomain\tarchbox\n"
+        );
+        assert_eq!(essentials.touched_files(), ["/etc/hostname", "/etc/hosts"]);
+    }
+
+    #[test]
+    fn configures_pacman_in_the_new_system() {
+        let logger = Logger::new(false);
+        let stock = "[options]\n#Color\n\n[core]\nInclude = /etc/pacman.d/mirrorlist\n\n\
+#[multilib]\n#Include = /etc/pacman.d/mirrorlist\n";
+        let mut essentials = in_memory(&logger, &[(PACMAN_CONF_PATH, stock)]);
+
+        essentials.set_pacman_options(&PacmanOptions::default());
+        essentials.configure_pacman().unwrap();
+        assert!(essentials.touched_files().is_empty());
+
+        essentials.set_pacman_options(&PacmanOptions {
+            color: true,
+            multilib: true,
+            ..PacmanOptions::default()
+        });
+        essentials.configure_pacman().unwrap();
+        assert_eq!(
+            content(&essentials, PACMAN_CONF_PATH),
+            "[options]\nColor\n\n[core]\nInclude = /etc/pacman.d/mirrorlist\n\n\
+[multilib]\nInclude = /etc/pacman.d/mirrorlist\n"
+        );
+    }
+
+    #[test]
+    fn edits_mkinitcpio_conf() {
+        let logger = Logger::new(false);
+        let mut essentials = in_memory(
+            &logger,
+            &[("/etc/mkinitcpio.conf", "MODULES=()\nHOOKS=(base systemd block filesystems)\n")],
+        );
+        essentials.kernel_cmdline.cryptdevice = Some("UUID=0a1b:cryptroot".to_string());
+        let cmdline = essentials.kernel_cmdline.clone();
+        essentials
+            .edit_mkinitcpio_conf(|conf| add_cmdline_hooks(conf, &cmdline))
+            .unwrap();
+
+        assert_eq!(
+            content(&essentials, "/etc/mkinitcpio.conf"),
+            "MODULES=()\nHOOKS=(base systemd block sd-encrypt filesystems)\n"
+        );
+        assert_eq!(essentials.initramfs_hooks().unwrap(), InitramfsHooks::Systemd);
+
+        let mut missing = in_memory(&logger, &[]);
+        assert!(missing.edit_mkinitcpio_conf(|_| {}).is_err());
+    }
+
+    #[test]
+    fn writes_under_the_target_root() {
+        let logger = Logger::new(false);
+        let root = std::env::temp_dir().join(format!("2lazy4arch-root-{}", std::process::id()));
+        let mut filesystem = Filesystem::new(&logger);
+        filesystem.set_target_root(root.to_str().unwrap()).unwrap();
+
+        let mut essentials = Essentials::new(&logger, Bootloader::Grub, SuperUserUtility::Sudo);
+        essentials.set_target(&filesystem.target);
+        let written = essentials.set_hostname("archbox");
+        let hostname = fs::read_to_string(root.join("etc/hostname"));
+        let hosts = fs::read_to_string(root.join("etc/hosts"));
+        let _ = fs::remove_dir_all(&root);
+
+        written.unwrap();
+        assert_eq!(hostname.unwrap(), "archbox\n");
+        assert!(hosts.unwrap().contains("127.0.1.1\tarchbox.localdomain\tarchbox\n"));
+        assert_eq!(essentials.touched_files(), ["/etc/hostname", "/etc/hosts"]);
+    }
 }
@@ -1,10 +1,15 @@
 use std::{fs, os::unix::fs::FileTypeExt, path::Path};
 
 use anyhow::{anyhow, Result};
+use nix::sys::statvfs::statvfs;
 use serde::Deserialize;
 use shell_iface::{logger::Logger, Shell};
 
-use crate::partition_table::PartitionTable;
+use crate::{
+    checkpoint::CHECKPOINT_PATH,
+    partition_table::PartitionTable,
+    target::{InstallTarget, TargetRoot},
+};
 
 #[derive(Debug, Deserialize)]
 pub struct BlockDevicePartition {
@@ -24,6 +29,9 @@ pub struct Filesystem<'a> {
     pub partitions: PartitionTable,
     pub format_boot: bool,
     pub format_home: bool,
+    pub target: InstallTarget,
+    /// Whether mount_partitions mounted everything and nothing unmounted it since
+    pub(crate) mounted: bool,
 }
 
 impl<'a> Filesystem<'a> {
@@ -34,6 +42,8 @@ impl<'a> Filesystem<'a> {
             partitions: PartitionTable::new(),
             format_boot: false,
             format_home: false,
+            target: InstallTarget::default(),
+            mounted: false,
         }
     }
 
@@ -67,7 +77,7 @@ impl<'a> Filesystem<'a> {
 
         let _ = self.shell.run_and_wait_with_args(
             "mkfs.ext4",
-            &format!("-F {}", self.partitions.get_value("root").clone().unwrap()),
+            &format!("-F {}", self.partitions.get_value("root").unwrap()),
         )?;
 
         if self.partitions.get_value("home").is_none() {
@@ -77,7 +87,7 @@ impl<'a> Filesystem<'a> {
             if self.format_home {
                 let _ = self.shell.run_and_wait_with_args(
                     "mkfs.ext4",
-                    &format!("-F {}", self.partitions.get_value("home").clone().unwrap()),
+                    &format!("-F {}", self.partitions.get_value("home").unwrap()),
                 )?;
             } else {
                 self.shell.log("Format home is false, skipping...");
@@ -89,12 +99,12 @@ impl<'a> Filesystem<'a> {
                 "mkfs.fat",
                 &format!(
                     "-F 32 {}",
-                    self.partitions.get_value("boot").clone().unwrap()
+                    self.partitions.get_value("boot").unwrap()
                 ),
             );
         }
 
-        return Ok(());
+        Ok(())
     }
 
     /// Remove a mount point 
@@ -104,9 +114,9 @@ impl<'a> Filesystem<'a> {
 
     /// Set other partitions
     pub fn set_mount_points(&mut self, partition: &str, mount_point: &str) -> Result<()> {
-        if let Some(_) = self.partitions.get_key(mount_point) {
+        if self.partitions.get_key(mount_point).is_some() {
             // try to delete only if there is some value
-            match self.partitions.remove(Some(&mount_point), Some(&partition)) {
+            match self.partitions.remove(Some(mount_point), Some(partition)) {
                 Ok(_) => {}
                 Err(x) => {
                     self.shell.log(&x.to_string());
@@ -116,7 +126,7 @@ impl<'a> Filesystem<'a> {
         }
 
         let partition = partition.trim();
-        let metadata = fs::metadata(&partition)?;
+        let metadata = fs::metadata(partition)?;
         if !metadata.file_type().is_block_device() || !ends_with_number(partition) {
             self.shell.log(&format!(
                 "{}: NOT A BLOCK DEVICE or DOES NOT END WITH A NUMBER. Cannot mount to boot",
@@ -138,6 +148,50 @@ impl<'a> Filesystem<'a> {
         Ok(())
     }
 
+    /// Installs into a plain directory instead of partitions.
+    /// The directory is created if it does not exist.
+    pub fn set_target_directory(&mut self, path: &str, tarball: Option<&str>) -> Result<()> {
+        let path = path.trim().trim_end_matches('/');
+        if !path.starts_with('/') || path.is_empty() {
+            self.shell
+                .log(&format!("{}: target directory is not an absolute path", path));
+            return Err(anyhow!("Target directory must be an absolute path."));
+        }
+
+        if Path::new(path).exists() && !Path::new(path).is_dir() {
+            self.shell.log(&format!("{}: exists and is not a directory", path));
+            return Err(anyhow!("{} is not a directory.", path));
+        }
+        fs::create_dir_all(path)?;
+
+        let tarball = tarball
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty())
+            .map(|x| x.to_string());
+
+        self.target = InstallTarget::Directory {
+            root: TargetRoot::new(path),
+            tarball,
+        };
+        Ok(())
+    }
+
+    /// Installs onto the partitions, mounted at the given path.
+    /// The default is /mnt.
+    pub fn set_target_root(&mut self, path: &str) -> Result<()> {
+        let path = path.trim().trim_end_matches('/');
+        if !path.starts_with('/') || path.is_empty() {
+            self.shell
+                .log(&format!("{}: target root is not an absolute path", path));
+            return Err(anyhow!("Target root must be an absolute path other than /."));
+        }
+
+        self.target = InstallTarget::Partitions {
+            root: TargetRoot::new(path),
+        };
+        Ok(())
+    }
+
     /// Mounts all partitions
     pub fn mount_partitions(&mut self) -> Result<()> {
         // check if essential partitions are set.
@@ -148,39 +202,48 @@ impl<'a> Filesystem<'a> {
             return Err(anyhow!("Boot or root is not set"));
         }
 
+        let target_root = self.target.root().clone();
+
         // mount root
-        self.shell
-            .run_and_wait_with_args("mount", &format!("{} /mnt", self.get_root().unwrap()))?;
+        self.shell.run_and_wait_with_args(
+            "mount",
+            &format!("--mkdir {} {}", self.get_root().unwrap(), target_root),
+        )?;
 
         // mount other devices in any order.
         for (k, v) in self.partitions.iter() {
-            let mount_path = format!("/mnt/{}", &k);
-            match Path::new(&mount_path).try_exists() {
+            let mount_path = target_root.path(k);
+            match mount_path.try_exists() {
                 Ok(exists) => {
                     // check if a dir exists to mount to.
                     // Ideally, it shouldn't and we should be making it.
                     if exists {
                         self.shell.log(&format!(
                             "{} exists. This was not supposed to happen. Trying to continue.",
-                            &mount_path
+                            mount_path.display()
                         ));
                     } else {
                         self.shell.run_and_wait_with_args(
                             "mount",
-                            &format!("--mkdir {} {}", v, mount_path),
+                            &format!("--mkdir {} {}", v, mount_path.display()),
                         )?;
                     }
                 }
                 Err(e) => {
-                    self.shell.log(&format!("Existence of {} cannot be confirmed. This is usually a permission error. Original Error: {:#?}", &mount_path, e));
-                    return Err(anyhow!("Could not confirm the existence of {}. This could be a permission issue on /mnt ", mount_path));
+                    self.shell.log(&format!("Existence of {} cannot be confirmed. This is usually a permission error. Original Error: {:#?}", mount_path.display(), e));
+                    return Err(anyhow!("Could not confirm the existence of {}. This could be a permission issue on {} ", mount_path.display(), target_root));
                 }
             };
         }
 
+        self.mounted = true;
         Ok(())
     }
 
+    pub fn is_mounted(&self) -> bool {
+        self.mounted
+    }
+
     pub fn partition_disks(&mut self, disk: &str) -> Result<()> {
         let mut handle = self.shell.spawn_with_args("cfdisk", disk)?;
         let status = handle.wait()?;
@@ -192,31 +255,141 @@ impl<'a> Filesystem<'a> {
         Err(anyhow!("cfdisk failed. Partitioning failure."))
     }
 
+    /// Erases the disk and lays out a GPT with boot, root and, if asked for, home.
+    /// root takes the rest of the disk if no size is given or there is no home.
+    /// Sizes are as sfdisk takes them, eg. 1G or 512M.
+    /// Sets the new partitions to be formatted and mounted.
+    pub fn wipe_and_partition(
+        &mut self,
+        device: &str,
+        boot_size: &str,
+        root_size: Option<&str>,
+        home: bool,
+    ) -> Result<()> {
+        let device = device.trim();
+        let metadata = fs::metadata(device)?;
+        if !metadata.file_type().is_block_device() {
+            self.shell
+                .log(&format!("{}: NOT A BLOCK DEVICE. Cannot partition", device));
+            return Err(anyhow!("{} is not a disk.", device));
+        }
+
+        let mut script = format!("label: gpt\nsize={}, type=U\n", boot_size);
+        match (root_size, home) {
+            (Some(size), true) => script.push_str(&format!("size={}, type=L\ntype=L\n", size)),
+            (None, true) => {
+                self.shell.log("A home partition needs a root size");
+                return Err(anyhow!("Give root a size to make room for home."));
+            }
+            (_, false) => script.push_str("type=L\n"),
+        }
+
+        self.shell.log(&format!("Wiping {}", device));
+        self.shell.run_and_wait_with_args("wipefs", &format!("-a {}", device))?;
+
+        // sfdisk reads the layout from stdin
+        let script_path = "/tmp/2lazy4arch-partitions.sfdisk";
+        fs::write(script_path, script)?;
+        self.shell.run_and_wait_with_args(
+            "sh",
+            &format!(
+                "-c {}",
+                shell_words::quote(&format!("sfdisk {} < {}", device, script_path))
+            ),
+        )?;
+        // the partitions show up in /dev once udev has seen them
+        self.shell.run_and_wait_with_args("udevadm", "settle")?;
+
+        // eg. /dev/nvme0n1p1, but /dev/sda1
+        let prefix = if ends_with_number(device) {
+            format!("{}p", device)
+        } else {
+            device.to_string()
+        };
+        self.clear_mounts();
+        self.set_boot(&format!("{}1", prefix))?;
+        self.set_root(&format!("{}2", prefix))?;
+        if home {
+            self.set_home(Some(&format!("{}3", prefix)))?;
+        }
+        self.format_boot = true;
+        self.format_home = true;
+        Ok(())
+    }
+
+    /// Packs the installed directory into the tarball, if one was asked for.
+    /// Keeps numeric owners, ACLs and xattrs so the rootfs can be imported as is.
+    /// Leaves out the checkpoint, it has the passwords in it.
+    pub fn pack_tarball(&mut self) -> Result<()> {
+        let (path, tarball) = match &self.target {
+            InstallTarget::Directory {
+                root,
+                tarball: Some(tarball),
+            } => (root.to_string(), tarball.clone()),
+            _ => return Ok(()),
+        };
+
+        self.shell
+            .log(&format!("Packing {} into {}", path, tarball));
+        self.shell.run_and_wait_with_args(
+            "tar",
+            &format!(
+                "--numeric-owner --acls --xattrs --one-file-system --exclude=.{} -C {} -czf {} .",
+                CHECKPOINT_PATH, path, tarball
+            ),
+        )?;
+        Ok(())
+    }
+
+    /// What the new system's / is on and the bytes it can hold.
+    /// The root partition gets formatted, so its whole size counts. A directory keeps
+    /// what is already on its filesystem, so only the free space does.
+    /// None if the root partition is not set yet.
+    pub fn root_space(&mut self) -> Result<Option<(String, u64)>> {
+        match &self.target {
+            InstallTarget::Partitions { .. } => {
+                let root = match self.get_root() {
+                    Some(x) => x,
+                    None => return Ok(None),
+                };
+                let output = self
+                    .shell
+                    .run_with_args("lsblk", &format!("-bndo SIZE {}", root))?;
+                let size = String::from_utf8(output.stdout)?.trim().parse::<u64>()?;
+                Ok(Some((root, size)))
+            }
+            InstallTarget::Directory { root, .. } => {
+                // the directory may not be there yet, its parent's filesystem is what counts
+                let mut path = root.as_path();
+                while !path.exists() {
+                    path = match path.parent() {
+                        Some(x) => x,
+                        None => return Ok(None),
+                    };
+                }
+                let stat = statvfs(path)?;
+                let free = stat.blocks_available() as u64 * stat.fragment_size() as u64;
+                Ok(Some((format!("free space on {}", path.display()), free)))
+            }
+        }
+    }
+
     /* GETTERS */
     pub fn get_boot(&self) -> Option<String> {
-        match self.partitions.get_value("boot") {
-            Some(x) => Some(x.to_string()),
-            None => None,
-        }
+        self.partitions.get_value("boot").map(|x| x.to_string())
     }
 
     pub fn get_home(&self) -> Option<String> {
-        match self.partitions.get_value("home") {
-            Some(x) => Some(x.to_string()),
-            None => None,
-        }
+        self.partitions.get_value("home").map(|x| x.to_string())
     }
 
     pub fn get_root(&self) -> Option<String> {
-        match self.partitions.get_value("root") {
-            Some(x) => Some(x.to_string()),
-            None => None,
-        }
+        self.partitions.get_value("root").map(|x| x.to_string())
     }
 
     /* SETTERS */
     pub fn set_boot(&mut self, partition: &str) -> Result<()> {
-        if let Some(_) = self.get_boot() {
+        if self.get_boot().is_some() {
             // try to delete only if there is some value
             match self.partitions.remove_key("boot") {
                 Ok(_) => {}
@@ -228,7 +401,7 @@ impl<'a> Filesystem<'a> {
         }
 
         let partition = partition.trim();
-        let metadata = fs::metadata(&partition)?;
+        let metadata = fs::metadata(partition)?;
         if !metadata.file_type().is_block_device() || !ends_with_number(partition) {
             self.shell.log(&format!(
                 "{}: NOT A BLOCK DEVICE or DOES NOT END WITH A NUMBER. Cannot mount to boot",
@@ -252,7 +425,7 @@ impl<'a> Filesystem<'a> {
 
     pub fn set_home(&mut self, partition: Option<&str>) -> Result<()> {
         if partition.is_none() {
-            if let Some(_) = self.get_home() {
+            if self.get_home().is_some() {
                 // try to delete only if there is some value
                 match self.partitions.remove_key("home") {
                     Ok(_) => {}
@@ -266,7 +439,7 @@ impl<'a> Filesystem<'a> {
         }
 
         let partition = partition.unwrap().trim();
-        let metadata = fs::metadata(&partition)?;
+        let metadata = fs::metadata(partition)?;
         if !metadata.file_type().is_block_device() || !ends_with_number(partition) {
             self.shell.log(&format!(
                 "{}: NOT A BLOCK DEVICE or DOES NOT END WITH A NUMBER. Cannot mount to boot",
@@ -289,7 +462,7 @@ impl<'a> Filesystem<'a> {
     }
 
     pub fn set_root(&mut self, partition: &str) -> Result<()> {
-        if let Some(_) = self.get_root() {
+        if self.get_root().is_some() {
             // try to delete only if there is some value
             match self.partitions.remove_key("root") {
                 Ok(_) => {}
@@ -300,7 +473,7 @@ impl<'a> Filesystem<'a> {
             }
         }
         let partition = partition.trim();
-        let metadata = fs::metadata(&partition)?;
+        let metadata = fs::metadata(partition)?;
         if !metadata.file_type().is_block_device() || !ends_with_number(partition) {
             self.shell.log(&format!(
                 "{}: NOT A BLOCK DEVICE or DOES NOT END WITH A NUMBER. Cannot mount to root",
@@ -328,12 +501,38 @@ impl<'a> Filesystem<'a> {
     }
 
     pub fn try_unmount(&mut self) {
+        // nothing was mounted for directory installs
+        if self.target.is_directory() {
+            return;
+        }
+
         for (_, v) in self.partitions.iter() {
             let _ = self.shell.run_and_wait_with_args("umount", v);
         }
+        self.mounted = false;
     }
 }
 
 fn ends_with_number(s: &str) -> bool {
     s.chars().rev().take_while(|&c| c.is_ascii_digit()).count() > 0
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn target_root_must_be_absolute_and_not_slash() {
+        let logger = Logger::new(false);
+        let mut filesystem = Filesystem::new(&logger);
+
+        for path in ["/", " // ", "mnt", ""] {
+            assert!(filesystem.set_target_root(path).is_err(), "{:?}", path);
+        }
+        assert_eq!(filesystem.target.root().to_string(), "/mnt");
+
+        filesystem.set_target_root(" /mnt/new/ ").unwrap();
+        assert_eq!(filesystem.target.root().to_string(), "/mnt/new");
+        assert!(!filesystem.target.is_directory());
+    }
+}
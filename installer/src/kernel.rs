@@ -0,0 +1,89 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+/// Kernel flavours from the official repositories.
+/// More than one can be installed, each gets its own boot entries.
+/// Named by package in config files, eg. "linux-lts".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Kernel {
+    #[serde(rename = "linux")]
+    Linux,
+    #[serde(rename = "linux-lts")]
+    Lts,
+    #[serde(rename = "linux-zen")]
+    Zen,
+    #[serde(rename = "linux-hardened")]
+    Hardened,
+}
+
+impl Kernel {
+    pub const ALL: [Kernel; 4] = [Kernel::Linux, Kernel::Lts, Kernel::Zen, Kernel::Hardened];
+
+    /// Package name, also used in the names of the files it puts in /boot
+    pub fn package(&self) -> &'static str {
+        match self {
+            Kernel::Linux => "linux",
+            Kernel::Lts => "linux-lts",
+            Kernel::Zen => "linux-zen",
+            Kernel::Hardened => "linux-hardened",
+        }
+    }
+
+    pub fn headers(&self) -> String {
+        format!("{}-headers", self.package())
+    }
+
+    /// eg. /vmlinuz-linux-lts
+    pub fn vmlinuz(&self) -> String {
+        format!("/vmlinuz-{}", self.package())
+    }
+
+    /// eg. /initramfs-linux-lts.img
+    pub fn initramfs(&self) -> String {
+        format!("/initramfs-{}.img", self.package())
+    }
+
+    /// eg. /initramfs-linux-lts-fallback.img
+    pub fn fallback_initramfs(&self) -> String {
+        format!("/initramfs-{}-fallback.img", self.package())
+    }
+
+    /// Unified kernel image on the ESP, eg. /EFI/Linux/arch-linux-lts.efi
+    /// systemd-boot lists every image in /EFI/Linux on its own.
+    pub fn uki(&self) -> String {
+        format!("/EFI/Linux/arch-{}.efi", self.package())
+    }
+
+    /// eg. /EFI/Linux/arch-linux-lts-fallback.efi
+    pub fn fallback_uki(&self) -> String {
+        format!("/EFI/Linux/arch-{}-fallback.efi", self.package())
+    }
+
+    /// mkinitcpio preset that builds the images for this kernel
+    pub fn preset(&self) -> String {
+        format!("/etc/mkinitcpio.d/{}.preset", self.package())
+    }
+
+    /// Name of the systemd-boot entry, without .conf
+    /// The default kernel keeps the plain arch.conf that was always written.
+    pub fn entry_name(&self) -> String {
+        match self {
+            Kernel::Linux => "arch".to_string(),
+            _ => format!("arch-{}", self.package().trim_start_matches("linux-")),
+        }
+    }
+
+    pub fn title(&self) -> String {
+        match self {
+            Kernel::Linux => "Arch Linux".to_string(),
+            _ => format!("Arch Linux ({})", self.package()),
+        }
+    }
+}
+
+impl Display for Kernel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.package())
+    }
+}
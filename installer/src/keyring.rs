@@ -0,0 +1,202 @@
+use std::fmt::Display;
+
+/// Where pacman-key keeps the keyring
+pub const GNUPG_DIR: &str = "/etc/pacman.d/gnupg";
+
+/// The live environment's keyring, checked before pacstrap.
+/// Stale ISOs ship an archlinux-keyring that does not know the newer packagers' keys.
+#[derive(Debug, Default)]
+pub struct KeyringHealth {
+    /// pacman-key --init has been run
+    pub initialized: bool,
+    /// Version of archlinux-keyring installed
+    pub installed: Option<String>,
+    /// Version of archlinux-keyring in the synced repositories
+    pub available: Option<String>,
+    /// The repositories have a newer archlinux-keyring than the one installed
+    pub outdated: bool,
+}
+
+impl Display for KeyringHealth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if !self.initialized {
+            return write!(
+                f,
+                "The keyring is not initialized. Run `pacman-key --init` and `pacman-key --populate archlinux`."
+            );
+        }
+        match (&self.installed, &self.available) {
+            (Some(installed), Some(available)) if self.outdated => write!(
+                f,
+                "archlinux-keyring {} is older than {} in the repositories. \
+If pacstrap fails with signature errors, run `pacman -Sy archlinux-keyring`.",
+                installed, available
+            ),
+            (Some(installed), _) => write!(f, "archlinux-keyring {} is up to date.", installed),
+            (None, _) => write!(
+                f,
+                "archlinux-keyring is not installed. Run `pacman -Sy archlinux-keyring`."
+            ),
+        }
+    }
+}
+
+/// Keyring and signature failures pacman reports, each with its own fix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyringProblem {
+    NotInitialized,
+    /// The packager's key is not signed by the master keys the keyring has
+    UnknownTrust,
+    MarginalTrust,
+    /// The packager's key is not in the keyring at all
+    MissingKey,
+    /// The signature does not match, an outdated keyring or a broken download
+    InvalidSignature,
+    /// The checksum does not match, a broken download
+    CorruptedPackage,
+}
+
+impl KeyringProblem {
+    /// Finds the first keyring problem in what pacman wrote to stderr.
+    pub fn diagnose(stderr: &str) -> Option<KeyringProblem> {
+        let stderr = stderr.to_lowercase();
+        let patterns: &[(&[&str], KeyringProblem)] = &[
+            (&["is unknown trust"], KeyringProblem::UnknownTrust),
+            (&["is marginal trust"], KeyringProblem::MarginalTrust),
+            (
+                &[
+                    "could not be looked up remotely",
+                    "required key missing from keyring",
+                ],
+                KeyringProblem::MissingKey,
+            ),
+            (
+                &[
+                    "public keyring not found",
+                    "have you run 'pacman-key --init'",
+                    "keyring is not writable",
+                ],
+                KeyringProblem::NotInitialized,
+            ),
+            (
+                &["is invalid", "(pgp signature)"],
+                KeyringProblem::InvalidSignature,
+            ),
+            (
+                &["invalid or corrupted package (checksum)"],
+                KeyringProblem::CorruptedPackage,
+            ),
+        ];
+
+        patterns
+            .iter()
+            .find(|(needles, _)| needles.iter().any(|x| stderr.contains(x)))
+            .map(|(_, problem)| *problem)
+    }
+
+    /// What the user should do about it
+    pub fn remedy(&self) -> &'static str {
+        match self {
+            KeyringProblem::NotInitialized => {
+                "Run `pacman-key --init` and `pacman-key --populate archlinux`, then try again."
+            }
+            KeyringProblem::UnknownTrust | KeyringProblem::MarginalTrust => {
+                "The keyring is older than the packages. Run `pacman -Sy archlinux-keyring` \
+(or `pacman-key --populate archlinux` if it is already up to date), then try again."
+            }
+            KeyringProblem::MissingKey => {
+                "A packager's key is missing. Run `pacman -Sy archlinux-keyring`, then try again. \
+If the network blocks key lookups, boot a newer ISO."
+            }
+            KeyringProblem::InvalidSignature => {
+                "Either the keyring is outdated or the download is broken. Run \
+`pacman -Sy archlinux-keyring`. If it still fails, delete the package from \
+/var/cache/pacman/pkg (or run `pacman -Scc`) and try another mirror."
+            }
+            KeyringProblem::CorruptedPackage => {
+                "The download is broken. Delete the package from /var/cache/pacman/pkg \
+(or run `pacman -Scc`) and try another mirror."
+            }
+        }
+    }
+}
+
+impl Display for KeyringProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let problem = match self {
+            KeyringProblem::NotInitialized => "The pacman keyring is not initialized.",
+            KeyringProblem::UnknownTrust => "A package is signed by a key of unknown trust.",
+            KeyringProblem::MarginalTrust => "A package is signed by a key of marginal trust.",
+            KeyringProblem::MissingKey => "A package is signed by a key that is not in the keyring.",
+            KeyringProblem::InvalidSignature => "A package has an invalid signature.",
+            KeyringProblem::CorruptedPackage => "A package is corrupted.",
+        };
+        write!(f, "{} {}", problem, self.remedy())
+    }
+}
+
+/// Version from the output of `pacman -Si`, eg. "Version         : 20240313-1"
+pub fn parse_sync_version(info: &str) -> Option<String> {
+    info.lines().find_map(|x| {
+        let (key, value) = x.split_once(':')?;
+        if key.trim() == "Version" {
+            Some(value.trim().to_string())
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diagnoses_pacman_signature_errors() {
+        let cases = [
+            (
+                "error: linux: signature from \"Jan Alexander Steffens (heftig) <heftig@archlinux.org>\" is unknown trust\n\
+:: File /var/cache/pacman/pkg/linux-6.8.9.arch1-1-x86_64.pkg.tar.zst is corrupted (invalid or corrupted package (PGP signature)).",
+                KeyringProblem::UnknownTrust,
+            ),
+            (
+                "error: glibc: signature from \"Frederik Schwan <freswa@archlinux.org>\" is marginal trust",
+                KeyringProblem::MarginalTrust,
+            ),
+            (
+                "error: key \"3B94A80E50A477C7\" could not be looked up remotely\n\
+error: required key missing from keyring",
+                KeyringProblem::MissingKey,
+            ),
+            (
+                "warning: Public keyring not found; have you run 'pacman-key --init'?",
+                KeyringProblem::NotInitialized,
+            ),
+            (
+                "error: mesa: signature from \"Laurent Carlier <lordheavym@archlinux.org>\" is invalid",
+                KeyringProblem::InvalidSignature,
+            ),
+            (
+                "error: failed to commit transaction (invalid or corrupted package (checksum))",
+                KeyringProblem::CorruptedPackage,
+            ),
+        ];
+        for (stderr, problem) in cases {
+            assert_eq!(KeyringProblem::diagnose(stderr), Some(problem), "{}", stderr);
+        }
+
+        assert_eq!(KeyringProblem::diagnose("error: target not found: foo"), None);
+    }
+
+    #[test]
+    fn reads_the_version_from_pacman_si() {
+        let info = "\
+Repository      : core
+Name            : archlinux-keyring
+Version         : 20240520-1
+Description     : Arch Linux PGP keyring
+";
+        assert_eq!(parse_sync_version(info), Some("20240520-1".to_string()));
+        assert_eq!(parse_sync_version("error: package 'x' was not found"), None);
+    }
+}
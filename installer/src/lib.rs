@@ -1,219 +1,344 @@
-use std::path::PathBuf;
+use std::collections::HashSet;
 
 use anyhow::Result;
 use base_installer::BaseInstaller;
+use checkpoint::{checkpoint_path, Checkpoint, CHECKPOINT_PATH};
+use config::InstallConfig;
+use engine::{Engine, Recovery, StepFailure, LOG_FILE};
 use essentials::Essentials;
 use filesystem_tasks::Filesystem;
-use pacman::Pacman;
-use utils::{write_to_file, INSTALL_SUCCESS_FLAG};
+use pacman::{CacheSharing, Pacman};
+use plan::{install_plan, InstallContext, InstallSettings};
+use post_install::DesktopEnvironment;
+use shell_iface::{logger::Logger, Shell};
+use size_estimate::SizeEstimate;
+use target::InstallTarget;
+use utils::{append_to_file, get_processor_make};
 
+pub mod archive;
 pub mod base_installer;
+pub mod checkpoint;
+pub mod cmdline;
+pub mod config;
+pub mod engine;
 pub mod essentials;
 pub mod filesystem_tasks;
+pub mod kernel;
+pub mod keyring;
+pub mod mirrorlist;
+pub mod mkinitcpio;
+pub mod offline;
 pub mod pacman;
+pub mod pacman_conf;
+pub mod pacman_error;
 pub mod partition_table;
+pub mod plan;
 pub mod post_install;
+pub mod secure_boot;
+pub mod size_estimate;
+pub mod sync_db;
+pub mod target;
+pub mod target_fs;
+pub mod transaction;
 pub mod utils;
 
-pub fn install(
-    filesystem: &mut Filesystem,
-    base_installer: &mut BaseInstaller,
-    essentials: &mut Essentials,
-    pacman: &mut Pacman,
-    selected_reflector_country: &str,
-    selected_timezone: &str,
-    selected_locale: &str,
-    selected_encoding: &str,
-    swap_size: usize,
-    username: &str,
-    password: &str,
-    root_password: &str,
-    hostname: &str,
-) {
-    println!("Runnning reflector");
-    pacman.run_reflector(&selected_reflector_country).unwrap();
-
-    println!("Setting up filesystem");
-    match install_filesystem(filesystem) {
-        Ok(_) => {}
-        Err(e) => {
-            filesystem.try_unmount();
-            println!("Installing filesystem failed");
-            let _ = write_to_file("log.txt", &e.to_string());
-            return;
-        }
-    }
+/// Installs with what was picked in the installers, step by step, see plan::install_plan.
+/// Progress goes to the logger, failures are printed and logged.
+/// recover is asked what to do about a failed step, without it the install stops
+/// unless the step is optional.
+pub fn install<'a>(
+    filesystem: &mut Filesystem<'a>,
+    base_installer: &mut BaseInstaller<'a>,
+    essentials: &mut Essentials<'a>,
+    pacman: &mut Pacman<'a>,
+    settings: &InstallSettings,
+    logger: &'a Logger,
+    recover: Option<&mut dyn FnMut(&StepFailure) -> Recovery>,
+) -> Result<()> {
+    run_install(
+        filesystem,
+        base_installer,
+        essentials,
+        pacman,
+        settings,
+        logger,
+        &[],
+        recover,
+    )
+}
 
-    println!("Doing a base install");
-    match install_base(base_installer) {
-        Ok(_) => {}
-        Err(e) => {
-            filesystem.try_unmount();
-            println!("Installing base failed");
-            let _ = write_to_file("log.txt", &e.to_string());
-            return;
-        }
-    }
+/// Continues an install that failed or was interrupted, from the checkpoint on the new system.
+/// root is its root partition, or its directory for a directory install.
+/// Formatting and the other steps that were done are not run again.
+pub fn resume(root: &str, logger: &Logger) -> Result<()> {
+    let checkpoint = Checkpoint::find(root, logger)?;
+    let config = &checkpoint.config;
+    logger.print(&format!(
+        "Resuming the install on {}, {} steps were done",
+        root,
+        checkpoint.completed.len()
+    ));
 
-    println!("Setting up the essentials");
-    match install_essentials(
-        essentials,
-        selected_timezone,
-        selected_locale,
-        selected_encoding,
-        swap_size,
-        username,
-        password,
-        root_password,
-        hostname,
-        selected_reflector_country,
-    ) {
-        Ok(_) => {}
-        Err(e) => {
-            filesystem.try_unmount();
-            println!("Installing essentials failed");
-            let _ = write_to_file("log.txt", &e.to_string());
-            return;
-        }
-    }
+    let mut filesystem = Filesystem::new(logger);
+    let mut base_installer = BaseInstaller::new(logger);
+    let mut essentials = Essentials::new(logger, config.bootloader, config.super_user_utility);
+    let mut pacman = Pacman::new(logger);
+    config.apply_disk(&mut filesystem)?;
+    config.apply_settings(&mut base_installer, &mut essentials, &mut pacman);
 
-    match write_to_file(INSTALL_SUCCESS_FLAG, "true") {
-        Ok(_) => {}
-        Err(e) => {
-            filesystem.try_unmount();
-            eprintln!("Setting success flag failed");
-            let _ = write_to_file("log.txt", &e.to_string());
-            return;
-        }
+    let user = &config.users[0];
+    let settings = InstallSettings {
+        timezone: config.timezone.clone(),
+        locale: config.locale.clone(),
+        encoding: config.encoding.clone(),
+        swap_size: config.swap,
+        username: user.name.clone(),
+        password: user.password.clone(),
+        root_password: config.root_password.clone(),
+        hostname: config.hostname.clone(),
     };
+    run_install(
+        &mut filesystem,
+        &mut base_installer,
+        &mut essentials,
+        &mut pacman,
+        &settings,
+        logger,
+        &checkpoint.completed,
+        None,
+    )
+}
 
-    // Construct the destination path
-    let destination_path = PathBuf::from(format!("/mnt/home/{}/installer", username));
-    // Get the name of the executable from std::env::args
-    let executable_name = match std::env::args().next() {
-        Some(x) => x,
-        None => {
-            eprintln!("Failed to get the executable name from arguments.");
-            let _ = write_to_file(
-                "log.txt",
-                "Failed to get the executable name from arguments.",
-            );
-            return;
+/// Saves a checkpoint after every step, and skips the steps in completed.
+#[allow(clippy::too_many_arguments)]
+fn run_install<'a>(
+    filesystem: &mut Filesystem<'a>,
+    base_installer: &mut BaseInstaller<'a>,
+    essentials: &mut Essentials<'a>,
+    pacman: &mut Pacman<'a>,
+    settings: &InstallSettings,
+    logger: &'a Logger,
+    completed: &[String],
+    mut recover: Option<&mut dyn FnMut(&StepFailure) -> Recovery>,
+) -> Result<()> {
+    let mut context = InstallContext::new(logger, filesystem, base_installer, essentials, pacman, settings);
+    let mut saved = false;
+    let has_recover = recover.is_some();
+    let mut ask = |x: &mut InstallContext, failure: &StepFailure| {
+        let failure = StepFailure {
+            shell: Some(x.shell_command()),
+            ..failure.clone()
+        };
+        match recover.as_mut() {
+            Some(recover) => recover(&failure),
+            None => Recovery::Abort,
         }
     };
-
-    // Perform the file copy operation
-    if let Err(e) = std::fs::copy(executable_name, &destination_path) {
-        eprintln!("Failed to copy the installer: {}", e);
-        eprintln!(
-            "Please copy the file manually to {}",
-            destination_path.display()
-        );
-        let _ = write_to_file(
-            "log.txt",
-            &format!("Failed to copy the executable. {}", &e.to_string()),
-        );
-        return;
+    let result = Engine::new(logger).resume(
+        &install_plan(),
+        &mut context,
+        completed,
+        |x, done| {
+            let checkpoint = Checkpoint::new(x, done)?;
+            checkpoint.save(x)?;
+            saved |= checkpoint_path(x).is_some();
+            Ok(())
+        },
+        if has_recover { Some(&mut ask) } else { None },
+    );
+    if let Err(e) = result {
+        if saved {
+            let root = match &context.target {
+                InstallTarget::Directory { root, .. } => root.to_string(),
+                InstallTarget::Partitions { .. } => context.filesystem.get_root().unwrap_or_default(),
+            };
+            logger.print(&format!(
+                "\nThe progress is saved. Once the problem is fixed, continue with:\n    {} resume {}",
+                std::env::args().next().unwrap_or("2lazy4arch".to_string()),
+                root
+            ));
+        }
+        return Err(e);
     }
 
-    println!(
-        "Successfully copied the executable to {}. 
-        \nPlease run the installer after rebooting to the installed system.
-        \nInstaller completed successfully.",
-        destination_path.display()
-    );
-    let _ = write_to_file(
-        "log.txt",
-        "Installer completed successfully."
+    // it has the passwords in it
+    let target = context.target.clone();
+    let _ = std::fs::remove_file(target.root().path(CHECKPOINT_PATH));
+    let secure_boot_report = context.secure_boot_report.take();
+    let _ = append_to_file(
+        LOG_FILE,
+        &format!(
+            "Configuration files written:\n{}\n",
+            essentials.touched_files().join("\n")
+        ),
     );
-}
 
-fn install_filesystem(filesystem: &mut Filesystem) -> Result<()> {
-    // Format partitions
-    {
-        filesystem.format_partitions()?;
+    if let Some(snapshot) = &pacman.snapshot {
+        let report = format!(
+            "Packages installed from the Arch Linux Archive snapshot of {}.{}",
+            snapshot.date,
+            if snapshot.keep_pinned {
+                " The mirrorlist is still pinned to it, see /etc/pacman.d/mirrorlist to unpin."
+            } else {
+                ""
+            }
+        );
+        logger.print(&format!("\n{}", report));
+        let _ = append_to_file(LOG_FILE, &format!("{}\n", report));
     }
 
-    {
-        filesystem.mount_partitions()?;
+    if let Some(report) = secure_boot_report {
+        logger.print(&format!("\n{}", report));
+        let _ = append_to_file(LOG_FILE, &report.to_string());
     }
 
+    if target.is_directory() {
+        logger.print(&format!(
+            "\nThe rootfs is ready at {}.
+            \nRun the installer inside the container to set up the rest.
+            \nInstaller completed successfully.",
+            target.root()
+        ));
+    } else {
+        logger.print(
+            "\nPlease run the installer after rebooting to the installed system.
+            \nInstaller completed successfully.",
+        );
+    }
+    let _ = append_to_file(LOG_FILE, "Installer completed successfully.\n");
     Ok(())
 }
 
-fn install_base(base_installer: &mut BaseInstaller) -> Result<()> {
-    // Install base packages
-    {
-        base_installer.base_packages_install()?;
-    }
+/// Installs from a config file instead of the TUI, without asking anything.
+/// Everything in the config is checked before the disks are touched.
+pub fn install_from_config(path: &str, logger: &Logger) -> Result<()> {
+    let config = InstallConfig::load(path)?;
+    config.validate()?;
 
-    // generate fstab
-    {
-        base_installer.genfstab()?;
+    let mut filesystem = Filesystem::new(logger);
+    let mut base_installer = BaseInstaller::new(logger);
+    let mut essentials = Essentials::new(logger, config.bootloader, config.super_user_utility);
+    let mut pacman = Pacman::new(logger);
+    let mut shell = Shell::new("Hooks", logger);
+
+    logger.print("Checking the packages");
+    config.check_packages(&mut pacman)?;
+
+    for hook in &config.hooks.before {
+        logger.print(&format!("Running hook: {}", hook));
+        shell.run_and_wait_with_args("sh", &format!("-c {}", shell_words::quote(hook)))?;
     }
 
+    logger.print(&format!("Setting up the installer from {}", path));
+    config.apply_disk(&mut filesystem)?;
+    config.apply_settings(&mut base_installer, &mut essentials, &mut pacman);
+
+    // the first user is checked to be there by validate
+    let user = &config.users[0];
+    let settings = InstallSettings {
+        timezone: config.timezone.clone(),
+        locale: config.locale.clone(),
+        encoding: config.encoding.clone(),
+        swap_size: config.swap,
+        username: user.name.clone(),
+        password: user.password.clone(),
+        root_password: config.root_password.clone(),
+        hostname: config.hostname.clone(),
+    };
+    install(
+        &mut filesystem,
+        &mut base_installer,
+        &mut essentials,
+        &mut pacman,
+        &settings,
+        logger,
+        None,
+    )?;
+
+    for hook in &config.hooks.after {
+        logger.print(&format!("Running hook: {}", hook));
+        shell.run_and_wait_with_args("sh", &format!("-c {}", shell_words::quote(hook)))?;
+    }
     Ok(())
 }
 
-fn install_essentials(
+/// How much the install downloads and takes on /, read from the sync databases,
+/// and how much room / has. Shown before the install starts.
+pub fn estimate_size(
+    filesystem: &mut Filesystem,
+    base_installer: &mut BaseInstaller,
     essentials: &mut Essentials,
-    selected_timezone: &str,
-    selected_locale: &str,
-    selected_encoding: &str,
+    pacman: &mut Pacman,
     swap_size: usize,
-    username: &str,
-    password: &str,
-    root_password: &str,
-    hostname: &str,
-    selected_reflector_country: &str,
-) -> Result<()> {
-    {
-        println!("Entering chroot");
-        essentials.chroot()?;
-
-        {
-            println!("Initializing swap");
-            essentials.initialize_swap(swap_size)?;
-        }
-
-        {
-            println!("Setting timezones");
-            essentials.set_timezones(&selected_timezone)?;
-        }
+) -> Result<SizeEstimate> {
+    let target = filesystem.target.clone();
+    base_installer.set_target(&target);
+    base_installer.set_kernels(&essentials.kernels);
+    essentials.set_target(&target);
 
-        {
-            println!("Setting locale");
-            essentials.gen_locale(&selected_locale, &selected_encoding)?;
-        }
-
-        {
-            println!("Setting hostname");
-            essentials.set_hostname(&hostname)?;
-        }
-
-        {
-            println!("Setting up root");
-            essentials.set_password("root", &root_password)?;
-        }
-
-        {
-            println!("Setting up packages");
-            essentials.install_essentials(selected_reflector_country, None)?;
-        }
+    let db = pacman.sync_db()?;
+    let base = base_installer.packages();
+    let all = install_packages(base_installer, essentials);
+    let mut kernel = essentials
+        .kernels
+        .iter()
+        .flat_map(|x| [x.package().to_string(), x.headers()])
+        .collect::<Vec<String>>();
+    kernel.push("linux-firmware".to_string());
+    if let Some(p) = get_processor_make() {
+        kernel.push(format!("{}-ucode", p));
+    }
+    // containers have no kernel of their own
+    kernel.retain(|x| all.contains(x));
+    let essential = all
+        .iter()
+        .filter(|x| !base.contains(x) && !kernel.contains(x))
+        .cloned()
+        .collect::<Vec<String>>();
+    let base = base
+        .into_iter()
+        .filter(|x| !kernel.contains(x))
+        .collect::<Vec<String>>();
 
-        {
-            println!("Setting up bootloader");
-            essentials.install_bootloader()?;
-            essentials.mkinitcpio()?;
-        }
+    let mut counted = HashSet::new();
+    let sets = [("base", base), ("kernel", kernel), ("essentials", essential)]
+        .into_iter()
+        .map(|(name, packages)| (name.to_string(), db.size(&packages, &mut counted)))
+        .collect();
+    // the AUR is not in the sync databases
+    let desktops = DesktopEnvironment::all()
+        .into_iter()
+        .filter(|x| !x.is_aur())
+        .map(|x| {
+            let packages = x.packages().iter().map(|x| x.to_string()).collect::<Vec<String>>();
+            (x.to_string(), db.size(&packages, &mut counted.clone()))
+        })
+        .collect();
 
-        {
-            println!("Setting up user");
-            essentials.user_management(&username, &password)?;
-        }
+    Ok(SizeEstimate {
+        sets,
+        desktops,
+        swap: if target.is_directory() {
+            0
+        } else {
+            swap_size as u64 * 1024 * 1024 * 1024
+        },
+        cache_on_target: pacman.offline.is_none() && pacman.cache_sharing != CacheSharing::Bind,
+        root_space: filesystem.root_space()?,
+    })
+}
 
-        println!("Completed, exiting installer");
+/// Every package the install needs, pacstrap and chroot alike.
+/// No desktop is in it, post install picks one after rebooting into the new system.
+pub(crate) fn install_packages(base_installer: &BaseInstaller, essentials: &Essentials) -> Vec<String> {
+    let mut packages = base_installer.packages();
+    packages.extend(essentials.packages());
+    packages.extend(essentials.extra_packages.iter().cloned());
+    if essentials.secure_boot.is_some() && !essentials.target().is_directory() {
+        packages.push("sbctl".to_string());
     }
-
-    Ok(())
+    packages.sort();
+    packages.dedup();
+    packages
 }
+
@@ -0,0 +1,403 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+pub const MIRRORLIST_PATH: &str = "/etc/pacman.d/mirrorlist";
+/// Read by reflector.service, which reflector.timer starts every week
+pub const REFLECTOR_CONF_PATH: &str = "/etc/xdg/reflector/reflector.conf";
+
+/// Countries that have mirrors, for when reflector cannot list them.
+pub const FALLBACK_COUNTRIES: &[&str] = &[
+    "India",
+    "Australia",
+    "Austria",
+    "Azerbaijan",
+    "Bangladesh",
+    "Belarus",
+    "Belgium",
+    "Bosnia and Herzegovina",
+    "Brazil",
+    "Bulgaria",
+    "Cambodia",
+    "Canada",
+    "Chile",
+    "China",
+    "Colombia",
+    "Croatia",
+    "Czechia",
+    "Denmark",
+    "Ecuador",
+    "Estonia",
+    "Finland",
+    "France",
+    "Georgia",
+    "Germany",
+    "Greece",
+    "Hong Kong",
+    "Hungary",
+    "Iceland",
+    "Indonesia",
+    "Iran",
+    "Israel",
+    "Italy",
+    "Japan",
+    "Kazakhstan",
+    "Kenya",
+    "Latvia",
+    "Lithuania",
+    "Luxembourg",
+    "Mauritius",
+    "Mexico",
+    "Moldova",
+    "Monaco",
+    "Netherlands",
+    "New Caledonia",
+    "New Zealand",
+    "North Macedonia",
+    "Norway",
+    "Paraguay",
+    "Poland",
+    "Portugal",
+    "Romania",
+    "Russia",
+    "Réunion",
+    "Serbia",
+    "Singapore",
+    "Slovakia",
+    "Slovenia",
+    "South Africa",
+    "South Korea",
+    "Spain",
+    "Sweden",
+    "Switzerland",
+    "Taiwan",
+    "Thailand",
+    "Türkiye",
+    "Ukraine",
+    "United Kingdom",
+    "United States",
+    "Uzbekistan",
+    "Vietnam",
+];
+
+/// Which mirrors end up in the mirrorlist.
+/// reflector picks the mirrors, the servers given by hand go before them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MirrorOptions {
+    /// Country names or codes, as reflector takes them. Every country if empty.
+    pub countries: Vec<String>,
+    pub https_only: bool,
+    /// Leaves out mirrors that have not synced in this many hours
+    pub max_age: Option<u32>,
+    /// Keeps only the fastest this many mirrors
+    pub count: Option<usize>,
+    /// eg. https://mirror.example.org/archlinux/$repo/os/$arch
+    pub servers: Vec<String>,
+}
+
+impl Default for MirrorOptions {
+    fn default() -> Self {
+        MirrorOptions {
+            countries: Vec::new(),
+            https_only: true,
+            max_age: None,
+            count: None,
+            servers: Vec::new(),
+        }
+    }
+}
+
+impl MirrorOptions {
+    /// Servers given by hand with no countries are used on their own.
+    pub fn uses_reflector(&self) -> bool {
+        !self.countries.is_empty() || self.servers.is_empty()
+    }
+
+    /// reflector's arguments, one option per entry, without --save.
+    pub fn reflector_options(&self) -> Vec<String> {
+        let mut options = Vec::new();
+        if !self.countries.is_empty() {
+            options.push(format!(
+                "--country {}",
+                shell_words::quote(&self.countries.join(","))
+            ));
+        }
+        if self.https_only {
+            options.push("--protocol https".to_string());
+        }
+        if let Some(age) = self.max_age {
+            options.push(format!("--age {}", age));
+        }
+        if let Some(count) = self.count {
+            options.push(format!("--number {}", count));
+        }
+        options.push("--sort rate".to_string());
+        options
+    }
+
+    /// Arguments to run reflector with, saving to the mirrorlist.
+    pub fn reflector_args(&self) -> String {
+        format!(
+            "{} --save {}",
+            self.reflector_options().join(" "),
+            MIRRORLIST_PATH
+        )
+    }
+
+    /// reflector.conf takes one option per line, parsed like a shell would.
+    pub fn reflector_conf(&self) -> String {
+        format!(
+            "# Written by 2lazy4arch, used by reflector.service.
+# See reflector --help for the options.
+--save {}
+{}
+",
+            MIRRORLIST_PATH,
+            self.reflector_options().join("\n")
+        )
+    }
+
+    /// Checks the servers given by hand.
+    pub fn validate(&self) -> Result<()> {
+        for server in &self.servers {
+            validate_server(server)?;
+        }
+        Ok(())
+    }
+}
+
+/// A server has to be a URL pacman can fetch from, with $repo in it
+/// so that each repository gets its own path.
+pub fn validate_server(server: &str) -> Result<()> {
+    let known_scheme = ["http://", "https://", "ftp://", "file://"]
+        .iter()
+        .any(|x| server.starts_with(x));
+    if !known_scheme || server.contains(char::is_whitespace) {
+        return Err(anyhow!("{} is not a URL", server));
+    }
+    if !server.contains("$repo") {
+        return Err(anyhow!(
+            "{} has no $repo in it, eg. https://example.org/archlinux/$repo/os/$arch",
+            server
+        ));
+    }
+    Ok(())
+}
+
+/// /etc/pacman.d/mirrorlist
+/// Comments and commented out servers are kept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mirrorlist {
+    lines: Vec<String>,
+}
+
+impl Mirrorlist {
+    pub fn parse(content: &str) -> Mirrorlist {
+        Mirrorlist {
+            lines: content.lines().map(|x| x.to_string()).collect(),
+        }
+    }
+
+    pub fn render(&self) -> String {
+        format!("{}\n", self.lines.join("\n"))
+    }
+
+    /// Servers pacman uses, in the order it tries them.
+    pub fn servers(&self) -> Vec<String> {
+        self.lines
+            .iter()
+            .filter_map(|x| server_of(x))
+            .map(|x| x.to_string())
+            .collect()
+    }
+
+    /// Puts the servers before every other one, in the order given.
+    /// Servers already in use are left where they are, commented out copies are dropped.
+    pub fn add_servers(&mut self, servers: &[String]) {
+        let active = self.servers();
+        let mut new_lines = Vec::new();
+        for server in servers {
+            if active.contains(server) {
+                continue;
+            }
+
+            self.lines.retain(|x| {
+                x.trim_start()
+                    .strip_prefix('#')
+                    .and_then(server_of)
+                    .is_none_or(|x| x != server)
+            });
+            new_lines.push(format!("Server = {}", server));
+        }
+
+        let index = self
+            .lines
+            .iter()
+            .position(|x| server_of(x).is_some())
+            .unwrap_or(self.lines.len());
+        self.lines.splice(index..index, new_lines);
+    }
+
+    pub fn remove_server(&mut self, server: &str) {
+        self.lines.retain(|x| server_of(x) != Some(server));
+    }
+}
+
+/// The URL of an uncommented Server = line
+fn server_of(line: &str) -> Option<&str> {
+    let (key, value) = line.split_once('=')?;
+    if key.trim() == "Server" {
+        Some(value.trim())
+    } else {
+        None
+    }
+}
+
+/// Country names from `reflector --list-countries`.
+/// The output is a table of name, code and number of mirrors, after a line of dashes.
+pub fn parse_countries(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .skip_while(|x| !x.starts_with('-'))
+        .skip(1)
+        .filter_map(|x| {
+            let mut words = x.split_whitespace().collect::<Vec<&str>>();
+            // count and code
+            words.pop()?;
+            words.pop()?;
+            if words.is_empty() {
+                None
+            } else {
+                Some(words.join(" "))
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIRRORLIST: &str = "\
+##
+## Arch Linux repository mirrorlist
+## Generated on 2024-05-01
+##
+
+## Germany
+#Server = https://mirror.example.de/archlinux/$repo/os/$arch
+Server = https://mirror.example.fr/archlinux/$repo/os/$arch
+Server=http://mirror.example.nl/$repo/os/$arch
+";
+
+    fn server(host: &str) -> String {
+        format!("https://{}/archlinux/$repo/os/$arch", host)
+    }
+
+    #[test]
+    fn reads_the_active_servers() {
+        let mirrorlist = Mirrorlist::parse(MIRRORLIST);
+        assert_eq!(
+            mirrorlist.servers(),
+            [
+                server("mirror.example.fr"),
+                "http://mirror.example.nl/$repo/os/$arch".to_string()
+            ]
+        );
+        assert_eq!(mirrorlist.render(), MIRRORLIST);
+    }
+
+    #[test]
+    fn servers_given_by_hand_go_first() {
+        let mut mirrorlist = Mirrorlist::parse(MIRRORLIST);
+        mirrorlist.add_servers(&[
+            server("mirror.lan"),
+            server("mirror.example.de"),
+            server("mirror.example.fr"),
+        ]);
+        assert_eq!(
+            mirrorlist.servers(),
+            [
+                server("mirror.lan"),
+                server("mirror.example.de"),
+                server("mirror.example.fr"),
+                "http://mirror.example.nl/$repo/os/$arch".to_string()
+            ]
+        );
+        // the commented out copy is gone
+        assert!(!mirrorlist.render().contains("#Server"));
+
+        mirrorlist.remove_server(&server("mirror.lan"));
+        mirrorlist.remove_server("http://mirror.example.nl/$repo/os/$arch");
+        assert_eq!(
+            mirrorlist.servers(),
+            [server("mirror.example.de"), server("mirror.example.fr")]
+        );
+    }
+
+    #[test]
+    fn add_servers_to_an_empty_mirrorlist() {
+        let mut mirrorlist = Mirrorlist::parse("# empty");
+        mirrorlist.add_servers(&[server("a.example.org"), server("b.example.org")]);
+        assert_eq!(
+            mirrorlist.render(),
+            format!(
+                "# empty\nServer = {}\nServer = {}\n",
+                server("a.example.org"),
+                server("b.example.org")
+            )
+        );
+    }
+
+    #[test]
+    fn parses_reflector_countries() {
+        let output = "\
+Country                Code Count
+---------------------- ---- -----
+Australia              AU      13
+Bosnia and Herzegovina BA       3
+India                  IN      10
+Türkiye                TR       4
+";
+        assert_eq!(
+            parse_countries(output),
+            ["Australia", "Bosnia and Herzegovina", "India", "Türkiye"]
+        );
+        assert!(parse_countries("error: failed to retrieve mirrorstatus data").is_empty());
+    }
+
+    #[test]
+    fn reflector_options() {
+        let options = MirrorOptions {
+            countries: vec!["Germany".to_string(), "United States".to_string()],
+            https_only: true,
+            max_age: Some(12),
+            count: Some(20),
+            servers: Vec::new(),
+        };
+        assert_eq!(
+            options.reflector_args(),
+            "--country 'Germany,United States' --protocol https --age 12 --number 20 --sort rate \
+--save /etc/pacman.d/mirrorlist"
+        );
+        assert!(options.uses_reflector());
+        assert!(options
+            .reflector_conf()
+            .contains("\n--country 'Germany,United States'\n--protocol https\n"));
+
+        let by_hand = MirrorOptions {
+            servers: vec![server("mirror.lan")],
+            ..MirrorOptions::default()
+        };
+        assert!(!by_hand.uses_reflector());
+        assert!(by_hand.validate().is_ok());
+    }
+
+    #[test]
+    fn validates_servers() {
+        assert!(validate_server("file:///srv/repo/$repo/os/$arch").is_ok());
+        assert!(validate_server("https://mirror.example.org/archlinux").is_err());
+        assert!(validate_server("mirror.example.org/$repo/os/$arch").is_err());
+        assert!(validate_server("https://mirror example.org/$repo").is_err());
+    }
+}
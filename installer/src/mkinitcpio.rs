@@ -0,0 +1,400 @@
+use crate::cmdline::InitramfsHooks;
+
+/// Where the hooks that are known go, relative to each other.
+/// Follows the order given [here](https://wiki.archlinux.org/title/Mkinitcpio#Common_hooks)
+/// and on the wiki pages of encryption, LVM, RAID, btrfs, plymouth and hibernation.
+const HOOK_ORDER: &[&str] = &[
+    "base",
+    "systemd",
+    "udev",
+    "usr",
+    "autodetect",
+    "microcode",
+    "modconf",
+    "kms",
+    "keyboard",
+    "keymap",
+    "sd-vconsole",
+    "consolefont",
+    "plymouth",
+    "block",
+    "mdadm_udev",
+    "encrypt",
+    "sd-encrypt",
+    "lvm2",
+    "resume",
+    "btrfs",
+    "filesystems",
+    "fsck",
+];
+
+/// /etc/mkinitcpio.conf
+/// Only the arrays that are changed are rewritten, comments and everything else is kept as is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MkinitcpioConf {
+    lines: Vec<String>,
+}
+
+/// An array assignment found in the config, eg. HOOKS=(base udev)
+struct ArraySpan {
+    /// First and last line of the assignment, it can span multiple lines.
+    start: usize,
+    end: usize,
+    values: Vec<String>,
+}
+
+impl MkinitcpioConf {
+    pub fn parse(content: &str) -> MkinitcpioConf {
+        MkinitcpioConf {
+            lines: content.lines().map(|x| x.to_string()).collect(),
+        }
+    }
+
+    pub fn render(&self) -> String {
+        format!("{}\n", self.lines.join("\n"))
+    }
+
+    /// Values of a bash array, eg. array("HOOKS")
+    /// bash uses the last assignment, so does this.
+    /// Empty if it is not set.
+    pub fn array(&self, name: &str) -> Vec<String> {
+        match self.find_array(name) {
+            Some(x) => x.values,
+            None => Vec::new(),
+        }
+    }
+
+    /// Replaces the last assignment of the array, or appends one if there is none.
+    pub fn set_array(&mut self, name: &str, values: &[String]) {
+        let line = format!("{}=({})", name, values.join(" "));
+        match self.find_array(name) {
+            Some(span) => {
+                self.lines.splice(span.start..=span.end, [line]);
+            }
+            None => self.lines.push(line),
+        }
+    }
+
+    pub fn hooks(&self) -> Vec<String> {
+        self.array("HOOKS")
+    }
+
+    pub fn modules(&self) -> Vec<String> {
+        self.array("MODULES")
+    }
+
+    pub fn binaries(&self) -> Vec<String> {
+        self.array("BINARIES")
+    }
+
+    pub fn has_hook(&self, hook: &str) -> bool {
+        self.hooks().iter().any(|x| x == hook)
+    }
+
+    /// The systemd hook replaces udev and the busybox hooks, eg. encrypt becomes sd-encrypt
+    pub fn initramfs_hooks(&self) -> InitramfsHooks {
+        if self.has_hook("systemd") {
+            InitramfsHooks::Systemd
+        } else {
+            InitramfsHooks::Busybox
+        }
+    }
+
+    /// Adds a hook where it belongs, eg. encrypt goes after block and before filesystems.
+    /// Hooks that are not known go right before filesystems.
+    /// Does nothing if the hook is already there.
+    pub fn add_hook(&mut self, hook: &str) {
+        let mut hooks = self.hooks();
+        if hooks.iter().any(|x| x == hook) {
+            return;
+        }
+
+        let index = match rank(hook) {
+            // before the first hook that has to come later
+            Some(r) => hooks
+                .iter()
+                .position(|x| rank(x).is_some_and(|x| x > r))
+                .unwrap_or(hooks.len()),
+            None => hooks
+                .iter()
+                .position(|x| x == "filesystems")
+                .unwrap_or(hooks.len()),
+        };
+        hooks.insert(index, hook.to_string());
+        self.set_array("HOOKS", &hooks);
+    }
+
+    /// Adds a hook right before another one, or at the end if that one is missing.
+    /// For hooks whose place depends on the setup.
+    pub fn add_hook_before(&mut self, hook: &str, before: &str) {
+        let mut hooks = self.hooks();
+        if hooks.iter().any(|x| x == hook) {
+            return;
+        }
+
+        let index = hooks
+            .iter()
+            .position(|x| x == before)
+            .unwrap_or(hooks.len());
+        hooks.insert(index, hook.to_string());
+        self.set_array("HOOKS", &hooks);
+    }
+
+    pub fn remove_hook(&mut self, hook: &str) {
+        let hooks = self
+            .hooks()
+            .into_iter()
+            .filter(|x| x != hook)
+            .collect::<Vec<String>>();
+        self.set_array("HOOKS", &hooks);
+    }
+
+    /// Appends a module unless it is already there.
+    /// eg. amdgpu or nvidia_drm for early KMS
+    pub fn add_module(&mut self, module: &str) {
+        self.append_if_absent("MODULES", module);
+    }
+
+    /// Appends a binary unless it is already there. eg. /usr/bin/btrfs
+    pub fn add_binary(&mut self, binary: &str) {
+        self.append_if_absent("BINARIES", binary);
+    }
+
+    fn append_if_absent(&mut self, name: &str, value: &str) {
+        let mut values = self.array(name);
+        if values.iter().any(|x| x == value) {
+            return;
+        }
+        values.push(value.to_string());
+        self.set_array(name, &values);
+    }
+
+    /// The last uncommented NAME=( ... ) in the file.
+    /// Older configs have NAME="..." instead, it is read the same and rewritten as an array.
+    fn find_array(&self, name: &str) -> Option<ArraySpan> {
+        let prefix = format!("{}=", name);
+        let mut found = None;
+
+        let mut index = 0;
+        while index < self.lines.len() {
+            let line = self.lines[index].trim_start();
+            // the words start after the bracket, split_string needs to know the quote
+            let (split, skip): (SplitFn, usize) =
+                match line.strip_prefix(&prefix).and_then(|x| x.chars().next()) {
+                    Some('(') => (split_array, 1),
+                    Some('"' | '\'') => (split_string, 0),
+                    _ => {
+                        index += 1;
+                        continue;
+                    }
+                };
+
+            // collect lines until the closing bracket or quote
+            let start = index;
+            let mut body = line[prefix.len() + skip..].to_string();
+            let (mut values, mut closed) = split(&body);
+            while !closed && index + 1 < self.lines.len() {
+                index += 1;
+                body.push('\n');
+                body.push_str(&self.lines[index]);
+                (values, closed) = split(&body);
+            }
+
+            found = Some(ArraySpan {
+                start,
+                end: index,
+                values,
+            });
+            index += 1;
+        }
+
+        found
+    }
+}
+
+/// split_array or split_string
+type SplitFn = fn(&str) -> (Vec<String>, bool);
+
+fn rank(hook: &str) -> Option<usize> {
+    HOOK_ORDER.iter().position(|x| *x == hook)
+}
+
+/// Splits a quoted string into its words, eg. "base udev autodetect"
+/// Returns whether the closing quote was found.
+fn split_string(body: &str) -> (Vec<String>, bool) {
+    let quote = match body.chars().next() {
+        Some(x) => x,
+        None => return (Vec::new(), false),
+    };
+    let body = &body[quote.len_utf8()..];
+    let (words, closed) = match body.find(quote) {
+        Some(end) => (&body[..end], true),
+        None => (body, false),
+    };
+    (
+        words.split_whitespace().map(|x| x.to_string()).collect(),
+        closed,
+    )
+}
+
+/// Splits the inside of a bash array into its words.
+/// Handles quotes and comments. Returns whether the closing bracket was found.
+fn split_array(body: &str) -> (Vec<String>, bool) {
+    let mut values = Vec::new();
+    let mut word = String::new();
+    let mut quote: Option<char> = None;
+    let mut in_comment = false;
+
+    for c in body.chars() {
+        if in_comment {
+            if c == '\n' {
+                in_comment = false;
+            }
+            continue;
+        }
+
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.push(c),
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '#') if word.is_empty() => in_comment = true,
+            (None, ')') => {
+                if !word.is_empty() {
+                    values.push(word);
+                }
+                return (values, true);
+            }
+            (None, c) if c.is_whitespace() => {
+                if !word.is_empty() {
+                    values.push(std::mem::take(&mut word));
+                }
+            }
+            (None, c) => word.push(c),
+        }
+    }
+
+    if !word.is_empty() {
+        values.push(word);
+    }
+    (values, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STOCK: &str = "\
+# vim:set ft=sh
+MODULES=()
+
+BINARIES=()
+
+FILES=()
+
+# HOOKS=(base udev autodetect modconf block filesystems fsck)
+HOOKS=(base systemd autodetect microcode modconf kms keyboard sd-vconsole block filesystems fsck)
+
+#COMPRESSION=\"zstd\"
+";
+
+    fn words(x: &str) -> Vec<String> {
+        x.split_whitespace().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn reads_the_uncommented_arrays() {
+        let conf = MkinitcpioConf::parse(STOCK);
+        assert_eq!(
+            conf.hooks(),
+            words("base systemd autodetect microcode modconf kms keyboard sd-vconsole block filesystems fsck")
+        );
+        assert!(conf.modules().is_empty());
+        assert_eq!(conf.initramfs_hooks(), InitramfsHooks::Systemd);
+        assert_eq!(conf.render(), STOCK);
+    }
+
+    #[test]
+    fn adds_hooks_in_order_and_keeps_the_rest() {
+        let mut conf = MkinitcpioConf::parse(STOCK);
+        conf.add_hook("sd-encrypt");
+        conf.add_hook("lvm2");
+        conf.add_hook("plymouth");
+        conf.add_hook("lvm2");
+        conf.add_module("amdgpu");
+        conf.add_binary("/usr/bin/btrfs");
+
+        assert_eq!(
+            conf.render(),
+            STOCK
+                .replace("MODULES=()", "MODULES=(amdgpu)")
+                .replace("BINARIES=()", "BINARIES=(/usr/bin/btrfs)")
+                .replace(
+                    "\nHOOKS=(base systemd autodetect microcode modconf kms keyboard sd-vconsole block filesystems fsck)",
+                    "\nHOOKS=(base systemd autodetect microcode modconf kms keyboard sd-vconsole plymouth block sd-encrypt lvm2 filesystems fsck)"
+                )
+        );
+    }
+
+    #[test]
+    fn unknown_hooks_go_before_filesystems() {
+        let mut conf = MkinitcpioConf::parse("HOOKS=(base udev block filesystems fsck)");
+        conf.add_hook("zfs");
+        conf.add_hook_before("grub-btrfs-overlayfs", "fsck");
+        conf.remove_hook("fsck");
+        assert_eq!(
+            conf.hooks(),
+            words("base udev block zfs filesystems grub-btrfs-overlayfs")
+        );
+    }
+
+    #[test]
+    fn multi_line_arrays_with_comments() {
+        let content = "\
+HOOKS=(
+    base
+    udev # needed for block
+    'block'
+    filesystems
+)
+FILES=()";
+        let mut conf = MkinitcpioConf::parse(content);
+        assert_eq!(conf.hooks(), words("base udev block filesystems"));
+        assert_eq!(conf.initramfs_hooks(), InitramfsHooks::Busybox);
+
+        conf.add_hook("encrypt");
+        assert_eq!(
+            conf.render(),
+            "HOOKS=(base udev block encrypt filesystems)\nFILES=()\n"
+        );
+    }
+
+    #[test]
+    fn string_form() {
+        let mut conf = MkinitcpioConf::parse(
+            "MODULES=\"\"\nHOOKS=\"base udev autodetect modconf block filesystems keyboard fsck\"",
+        );
+        assert!(conf.modules().is_empty());
+        assert_eq!(
+            conf.hooks(),
+            words("base udev autodetect modconf block filesystems keyboard fsck")
+        );
+
+        conf.add_hook("encrypt");
+        conf.add_module("nvme");
+        assert_eq!(
+            conf.render(),
+            "MODULES=(nvme)\nHOOKS=(base udev autodetect modconf block encrypt filesystems keyboard fsck)\n"
+        );
+
+        let conf = MkinitcpioConf::parse("HOOKS='base\n  udev block'\nHOOKS_OLD=x");
+        assert_eq!(conf.hooks(), words("base udev block"));
+    }
+
+    #[test]
+    fn missing_array_is_appended() {
+        let mut conf = MkinitcpioConf::parse("# nothing here");
+        assert!(conf.hooks().is_empty());
+        conf.add_module("i915");
+        assert_eq!(conf.render(), "# nothing here\nMODULES=(i915)\n");
+    }
+}
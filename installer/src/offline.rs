@@ -0,0 +1,114 @@
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+/// Where the generated pacman.conf, and the database of a cache, are kept.
+/// Bind mounted into the chroot at the same path.
+pub const OFFLINE_DIR: &str = "/tmp/2lazy4arch-offline";
+/// Repository name used for a cache, which has no database of its own.
+pub const OFFLINE_REPO: &str = "offline";
+
+/// Packages are installed from a local directory instead of the mirrors.
+/// For machines without network, eg. a USB stick with the packages on it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum OfflineSource {
+    /// A directory made with repo-add, eg. /mnt/usb/repo with custom.db.tar.gz in it.
+    Repo { path: String, name: String },
+    /// A directory of packages, eg. a copy of /var/cache/pacman/pkg.
+    /// A database is generated for it with repo-add.
+    Cache { path: String },
+}
+
+impl OfflineSource {
+    pub fn path(&self) -> &str {
+        match self {
+            OfflineSource::Repo { path, .. } | OfflineSource::Cache { path } => {
+                path.trim_end_matches('/')
+            }
+        }
+    }
+
+    pub fn repo_name(&self) -> &str {
+        match self {
+            OfflineSource::Repo { name, .. } => name,
+            OfflineSource::Cache { .. } => OFFLINE_REPO,
+        }
+    }
+
+    /// Directory that has the database
+    pub fn server_dir(&self) -> &str {
+        match self {
+            OfflineSource::Repo { .. } => self.path(),
+            OfflineSource::Cache { .. } => OFFLINE_DIR,
+        }
+    }
+
+    /// pacman.conf used by pacstrap and by pacman inside the chroot
+    pub fn conf_path() -> String {
+        format!("{}/pacman.conf", OFFLINE_DIR)
+    }
+
+    /// Directories that have to be at the same path inside the chroot
+    pub fn mounts(&self) -> Vec<String> {
+        vec![self.path().to_string(), OFFLINE_DIR.to_string()]
+    }
+
+    /// Checks that the directory is there and has what pacman needs.
+    pub fn validate(&self) -> Result<()> {
+        if !Path::new(self.path()).is_dir() {
+            return Err(anyhow!("{} is not a directory", self.path()));
+        }
+
+        match self {
+            OfflineSource::Repo { name, .. } => {
+                let database = format!("{}/{}.db", self.path(), name);
+                if !Path::new(&database).exists() {
+                    return Err(anyhow!(
+                        "{} has no database, expected {} made by repo-add",
+                        self.path(),
+                        database
+                    ));
+                }
+            }
+            OfflineSource::Cache { .. } => {
+                if self.packages()?.is_empty() {
+                    return Err(anyhow!("There are no packages in {}", self.path()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Package files in the directory, signatures left out.
+    pub fn packages(&self) -> Result<Vec<String>> {
+        let mut packages = fs::read_dir(self.path())?
+            .filter_map(|x| x.ok())
+            .map(|x| x.path().display().to_string())
+            .filter(|x| x.contains(".pkg.tar") && !x.ends_with(".sig"))
+            .collect::<Vec<String>>();
+        packages.sort();
+        Ok(packages)
+    }
+
+    /// Nothing but the local repository. The packages are read straight from the
+    /// directory as it is the first CacheDir, new downloads would go to the second.
+    pub fn pacman_conf(&self) -> String {
+        format!(
+            "[options]
+Architecture = auto
+CacheDir = {}/
+CacheDir = /var/cache/pacman/pkg/
+SigLevel = Required DatabaseOptional
+LocalFileSigLevel = Optional
+
+[{}]
+Server = file://{}
+",
+            self.path(),
+            self.repo_name(),
+            self.server_dir()
+        )
+    }
+}
@@ -1,6 +1,40 @@
+use std::{collections::HashMap, process::ExitStatus};
+
 use anyhow::{anyhow, Result};
 use nix::unistd::Uid;
-use shell_iface::{logger::Logger, Shell};
+use serde::{Deserialize, Serialize};
+use shell_iface::{logger::Logger, progress::Progress, Shell};
+
+use crate::{
+    archive::ArchiveSnapshot,
+    keyring::{parse_sync_version, KeyringHealth, KeyringProblem, GNUPG_DIR},
+    mirrorlist::{parse_countries, MirrorOptions, Mirrorlist, MIRRORLIST_PATH},
+    offline::{OfflineSource, OFFLINE_DIR},
+    pacman_conf::{edit_pacman_conf, PacmanOptions},
+    pacman_error::PacmanError,
+    sync_db::{SyncDb, SYNC_DIR},
+    target::TargetRoot,
+    target_fs::{FileMode, RootFs, TargetFs},
+    transaction::{download_sizes, TransactionProgress},
+};
+
+/// pacman's default CacheDir, in the live environment and in the new system
+pub const PACKAGE_CACHE: &str = "/var/cache/pacman/pkg";
+
+/// How the live environment's package cache is shared with the new system.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheSharing {
+    /// The new system downloads into a cache of its own.
+    #[default]
+    Separate,
+    /// The live cache is used for the whole install, pacstrap and chroot alike.
+    /// Nothing is left in the new system's cache.
+    /// On the ISO the cache lives in RAM, best used with a cache on a disk or USB stick.
+    Bind,
+    /// Packages in the live cache are copied into the new system's before pacstrap.
+    Copy,
+}
 
 enum PackageManager{
     Pacman,
@@ -13,6 +47,19 @@ pub struct Pacman<'a> {
     /// or the acutal installed machine
     is_non_root: bool,
     program: PackageManager,
+    /// pacman.conf settings for both the live environment and the installed system
+    pub options: PacmanOptions,
+    /// Installs from a local directory instead of the mirrors if set.
+    pub offline: Option<OfflineSource>,
+    /// Which mirrors reflector picks, and servers given by hand
+    pub mirrors: MirrorOptions,
+    /// Installs from the Arch Linux Archive instead of the mirrors reflector finds if set.
+    pub snapshot: Option<ArchiveSnapshot>,
+    pub cache_sharing: CacheSharing,
+    /// Downloads every package in one transaction before anything is installed.
+    pub prefetch: bool,
+    /// Updates archlinux-keyring before pacstrap if the repositories have a newer one.
+    pub refresh_keyring: bool,
 }
 
 impl<'a> Pacman<'a> {
@@ -23,40 +70,37 @@ impl<'a> Pacman<'a> {
         Pacman {
             shell,
             is_non_root,
-            program: PackageManager::Pacman
+            program: PackageManager::Pacman,
+            options: PacmanOptions::default(),
+            offline: None,
+            mirrors: MirrorOptions::default(),
+            snapshot: None,
+            cache_sharing: CacheSharing::Separate,
+            prefetch: false,
+            refresh_keyring: true,
         }
     }
 
     pub fn yay(&mut self) -> &mut Self {
         self.program = PackageManager::Yay;
-        return self;
+        self
     }
 
     pub fn pacman(&mut self) -> &mut Self {
         self.program = PackageManager::Pacman;
-        return self;
+        self
     }
 
     pub fn update_mirrors(&mut self) -> Result<()> {
-        let status = if self.is_non_root {
-            self.shell.run_and_wait_with_args(
-                "su",
-                &format!("-c \"{} -Syyy --noconfirm\"", self.get_program()),
-            )?
-        } else {
-            if let PackageManager::Yay = self.program {
-                self.shell.log("ERROR: Called YAY as root.");
-                return Err(anyhow!("PACMAN: Called yay as root"));
-            }
-
-            self.shell
-                .run_and_wait_with_args("pacman", "-Syyy --noconfirm")?
-        };
+        let (status, output) = self.run_program(
+            &format!("{}-Syyy --noconfirm", self.config_arg()),
+            TransactionProgress::default(),
+        )?;
 
         if !status.success() {
             self.shell
                 .log("PACMAN: Could not update pacman. Failed when running pacman -Syyyu.");
-            return Err(anyhow!("Could not update pacman lists"));
+            return Err(self.failure(&output, "Could not update pacman lists"));
         }
         Ok(())
     }
@@ -66,25 +110,23 @@ impl<'a> Pacman<'a> {
 
         self.shell.log(&format!("Installing {}.", packages));
 
-        let status = if self.is_non_root {
-            self.shell.run_and_wait_with_args(
-                "su",
-                &format!("-c \"{} -Syu --noconfirm {}\"", self.get_program(), packages),
-            )?
-        } else {
-            if let PackageManager::Yay = self.program {
-                self.shell.log("ERROR: Called YAY as root.");
-                return Err(anyhow!("PACMAN: Called yay as root"));
+        // yay resolves AUR packages itself, pacman cannot size them
+        let sizes = match (&self.program, &self.offline) {
+            (PackageManager::Pacman, None) => {
+                let config = self.config_arg();
+                download_sizes(&mut self.shell, &config, &packages)
             }
-
-            self.shell
-                .run_and_wait_with_args("pacman", &format!("-Syu --noconfirm {}", packages))?
+            _ => HashMap::new(),
         };
+        let (status, output) = self.run_program(
+            &format!("{}-Syu --noconfirm {}", self.config_arg(), packages),
+            TransactionProgress::new(sizes),
+        )?;
 
         if !status.success() {
             self.shell
                 .log(&format!("PACMAN: Could not install {}.", packages));
-            return Err(anyhow!("Could not install {}", packages));
+            return Err(self.failure(&output, &format!("Could not install {}", packages)));
         }
 
         Ok(())
@@ -94,66 +136,483 @@ impl<'a> Pacman<'a> {
         let packages = packages.join(" ");
         self.shell.log(&format!("Uninstalling {}.", packages));
 
-        let status = if self.is_non_root {
-            self.shell.run_and_wait_with_args(
-                "su",
-                &format!("-c \"{} -Rns --noconfirm {}\"", self.get_program(), packages),
-            )?
-        } else {
-
-            if let PackageManager::Yay = self.program {
-                self.shell.log("ERROR: Called YAY as root.");
-                return Err(anyhow!("PACMAN: Called yay as root"));
-            }
-            self.shell
-                .run_and_wait_with_args("pacman", &format!("-Rns --noconfirm {}", packages))?
-        };
+        let (status, output) = self.run_program(
+            &format!("-Rns --noconfirm {}", packages),
+            TransactionProgress::default(),
+        )?;
 
         if !status.success() {
             self.shell
                 .log(&format!("Could not uninstall {}.", packages));
-            return Err(anyhow!("Could not uninstall {}", packages));
+            return Err(self.failure(&output, &format!("Could not uninstall {}", packages)));
         }
 
         Ok(())
     }
 
+    /// Runs pacman or yay, through su when not root, reporting its progress.
+    /// Returns the status and everything it printed, stdout then stderr.
+    /// pacman prints the names in conflicts and missing dependencies to stdout.
+    /// su asks for the password without a newline, which a pipe would hold back until
+    /// it was typed in, so through su the output stays on the terminal and nothing is returned.
+    fn run_program(
+        &mut self,
+        args: &str,
+        mut progress: TransactionProgress,
+    ) -> Result<(ExitStatus, String)> {
+        if self.is_non_root {
+            let args = format!("-c \"{} {}\"", self.get_program(), args);
+            let status = self.shell.run_and_wait_with_args_unchecked("su", &args)?;
+            return Ok((status, String::new()));
+        }
+        if let PackageManager::Yay = self.program {
+            self.shell.log("ERROR: Called YAY as root.");
+            return Err(anyhow!("PACMAN: Called yay as root"));
+        }
+
+        let logger = self.shell.logger();
+        let mut output = String::new();
+        let (status, stderr) =
+            self.shell
+                .run_and_wait_with_args_reading_output("pacman", args, |line| {
+                    output.push_str(line);
+                    output.push('\n');
+                    if let Some(x) = progress.parse_line(line) {
+                        logger.progress(&x);
+                    }
+                })?;
+        if status.success() {
+            self.shell.progress(&Progress::Done);
+        }
+        output.push_str(&stderr);
+        Ok((status, output))
+    }
+
+    /// A PacmanError if the output says what went wrong, the fallback message otherwise.
+    /// The UI can downcast to PacmanError for the offending names.
+    fn failure(&self, output: &str, fallback: &str) -> anyhow::Error {
+        match PacmanError::parse(output) {
+            Some(error) => {
+                self.shell.log(&format!("PACMAN: {}", error));
+                anyhow::Error::new(error)
+            }
+            None => anyhow!("{}", fallback),
+        }
+    }
+
+    /// The live environment's sync databases, synced first if there are none yet.
+    /// A fresh ISO has none until something runs pacman -Sy.
+    pub fn sync_db(&mut self) -> Result<SyncDb> {
+        if let Ok(db) = SyncDb::load(&mut self.shell, SYNC_DIR) {
+            return Ok(db);
+        }
+        if self.offline.is_some() {
+            return Err(anyhow!("There are no sync databases to read, the install is offline"));
+        }
+        self.shell.log("No sync databases, syncing them.");
+        // output is captured, the TUI may still own the terminal
+        self.shell.run_with_args("pacman", "-Sy")?;
+        SyncDb::load(&mut self.shell, SYNC_DIR)
+    }
+
     /// newer arch isos include reflector by default. this should be used in the live environment
     /// only. Using it in chroot without reflector installed might panic.
-    pub fn run_reflector(&mut self, country: &str) -> Result<()> {
-        let status = if self.is_non_root {
-            self.shell.run_and_wait_with_args(
-                "su",
-                &format!(
-                    "-c \"reflector -c {} --sort rate --save /etc/pacman.d/mirrorlist\"",
-                    country
-                ),
-            )?
+    /// Offline installs have no mirrors, only the local database is synced.
+    /// Pinned installs get the archive's snapshot as the mirrorlist.
+    pub fn run_reflector(&mut self) -> Result<()> {
+        if self.offline.is_some() {
+            self.shell.log("Offline install, skipping reflector.");
+            return self.update_mirrors();
+        }
+
+        if let Some(snapshot) = self.snapshot.clone() {
+            self.pin_mirrorlist(&snapshot)?;
+            return self.update_mirrors();
+        }
+
+        self.save_mirrorlist()?;
+        self.update_mirrors()?;
+
+        Ok(())
+    }
+
+    /// Points the mirrorlist at the snapshot, replacing whatever was there.
+    pub fn pin_mirrorlist(&mut self, snapshot: &ArchiveSnapshot) -> Result<()> {
+        self.shell.log(&format!(
+            "Pinning the mirrorlist to the archive snapshot of {}",
+            snapshot.date
+        ));
+        let mirrorlist = snapshot.mirrorlist()?;
+        if let Err(e) = RootFs::new(TargetRoot::host()).write(
+            MIRRORLIST_PATH,
+            &mirrorlist,
+            FileMode::CONFIG,
+        ) {
+            self.shell
+                .log(&format!("Could not write the mirrorlist: {}", e));
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Replaces the pinned mirrorlist with the mirrors reflector picks.
+    /// The databases are not synced, the next pacman -Syu moves to the current packages.
+    pub fn unpin(&mut self) -> Result<()> {
+        if self.snapshot.take().is_none() {
+            return Ok(());
+        }
+        self.shell.log("Unpinning the mirrorlist.");
+        self.save_mirrorlist()
+    }
+
+    /// Countries reflector knows mirrors in. Needs the network.
+    pub fn list_countries(&mut self) -> Result<Vec<String>> {
+        let output = self.shell.run_with_args("reflector", "--list-countries")?;
+        Ok(parse_countries(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    /// Writes the mirrors reflector picks to the mirrorlist,
+    /// then puts the servers given by hand in front of them.
+    fn save_mirrorlist(&mut self) -> Result<()> {
+        let mirrors = self.mirrors.clone();
+        if let Err(e) = mirrors.validate() {
+            self.shell.log(&format!("Invalid mirror: {}", e));
+            return Err(e);
+        }
+
+        let mut root_fs = RootFs::new(TargetRoot::host());
+        if mirrors.uses_reflector() {
+            let status = if self.is_non_root {
+                self.shell.run_and_wait_with_args(
+                    "su",
+                    &format!("-c \"reflector {}\"", mirrors.reflector_args()),
+                )?
+            } else {
+                self.shell
+                    .run_and_wait_with_args("reflector", &mirrors.reflector_args())?
+            };
+
+            if !status.success() {
+                self.shell
+                    .log("PACMAN: Reflector failed. Exited with non-zero status.");
+                return Err(anyhow!(
+                    "Could not retrieve new pacman mirrors from reflector."
+                ));
+            }
         } else {
-            self.shell.run_and_wait_with_args(
-                "reflector",
-                &format!("-c {} --sort rate --save /etc/pacman.d/mirrorlist", country),
-            )?
+            root_fs.write(
+                MIRRORLIST_PATH,
+                "# Written by 2lazy4arch from the servers given during the install.\n",
+                FileMode::CONFIG,
+            )?;
+        }
+
+        if !mirrors.servers.is_empty() {
+            let mut mirrorlist = Mirrorlist::parse(&root_fs.read(MIRRORLIST_PATH)?.unwrap_or_default());
+            mirrorlist.add_servers(&mirrors.servers);
+            if let Err(e) = root_fs.write(MIRRORLIST_PATH, &mirrorlist.render(), FileMode::CONFIG) {
+                self.shell
+                    .log(&format!("Could not write the mirrorlist: {}", e));
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Applies the options to this system's pacman.conf.
+    /// In the live environment this makes pacstrap download in parallel and see multilib.
+    /// Keys of extra repositories are imported first so their databases can be synced.
+    pub fn configure(&mut self) -> Result<()> {
+        self.shell.log("Configuring pacman.conf");
+        let options = self.options.clone();
+        for repo in &options.repos {
+            if let Err(e) = repo.validate() {
+                self.shell.log(&format!("Invalid repository: {}", e));
+                return Err(e);
+            }
+        }
+        self.import_repo_keys()?;
+
+        if let Err(e) = edit_pacman_conf(&mut RootFs::new(TargetRoot::host()), |conf| {
+            conf.apply(&options)
+        }) {
+            self.shell
+                .log(&format!("Could not configure pacman.conf: {}", e));
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Looks at the live environment's keyring. The databases have to be synced first.
+    pub fn keyring_health(&mut self) -> Result<KeyringHealth> {
+        let gnupg = std::path::Path::new(GNUPG_DIR);
+        let mut health = KeyringHealth {
+            initialized: gnupg.join("pubring.gpg").exists() && gnupg.join("trustdb.gpg").exists(),
+            ..Default::default()
         };
 
-        if !status.success() {
+        if let Ok(output) = self.shell.run_with_args("pacman", "-Q archlinux-keyring") {
+            health.installed = String::from_utf8_lossy(&output.stdout)
+                .split_whitespace()
+                .nth(1)
+                .map(|x| x.to_string());
+        }
+        if let Ok(output) = self.shell.run_with_args("pacman", "-Si archlinux-keyring") {
+            health.available = parse_sync_version(&String::from_utf8_lossy(&output.stdout));
+        }
+
+        if let (Some(installed), Some(available)) = (&health.installed, &health.available) {
+            let output = self
+                .shell
+                .run_with_args("vercmp", &format!("{} {}", installed, available))?;
+            health.outdated = String::from_utf8_lossy(&output.stdout).trim() == "-1";
+        }
+
+        Ok(health)
+    }
+
+    /// Initializes the keyring if it was never set up and updates archlinux-keyring
+    /// if it is outdated and refresh_keyring is set.
+    /// Offline installs only get the keyring initialized.
+    /// Returns what the keyring looks like afterwards.
+    pub fn ensure_keyring(&mut self) -> Result<KeyringHealth> {
+        self.shell.log("Checking the keyring.");
+        let mut health = self.keyring_health()?;
+
+        if !health.initialized {
+            self.shell.log("Initializing the keyring.");
+            let initialized = self.shell.run_and_wait_with_args("pacman-key", "--init");
+            let populated = self
+                .shell
+                .run_and_wait_with_args("pacman-key", "--populate archlinux");
+            if initialized.is_err() || populated.is_err() {
+                self.shell
+                    .log("PACMAN: pacman-key could not set up the keyring.");
+                return Err(anyhow!(
+                    "Could not set up the keyring. {}",
+                    KeyringProblem::NotInitialized.remedy()
+                ));
+            }
+            health = self.keyring_health()?;
+        }
+
+        if health.outdated && self.refresh_keyring && self.offline.is_none() {
+            self.shell.log("Updating archlinux-keyring.");
+            let (status, stderr) = self.shell.run_and_wait_with_args_capturing_stderr(
+                "pacman",
+                &format!("{}-S --noconfirm archlinux-keyring", self.config_arg()),
+            )?;
+            if !status.success() {
+                self.shell
+                    .log("PACMAN: Could not update archlinux-keyring.");
+                return Err(match KeyringProblem::diagnose(&stderr) {
+                    Some(problem) => anyhow!("Could not update archlinux-keyring. {}", problem),
+                    None => anyhow!("Could not update archlinux-keyring."),
+                });
+            }
+            health = self.keyring_health()?;
+        }
+
+        Ok(health)
+    }
+
+    /// Adds the signing keys of the extra repositories to the keyring and trusts them.
+    pub fn import_repo_keys(&mut self) -> Result<()> {
+        for repo in self.options.repos.clone() {
+            let key_file = match &repo.key_file {
+                Some(x) => x,
+                None => continue,
+            };
+
             self.shell
-                .log("PACMAN: Reflector failed. Exited with non-zero status.");
+                .log(&format!("Importing the key of [{}] from {}", repo.name, key_file));
+            let status = self
+                .shell
+                .run_and_wait_with_args("pacman-key", &format!("--add \"{}\"", key_file))?;
+            if !status.success() {
+                self.shell
+                    .log(&format!("PACMAN: pacman-key could not add {}.", key_file));
+                return Err(anyhow!("Could not import the key of [{}]", repo.name));
+            }
+
+            let output = self.shell.run_with_args(
+                "gpg",
+                &format!("--with-colons --show-keys \"{}\"", key_file),
+            )?;
+            let fingerprints = primary_fingerprints(&String::from_utf8_lossy(&output.stdout));
+            if fingerprints.is_empty() {
+                self.shell
+                    .log(&format!("PACMAN: No keys found in {}.", key_file));
+                return Err(anyhow!("Could not import the key of [{}]", repo.name));
+            }
+
+            for fingerprint in fingerprints {
+                let status = self
+                    .shell
+                    .run_and_wait_with_args("pacman-key", &format!("--lsign-key {}", fingerprint))?;
+                if !status.success() {
+                    self.shell
+                        .log(&format!("PACMAN: Could not sign {}.", fingerprint));
+                    return Err(anyhow!("Could not trust the key of [{}]", repo.name));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the pacman.conf of the offline install.
+    /// A cache gets a database generated with repo-add first.
+    /// Must be called in the live environment before anything is installed.
+    pub fn prepare_offline(&mut self) -> Result<()> {
+        let offline = match &self.offline {
+            Some(x) => x.clone(),
+            None => return Ok(()),
+        };
+
+        self.shell
+            .log(&format!("Preparing offline install from {}", offline.path()));
+        if let Err(e) = offline.validate() {
+            self.shell.log(&format!("Invalid offline source: {}", e));
+            return Err(e);
+        }
+        std::fs::create_dir_all(OFFLINE_DIR)?;
+
+        if let OfflineSource::Cache { .. } = offline {
+            let packages = offline
+                .packages()?
+                .iter()
+                .map(|x| format!("\"{}\"", x))
+                .collect::<Vec<String>>()
+                .join(" ");
+            self.shell.run_with_args(
+                "repo-add",
+                &format!(
+                    "-q \"{}/{}.db.tar.gz\" {}",
+                    OFFLINE_DIR,
+                    offline.repo_name(),
+                    packages
+                ),
+            )?;
+        }
+
+        std::fs::write(OfflineSource::conf_path(), offline.pacman_conf())?;
+        Ok(())
+    }
+
+    /// Fails if any of the packages, or anything they depend on, is not in the offline source.
+    /// Uses a database path of its own so the live environment's is left alone.
+    pub fn check_offline_packages(&mut self, packages: &[String]) -> Result<()> {
+        if self.offline.is_none() {
+            return Ok(());
+        }
+
+        self.shell.log("Checking the offline source for the packages.");
+        let dbpath = format!("{}/db", OFFLINE_DIR);
+        std::fs::create_dir_all(&dbpath)?;
+        let args = format!("{}--dbpath {}", self.config_arg(), dbpath);
+        self.shell.run_with_args("pacman", &format!("{} -Sy", args))?;
+
+        let output = self.shell.run_with_args("pacman", &format!("{} -Slq", args))?;
+        let available = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|x| x.to_string())
+            .collect::<Vec<String>>();
+        let missing = packages
+            .iter()
+            .filter(|x| !available.contains(x))
+            .cloned()
+            .collect::<Vec<String>>();
+        if !missing.is_empty() {
+            self.shell.log(&format!(
+                "PACMAN: Missing from the offline source: {}",
+                missing.join(" ")
+            ));
             return Err(anyhow!(
-                "Could not retrieve new pacman mirrors from reflector."
+                "Packages missing from the offline source: {}",
+                missing.join(", ")
             ));
         }
 
-        self.update_mirrors()?;
+        // resolves the dependencies without installing anything, pacman prints what is missing
+        if let Err(e) = self.shell.run_and_wait_with_args(
+            "pacman",
+            &format!("{} -Sp --print-format %n {}", args, packages.join(" ")),
+        ) {
+            self.shell.log(&format!(
+                "PACMAN: Dependencies are missing from the offline source: {}",
+                e
+            ));
+            return Err(anyhow!("Dependencies are missing from the offline source"));
+        }
+
+        Ok(())
+    }
+
+    /// Downloads the packages, and everything they depend on, into the cache pacstrap
+    /// and the chroot use. Resolved against the new system's empty database, so packages
+    /// the live environment already has are downloaded too.
+    pub fn prefetch(&mut self, root: &TargetRoot, packages: &[String]) -> Result<()> {
+        let cache = match self.cache_sharing {
+            CacheSharing::Bind => PACKAGE_CACHE.to_string(),
+            _ => root.path(PACKAGE_CACHE).display().to_string(),
+        };
+        self.shell
+            .log(&format!("Prefetching {} packages into {}", packages.len(), cache));
+        std::fs::create_dir_all(&cache)?;
+        std::fs::create_dir_all(root.path("/var/lib/pacman"))?;
 
+        if let Err(e) = self.shell.run_and_wait_with_args(
+            "pacman",
+            &format!(
+                "{}-r {} --cachedir {} -Syw --noconfirm {}",
+                self.config_arg(),
+                root,
+                cache,
+                packages.join(" ")
+            ),
+        ) {
+            self.shell
+                .log(&format!("PACMAN: Prefetching failed: {}", e));
+            return Err(anyhow!("Could not download the packages"));
+        }
         Ok(())
     }
 
+    /// --config of the offline install, followed by a space. Empty otherwise.
+    fn config_arg(&self) -> String {
+        match self.offline {
+            Some(_) => format!("--config {} ", OfflineSource::conf_path()),
+            None => String::new(),
+        }
+    }
 
     fn get_program(&self) -> &str {
         match self.program {
-            PackageManager::Pacman => &"pacman",
-            PackageManager::Yay => &"yay",
+            PackageManager::Pacman => "pacman",
+            PackageManager::Yay => "yay",
+        }
+    }
+}
+
+/// Fingerprints of the primary keys in `gpg --with-colons` output.
+/// Subkeys have their own fpr lines, only the first one after a pub line is the primary.
+fn primary_fingerprints(colons: &str) -> Vec<String> {
+    let mut fingerprints = Vec::new();
+    let mut after_pub = false;
+    for line in colons.lines() {
+        let fields = line.split(':').collect::<Vec<&str>>();
+        match fields[0] {
+            "pub" => after_pub = true,
+            "fpr" if after_pub => {
+                if let Some(x) = fields.get(9).filter(|x| !x.is_empty()) {
+                    fingerprints.push(x.to_string());
+                }
+                after_pub = false;
+            }
+            _ => {}
         }
     }
+    fingerprints
 }
@@ -0,0 +1,590 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::target_fs::{FileMode, TargetFs};
+
+pub const PACMAN_CONF_PATH: &str = "/etc/pacman.conf";
+
+/// The pacman.conf settings the installer manages.
+/// Applied to both the live environment and the installed system.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PacmanOptions {
+    pub color: bool,
+    /// None keeps whatever is set
+    pub parallel_downloads: Option<usize>,
+    pub verbose_pkg_lists: bool,
+    /// pacman in style
+    pub candy: bool,
+    pub multilib: bool,
+    /// Extra repositories, added after the stock ones
+    pub repos: Vec<Repo>,
+}
+
+impl Default for PacmanOptions {
+    /// Changes nothing on a stock pacman.conf
+    fn default() -> Self {
+        PacmanOptions {
+            color: false,
+            parallel_downloads: None,
+            verbose_pkg_lists: false,
+            candy: false,
+            multilib: false,
+            repos: Vec::new(),
+        }
+    }
+}
+
+/// A repository section, eg.
+/// [chaotic-aur]
+/// SigLevel = Required DatabaseOptional
+/// Server = https://example.com/$repo/$arch
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Repo {
+    pub name: String,
+    #[serde(default)]
+    pub sig_level: Option<String>,
+    #[serde(default)]
+    pub servers: Vec<String>,
+    /// eg. /etc/pacman.d/mirrorlist
+    #[serde(default)]
+    pub include: Option<String>,
+    /// Public key the packages are signed with, added to the keyring and locally signed.
+    /// Not written to pacman.conf.
+    #[serde(default)]
+    pub key_file: Option<String>,
+}
+
+impl Repo {
+    /// Checks that pacman can make sense of the repository.
+    pub fn validate(&self) -> Result<()> {
+        let valid_name = !self.name.is_empty()
+            && self
+                .name
+                .chars()
+                .all(|x| x.is_ascii_alphanumeric() || x == '-' || x == '_');
+        if !valid_name || self.name == "options" {
+            return Err(anyhow!("{} is not a valid repository name", self.name));
+        }
+
+        if self.servers.is_empty() && self.include.is_none() {
+            return Err(anyhow!("[{}] needs a Server or an Include", self.name));
+        }
+
+        if let Some(key_file) = &self.key_file {
+            if !Path::new(key_file).is_file() {
+                return Err(anyhow!("Key file {} does not exist", key_file));
+            }
+        }
+
+        for path in self.local_paths() {
+            if !Path::new(&path).is_dir() {
+                return Err(anyhow!("Local repository {} does not exist", path));
+            }
+        }
+        Ok(())
+    }
+
+    /// Directories of file:// servers, eg. file:///home/custompkgs is /home/custompkgs
+    /// Anything from the first $repo or $arch on is left out.
+    pub fn local_paths(&self) -> Vec<String> {
+        self.servers
+            .iter()
+            .filter_map(|x| x.strip_prefix("file://"))
+            .map(|x| match x.find('$') {
+                Some(i) => x[..i].trim_end_matches('/').to_string(),
+                None => x.trim_end_matches('/').to_string(),
+            })
+            .filter(|x| !x.is_empty())
+            .collect()
+    }
+
+    fn lines(&self) -> Vec<String> {
+        let mut lines = vec![format!("[{}]", self.name)];
+        if let Some(sig_level) = &self.sig_level {
+            lines.push(format!("SigLevel = {}", sig_level));
+        }
+        lines.extend(self.servers.iter().map(|x| format!("Server = {}", x)));
+        if let Some(include) = &self.include {
+            lines.push(format!("Include = {}", include));
+        }
+        lines
+    }
+}
+
+/// pacman.conf, edited section by section instead of by line number.
+/// Comments and everything that is not touched are kept as is,
+/// so applying the same change twice does nothing the second time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PacmanConf {
+    lines: Vec<String>,
+}
+
+/// A [section] header, commented out or not.
+struct Section {
+    name: String,
+    header: usize,
+    commented: bool,
+    /// One past the last line that belongs to the section.
+    end: usize,
+}
+
+impl PacmanConf {
+    pub fn parse(content: &str) -> PacmanConf {
+        PacmanConf {
+            lines: content.lines().map(|x| x.to_string()).collect(),
+        }
+    }
+
+    pub fn render(&self) -> String {
+        format!("{}\n", self.lines.join("\n"))
+    }
+
+    /// Value of an option in [options].
+    /// Some("") for flags like Color, None if it is not set.
+    pub fn option(&self, key: &str) -> Option<String> {
+        let section = self.section("options", false)?;
+        self.lines[section.header + 1..section.end]
+            .iter()
+            .filter_map(|x| parse_key_value(x))
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v)
+    }
+
+    /// Sets an option in [options]. Flags like Color take no value.
+    /// Uncomments the stock line if there is one, eg. #Color.
+    /// Otherwise it goes after the last option.
+    pub fn set_option(&mut self, key: &str, value: Option<&str>) {
+        self.set_option_after(key, value, None)
+    }
+
+    /// Same as set_option, but a new line goes right after the option `after` if it is there.
+    /// eg. ILoveCandy next to Color
+    pub fn set_option_after(&mut self, key: &str, value: Option<&str>, after: Option<&str>) {
+        let line = match value {
+            Some(x) => format!("{} = {}", key, x),
+            None => key.to_string(),
+        };
+
+        let section = match self.section("options", false) {
+            Some(x) => x,
+            None => {
+                self.lines.push("[options]".to_string());
+                self.lines.push(line);
+                return;
+            }
+        };
+
+        let body = section.header + 1..section.end;
+        let set = body
+            .clone()
+            .find(|x| parse_key_value(&self.lines[*x]).is_some_and(|(k, _)| k == key));
+        let commented = body.clone().find(|x| {
+            parse_commented_key_value(&self.lines[*x]).is_some_and(|(k, _)| k == key)
+        });
+        let is_option = |x: &String, key: Option<&str>| {
+            parse_key_value(x)
+                .or_else(|| parse_commented_key_value(x))
+                .is_some_and(|(k, _)| key.is_none() || key == Some(k.as_str()))
+        };
+        let last_option = body
+            .clone()
+            .rev()
+            .find(|x| after.is_some() && is_option(&self.lines[*x], after))
+            .or_else(|| body.rev().find(|x| is_option(&self.lines[*x], None)));
+
+        match (set, commented, last_option) {
+            (Some(x), _, _) | (None, Some(x), _) => self.lines[x] = line,
+            (None, None, Some(x)) => self.lines.insert(x + 1, line),
+            (None, None, None) => self.lines.insert(section.header + 1, line),
+        }
+    }
+
+    /// Comments an option out, keeping it around to be turned back on.
+    pub fn unset_option(&mut self, key: &str) {
+        let section = match self.section("options", false) {
+            Some(x) => x,
+            None => return,
+        };
+
+        for index in section.header + 1..section.end {
+            if parse_key_value(&self.lines[index]).is_some_and(|(k, _)| k == key) {
+                self.lines[index] = format!("#{}", self.lines[index].trim_start());
+            }
+        }
+    }
+
+    /// Repositories that are turned on, in the order pacman uses them.
+    pub fn repos(&self) -> Vec<String> {
+        self.sections()
+            .into_iter()
+            .filter(|x| !x.commented && x.name != "options")
+            .map(|x| x.name)
+            .collect()
+    }
+
+    /// Uncomments a stock repository, eg. multilib.
+    /// Fails if there is no such section, use add_repo for those.
+    pub fn enable_repo(&mut self, name: &str) -> Result<()> {
+        if self.section(name, false).is_some() {
+            return Ok(());
+        }
+
+        let section = match self.section(name, true) {
+            Some(x) => x,
+            None => return Err(anyhow!("There is no [{}] in pacman.conf", name)),
+        };
+
+        self.lines[section.header] = format!("[{}]", name);
+        for index in section.header + 1..section.end {
+            if parse_commented_key_value(&self.lines[index]).is_some() {
+                self.lines[index] = self.lines[index].trim().trim_start_matches('#').to_string();
+            }
+        }
+        Ok(())
+    }
+
+    /// Comments a repository out.
+    pub fn disable_repo(&mut self, name: &str) {
+        let section = match self.section(name, false) {
+            Some(x) => x,
+            None => return,
+        };
+
+        self.lines[section.header] = format!("#[{}]", name);
+        for index in section.header + 1..section.end {
+            if parse_key_value(&self.lines[index]).is_some() {
+                self.lines[index] = format!("#{}", self.lines[index].trim_start());
+            }
+        }
+    }
+
+    /// Adds a repository after the stock ones, or replaces it if it is already there.
+    pub fn add_repo(&mut self, repo: &Repo) {
+        let mut lines = repo.lines();
+        match self.section(&repo.name, false) {
+            Some(section) => {
+                // keep the blank line that separates it from the next section
+                let end = (section.header + 1..section.end)
+                    .rev()
+                    .find(|x| !self.lines[*x].trim().is_empty())
+                    .map_or(section.header + 1, |x| x + 1);
+                self.lines.splice(section.header..end, lines);
+            }
+            None => {
+                if self.lines.last().is_some_and(|x| !x.trim().is_empty()) {
+                    lines.insert(0, String::new());
+                }
+                self.lines.extend(lines);
+            }
+        }
+    }
+
+    pub fn remove_repo(&mut self, name: &str) {
+        if let Some(section) = self.section(name, false) {
+            // take the blank line add_repo put in front of it along
+            let start = if section.end == self.lines.len()
+                && section.header > 0
+                && self.lines[section.header - 1].trim().is_empty()
+            {
+                section.header - 1
+            } else {
+                section.header
+            };
+            self.lines.drain(start..section.end);
+        }
+    }
+
+    /// Applies everything in options.
+    /// Options that are turned off are commented out.
+    pub fn apply(&mut self, options: &PacmanOptions) -> Result<()> {
+        let flags = [
+            ("Color", options.color),
+            ("VerbosePkgLists", options.verbose_pkg_lists),
+            ("ILoveCandy", options.candy),
+        ];
+        for (key, enabled) in flags {
+            if enabled {
+                self.set_option_after(key, None, Some("Color"));
+            } else {
+                self.unset_option(key);
+            }
+        }
+
+        if let Some(parallel_downloads) = options.parallel_downloads {
+            self.set_option("ParallelDownloads", Some(&parallel_downloads.to_string()));
+        }
+
+        if options.multilib {
+            self.enable_repo("multilib")?;
+        } else {
+            self.disable_repo("multilib");
+        }
+
+        for repo in &options.repos {
+            self.add_repo(repo);
+        }
+        Ok(())
+    }
+
+    fn section(&self, name: &str, commented: bool) -> Option<Section> {
+        self.sections()
+            .into_iter()
+            .find(|x| x.name == name && x.commented == commented)
+    }
+
+    fn sections(&self) -> Vec<Section> {
+        let mut sections: Vec<Section> = Vec::new();
+        for (index, line) in self.lines.iter().enumerate() {
+            let (name, commented) = match parse_header(line) {
+                Some(x) => x,
+                None => {
+                    continue;
+                }
+            };
+            if let Some(last) = sections.last_mut() {
+                last.end = index;
+            }
+            sections.push(Section {
+                name,
+                header: index,
+                commented,
+                end: self.lines.len(),
+            });
+        }
+
+        // a commented section ends at the first blank line
+        for section in sections.iter_mut().filter(|x| x.commented) {
+            if let Some(x) =
+                (section.header + 1..section.end).find(|x| self.lines[*x].trim().is_empty())
+            {
+                section.end = x;
+            }
+        }
+        sections
+    }
+}
+
+/// [name] or #[name]
+fn parse_header(line: &str) -> Option<(String, bool)> {
+    let line = line.trim();
+    let (line, commented) = match line.strip_prefix('#') {
+        Some(x) => (x.trim(), true),
+        None => (line, false),
+    };
+    let name = line.strip_prefix('[')?.strip_suffix(']')?;
+    Some((name.to_string(), commented))
+}
+
+/// Key = Value, or just Key for flags.
+fn parse_key_value(line: &str) -> Option<(String, String)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') || line.starts_with('[') {
+        return None;
+    }
+    let (key, value) = match line.split_once('=') {
+        Some((k, v)) => (k.trim(), v.trim()),
+        None => (line, ""),
+    };
+    if key.is_empty() || !key.chars().all(|x| x.is_ascii_alphanumeric()) {
+        return None;
+    }
+    Some((key.to_string(), value.to_string()))
+}
+
+/// #Key = Value, the way the stock config has its defaults commented out.
+/// Prose comments like "# Misc options" have a space after the # and are not options.
+fn parse_commented_key_value(line: &str) -> Option<(String, String)> {
+    let line = line.trim().strip_prefix('#')?;
+    if line.starts_with(char::is_whitespace) {
+        return None;
+    }
+    parse_key_value(line)
+}
+
+/// Reads pacman.conf, edits it and writes it back if anything changed.
+/// Use a TargetFs rooted at / for the live environment.
+pub fn edit_pacman_conf<F: FnOnce(&mut PacmanConf) -> Result<()>>(
+    target_fs: &mut dyn TargetFs,
+    edit: F,
+) -> Result<()> {
+    let content = match target_fs.read(PACMAN_CONF_PATH)? {
+        Some(x) => x,
+        None => return Err(anyhow!("Could not open pacman.conf")),
+    };
+
+    let mut conf = PacmanConf::parse(&content);
+    edit(&mut conf)?;
+    let rendered = conf.render();
+    if rendered == content {
+        return Ok(());
+    }
+    target_fs.write(PACMAN_CONF_PATH, &rendered, FileMode::CONFIG)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::target_fs::{MemoryFs, MemoryNode};
+
+    /// The parts of the stock pacman.conf the installer touches
+    const STOCK: &str = "\
+#
+# /etc/pacman.conf
+#
+[options]
+HoldPkg     = pacman glibc
+Architecture = auto
+
+# Misc options
+#UseSyslog
+#Color
+#NoProgressBar
+CheckSpace
+#VerbosePkgLists
+ParallelDownloads = 5
+
+SigLevel    = Required DatabaseOptional
+LocalFileSigLevel = Optional
+
+[core]
+Include = /etc/pacman.d/mirrorlist
+
+[extra]
+Include = /etc/pacman.d/mirrorlist
+
+#[multilib-testing]
+#Include = /etc/pacman.d/mirrorlist
+
+#[multilib]
+#Include = /etc/pacman.d/mirrorlist
+
+# An example of a custom package repository.  See the pacman manpage for
+# tips on creating your own repositories.
+#[custom]
+#SigLevel = Optional TrustAll
+#Server = file:///home/custompkgs
+";
+
+    fn chaotic() -> Repo {
+        Repo {
+            name: "chaotic-aur".to_string(),
+            sig_level: Some("Required DatabaseOptional".to_string()),
+            servers: Vec::new(),
+            include: Some("/etc/pacman.d/chaotic-mirrorlist".to_string()),
+            key_file: None,
+        }
+    }
+
+    #[test]
+    fn reads_options_and_repos() {
+        let conf = PacmanConf::parse(STOCK);
+        assert_eq!(conf.option("ParallelDownloads"), Some("5".to_string()));
+        assert_eq!(conf.option("CheckSpace"), Some(String::new()));
+        assert_eq!(conf.option("Color"), None);
+        assert_eq!(conf.repos(), vec!["core", "extra"]);
+        assert_eq!(conf.render(), STOCK);
+    }
+
+    #[test]
+    fn sets_options_in_place() {
+        let mut conf = PacmanConf::parse(STOCK);
+        conf.set_option("Color", None);
+        conf.set_option_after("ILoveCandy", None, Some("Color"));
+        conf.set_option("ParallelDownloads", Some("10"));
+        conf.unset_option("CheckSpace");
+
+        assert_eq!(
+            conf.render(),
+            STOCK
+                .replace("#Color\n", "Color\nILoveCandy\n")
+                .replace("ParallelDownloads = 5", "ParallelDownloads = 10")
+                .replace("\nCheckSpace\n", "\n#CheckSpace\n")
+        );
+    }
+
+    #[test]
+    fn toggles_multilib() {
+        let mut conf = PacmanConf::parse(STOCK);
+        conf.enable_repo("multilib").unwrap();
+        conf.enable_repo("multilib").unwrap();
+        assert_eq!(conf.repos(), vec!["core", "extra", "multilib"]);
+        assert_eq!(
+            conf.render(),
+            STOCK.replace(
+                "#[multilib]\n#Include = /etc/pacman.d/mirrorlist",
+                "[multilib]\nInclude = /etc/pacman.d/mirrorlist"
+            )
+        );
+
+        conf.disable_repo("multilib");
+        assert_eq!(conf.render(), STOCK);
+        assert!(conf.enable_repo("chaotic-aur").is_err());
+    }
+
+    #[test]
+    fn adds_replaces_and_removes_repos() {
+        let mut conf = PacmanConf::parse(STOCK);
+        conf.add_repo(&chaotic());
+        assert_eq!(
+            conf.render(),
+            format!(
+                "{}\n[chaotic-aur]\nSigLevel = Required DatabaseOptional\n\
+Include = /etc/pacman.d/chaotic-mirrorlist\n",
+                STOCK
+            )
+        );
+
+        let mut repo = chaotic();
+        repo.sig_level = None;
+        conf.add_repo(&repo);
+        assert_eq!(conf.repos(), vec!["core", "extra", "chaotic-aur"]);
+        assert!(!conf.render().contains("SigLevel = Required DatabaseOptional\nInclude"));
+
+        conf.remove_repo("chaotic-aur");
+        assert_eq!(conf.render(), STOCK);
+    }
+
+    #[test]
+    fn applying_twice_changes_nothing() {
+        let options = PacmanOptions {
+            color: true,
+            parallel_downloads: Some(8),
+            verbose_pkg_lists: true,
+            candy: true,
+            multilib: true,
+            repos: vec![chaotic()],
+        };
+        let mut conf = PacmanConf::parse(STOCK);
+        conf.apply(&options).unwrap();
+        let once = conf.render();
+        conf.apply(&options).unwrap();
+        assert_eq!(conf.render(), once);
+        assert_eq!(once.matches("[multilib]").count(), 1);
+
+        conf.apply(&PacmanOptions::default()).unwrap();
+        assert_eq!(conf.option("Color"), None);
+        assert_eq!(conf.repos(), vec!["core", "extra", "chaotic-aur"]);
+    }
+
+    #[test]
+    fn edit_writes_only_on_change() {
+        let mut target_fs = MemoryFs::new().with_file(PACMAN_CONF_PATH, STOCK);
+        edit_pacman_conf(&mut target_fs, |_| Ok(())).unwrap();
+        assert!(target_fs.touched().is_empty());
+
+        edit_pacman_conf(&mut target_fs, |conf| conf.enable_repo("multilib")).unwrap();
+        assert_eq!(target_fs.touched(), [PACMAN_CONF_PATH]);
+        match target_fs.get(PACMAN_CONF_PATH) {
+            Some(MemoryNode::File { content, mode }) => {
+                assert!(content.contains("\n[multilib]\n"));
+                assert_eq!(*mode, FileMode::CONFIG);
+            }
+            x => panic!("pacman.conf is {:?}", x),
+        }
+
+        assert!(edit_pacman_conf(&mut MemoryFs::new(), |_| Ok(())).is_err());
+    }
+}
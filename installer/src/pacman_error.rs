@@ -0,0 +1,421 @@
+use std::fmt::Display;
+
+use crate::keyring::KeyringProblem;
+
+/// A file that is already on the disk and would be overwritten by a package
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileConflict {
+    pub package: String,
+    pub path: String,
+    /// The installed package that owns the file, None if no package does
+    pub owner: Option<String>,
+}
+
+/// Why pacman, pacstrap or yay failed, read from what they printed.
+/// Carries the offending names so the user can be told exactly what to fix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PacmanError {
+    /// error: target not found: foo
+    TargetNotFound(Vec<String>),
+    /// :: unable to satisfy dependency 'foo>=2' required by bar
+    UnsatisfiedDependencies(Vec<(String, String)>),
+    /// foo: /usr/bin/foo exists in filesystem (owned by bar)
+    ConflictingFiles(Vec<FileConflict>),
+    /// error: failed to init transaction (unable to lock database)
+    DatabaseLocked {
+        lock_file: String,
+    },
+    /// error: failed retrieving file 'foo-1.0-1-x86_64.pkg.tar.zst' from ...
+    DownloadFailed(Vec<String>),
+    /// error: key "ABCD..." could not be looked up remotely
+    KeyLookupFailed(Vec<String>),
+    Keyring(KeyringProblem),
+    /// Anything else, with the error lines pacman printed
+    Other(Vec<String>),
+}
+
+/// Where pacman keeps its lock file, used when the message does not say
+const DEFAULT_LOCK_FILE: &str = "/var/lib/pacman/db.lck";
+
+impl PacmanError {
+    /// None if there is no error in the output.
+    /// Takes stdout and stderr together, pacman prints most of the names to stdout.
+    pub fn parse(output: &str) -> Option<PacmanError> {
+        let lines = output.lines().map(|x| x.trim()).collect::<Vec<&str>>();
+
+        let mut not_found = lines
+            .iter()
+            .filter_map(|x| x.strip_prefix("error: target not found:"))
+            .map(|x| x.trim().to_string())
+            .collect::<Vec<String>>();
+        // yay lists them after "could not find all required packages", eg. "foo (Target)"
+        if let Some(index) = lines
+            .iter()
+            .position(|x| x.contains("could not find all required packages"))
+        {
+            not_found.extend(
+                lines[index + 1..]
+                    .iter()
+                    .map_while(|x| x.strip_suffix("(Target)"))
+                    .map(|x| x.trim().to_string()),
+            );
+        }
+        not_found.extend(
+            lines
+                .iter()
+                .filter_map(|x| x.split_once("No AUR package found for"))
+                .map(|(_, x)| x.trim().to_string()),
+        );
+        if !not_found.is_empty() {
+            not_found.dedup();
+            return Some(PacmanError::TargetNotFound(not_found));
+        }
+
+        let dependencies = lines
+            .iter()
+            .filter_map(|x| x.split_once("unable to satisfy dependency"))
+            .filter_map(|(_, x)| {
+                let (dependency, required_by) = x.split_once("required by")?;
+                Some((
+                    dependency.trim().trim_matches('\'').to_string(),
+                    required_by.trim().to_string(),
+                ))
+            })
+            .collect::<Vec<(String, String)>>();
+        if !dependencies.is_empty() {
+            return Some(PacmanError::UnsatisfiedDependencies(dependencies));
+        }
+
+        if lines.iter().any(|x| x.contains("(conflicting files)")) {
+            let conflicts = lines
+                .iter()
+                .filter_map(|x| parse_conflict(x))
+                .collect::<Vec<FileConflict>>();
+            return Some(PacmanError::ConflictingFiles(conflicts));
+        }
+
+        if lines
+            .iter()
+            .any(|x| x.contains("unable to lock database") || x.contains("could not lock database"))
+        {
+            let lock_file = lines
+                .iter()
+                .find_map(|x| x.split_once("you can remove"))
+                .map(|(_, x)| x.trim().to_string())
+                .unwrap_or(DEFAULT_LOCK_FILE.to_string());
+            return Some(PacmanError::DatabaseLocked { lock_file });
+        }
+
+        let keys = lines
+            .iter()
+            .filter(|x| x.contains("could not be looked up remotely"))
+            .filter_map(|x| x.split('"').nth(1))
+            .map(|x| x.to_string())
+            .collect::<Vec<String>>();
+        if !keys.is_empty() {
+            return Some(PacmanError::KeyLookupFailed(keys));
+        }
+
+        if let Some(problem) = KeyringProblem::diagnose(output) {
+            return Some(PacmanError::Keyring(problem));
+        }
+
+        let downloads = lines
+            .iter()
+            .filter_map(|x| x.strip_prefix("error: failed retrieving file"))
+            .filter_map(|x| x.split('\'').nth(1))
+            .map(|x| x.to_string())
+            .collect::<Vec<String>>();
+        if !downloads.is_empty()
+            || lines
+                .iter()
+                .any(|x| x.contains("failed to retrieve some files"))
+        {
+            let mut downloads = downloads;
+            downloads.dedup();
+            return Some(PacmanError::DownloadFailed(downloads));
+        }
+
+        let errors = lines
+            .iter()
+            .filter(|x| x.starts_with("error:"))
+            .map(|x| x.to_string())
+            .collect::<Vec<String>>();
+        if errors.is_empty() {
+            None
+        } else {
+            Some(PacmanError::Other(errors))
+        }
+    }
+
+    /// Names of the packages, files or keys at fault
+    pub fn culprits(&self) -> Vec<String> {
+        match self {
+            PacmanError::TargetNotFound(x) => x.clone(),
+            PacmanError::UnsatisfiedDependencies(x) => x.iter().map(|x| x.0.clone()).collect(),
+            PacmanError::ConflictingFiles(x) => x.iter().map(|x| x.path.clone()).collect(),
+            PacmanError::DatabaseLocked { lock_file } => vec![lock_file.clone()],
+            PacmanError::DownloadFailed(x) => x.clone(),
+            PacmanError::KeyLookupFailed(x) => x.clone(),
+            PacmanError::Keyring(_) | PacmanError::Other(_) => Vec::new(),
+        }
+    }
+
+    /// What the user should do about it
+    pub fn remedy(&self) -> String {
+        match self {
+            PacmanError::TargetNotFound(_) => {
+                "Check the package names for typos, or remove them from the list.".to_string()
+            }
+            PacmanError::UnsatisfiedDependencies(_) => {
+                "A repository is missing or out of sync. Enable it in pacman.conf \
+or try another mirror."
+                    .to_string()
+            }
+            PacmanError::ConflictingFiles(conflicts) => {
+                if conflicts.iter().any(|x| x.owner.is_none()) {
+                    "Move the files that no package owns out of the way, then try again."
+                        .to_string()
+                } else {
+                    "Remove the package that owns the files first, then try again.".to_string()
+                }
+            }
+            PacmanError::DatabaseLocked { lock_file } => format!(
+                "Another pacman is running, or one was interrupted. \
+If none is running, delete {} and try again.",
+                lock_file
+            ),
+            PacmanError::DownloadFailed(_) => {
+                "Check the network connection, or pick other mirrors.".to_string()
+            }
+            PacmanError::KeyLookupFailed(_) => KeyringProblem::MissingKey.remedy().to_string(),
+            PacmanError::Keyring(problem) => problem.remedy().to_string(),
+            PacmanError::Other(_) => "See the output of pacman above.".to_string(),
+        }
+    }
+}
+
+impl Display for PacmanError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let culprits = self.culprits().join(", ");
+        match self {
+            PacmanError::TargetNotFound(_) => write!(f, "Packages not found: {}.", culprits)?,
+            PacmanError::UnsatisfiedDependencies(x) => write!(
+                f,
+                "Unsatisfied dependencies: {}.",
+                x.iter()
+                    .map(|(dependency, required_by)| format!(
+                        "{} required by {}",
+                        dependency, required_by
+                    ))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            )?,
+            PacmanError::ConflictingFiles(_) => {
+                write!(f, "Files already on the disk: {}.", culprits)?
+            }
+            PacmanError::DatabaseLocked { .. } => write!(f, "The pacman database is locked.")?,
+            PacmanError::DownloadFailed(_) => write!(f, "Could not download: {}.", culprits)?,
+            PacmanError::KeyLookupFailed(_) => {
+                write!(f, "Could not look up the keys: {}.", culprits)?
+            }
+            PacmanError::Keyring(problem) => return write!(f, "{}", problem),
+            PacmanError::Other(errors) => write!(f, "{}", errors.join(" "))?,
+        }
+        write!(f, " {}", self.remedy())
+    }
+}
+
+impl std::error::Error for PacmanError {}
+
+/// eg. "foo: /usr/bin/foo exists in filesystem (owned by bar)"
+fn parse_conflict(line: &str) -> Option<FileConflict> {
+    // two of the new packages have the same file, eg. "/usr/bin/foo exists in both 'a' and 'b'"
+    // pacman does not put the package in front of these
+    if let Some((path, owners)) = line.split_once(" exists in both ") {
+        let mut owners = owners.split('\'');
+        let path = match path.split_once(": ") {
+            Some((_, x)) => x,
+            None => path,
+        };
+        return Some(FileConflict {
+            package: owners.nth(1)?.to_string(),
+            path: path.trim().to_string(),
+            owner: owners.nth(1).map(|x| x.to_string()),
+        });
+    }
+
+    let (package, rest) = line.split_once(": ")?;
+    let (path, owner) = rest.split_once(" exists in filesystem")?;
+    let owner = owner
+        .trim()
+        .strip_prefix("(owned by ")
+        .and_then(|x| x.strip_suffix(')'))
+        .map(|x| x.to_string());
+    Some(FileConflict {
+        package: package.to_string(),
+        path: path.trim().to_string(),
+        owner,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// stdout then stderr, the way Pacman::run_program hands them over
+    fn output(stdout: &str, stderr: &str) -> String {
+        format!("{}{}", stdout, stderr)
+    }
+
+    const CONFLICTS_STDOUT: &str = "\
+resolving dependencies...
+looking for conflicting packages...
+
+Packages (2) foo-1.0-1  foo-git-1.0.r3-1
+
+Total Installed Size:  0.12 MiB
+
+:: Proceed with installation? [Y/n] 
+(2/2) checking keys in keyring                     [######################] 100%
+(2/2) checking package integrity                   [######################] 100%
+(2/2) loading package files                        [######################] 100%
+(2/2) checking for file conflicts                  [######################] 100%
+foo: /usr/bin/foo exists in filesystem (owned by bar)
+foo: /etc/foo.conf exists in filesystem
+/usr/share/foo/data exists in both 'foo' and 'foo-git'
+Errors occurred, no packages were upgraded.
+";
+    const CONFLICTS_STDERR: &str = "error: failed to commit transaction (conflicting files)\n";
+
+    #[test]
+    fn conflicts_come_from_stdout() {
+        // stderr alone only says that there are conflicts
+        assert_eq!(
+            PacmanError::parse(CONFLICTS_STDERR),
+            Some(PacmanError::ConflictingFiles(Vec::new()))
+        );
+        assert_eq!(
+            PacmanError::parse(&output(CONFLICTS_STDOUT, CONFLICTS_STDERR)),
+            Some(PacmanError::ConflictingFiles(vec![
+                FileConflict {
+                    package: "foo".to_string(),
+                    path: "/usr/bin/foo".to_string(),
+                    owner: Some("bar".to_string()),
+                },
+                FileConflict {
+                    package: "foo".to_string(),
+                    path: "/etc/foo.conf".to_string(),
+                    owner: None,
+                },
+                FileConflict {
+                    package: "foo".to_string(),
+                    path: "/usr/share/foo/data".to_string(),
+                    owner: Some("foo-git".to_string()),
+                },
+            ]))
+        );
+    }
+
+    #[test]
+    fn unsatisfied_dependencies_come_from_stdout() {
+        let stdout = "\
+resolving dependencies...
+warning: cannot resolve \"libfoo>=2\", a dependency of \"bar\"
+:: The following package cannot be upgraded due to unresolvable dependencies:
+      bar
+
+:: unable to satisfy dependency 'libfoo>=2' required by bar
+";
+        let stderr = "error: failed to prepare transaction (could not satisfy dependencies)\n";
+        assert_eq!(
+            PacmanError::parse(&output(stdout, stderr)),
+            Some(PacmanError::UnsatisfiedDependencies(vec![(
+                "libfoo>=2".to_string(),
+                "bar".to_string()
+            )]))
+        );
+    }
+
+    #[test]
+    fn targets_not_found() {
+        let stderr = "error: target not found: neovimm\nerror: target not found: fierfox\n";
+        assert_eq!(
+            PacmanError::parse(&output("", stderr)),
+            Some(PacmanError::TargetNotFound(vec![
+                "neovimm".to_string(),
+                "fierfox".to_string()
+            ]))
+        );
+    }
+
+    #[test]
+    fn yay_targets_not_found() {
+        let stdout = "\
+ -> No AUR package found for paru-binn
+ -> Could not find all required packages:
+    paru-binn (Target)
+";
+        assert_eq!(
+            PacmanError::parse(&output(stdout, "")),
+            Some(PacmanError::TargetNotFound(vec!["paru-binn".to_string()]))
+        );
+    }
+
+    #[test]
+    fn database_locked() {
+        let stderr = "\
+error: failed to init transaction (unable to lock database)
+error: could not lock database: File exists
+  if you're sure a package manager is not already
+  running, you can remove /var/lib/pacman/db.lck
+";
+        let error = PacmanError::parse(&output("", stderr)).unwrap();
+        assert_eq!(
+            error,
+            PacmanError::DatabaseLocked {
+                lock_file: "/var/lib/pacman/db.lck".to_string()
+            }
+        );
+        assert!(error.to_string().contains("delete /var/lib/pacman/db.lck"));
+    }
+
+    #[test]
+    fn signature_errors_are_keyring_problems() {
+        let stderr = "\
+error: foo: signature from \"Some Packager <packager@archlinux.org>\" is unknown trust
+:: File /var/cache/pacman/pkg/foo-1.0-1-x86_64.pkg.tar.zst is corrupted (invalid or corrupted package (PGP signature)).
+error: failed to commit transaction (invalid or corrupted package)
+";
+        assert_eq!(
+            PacmanError::parse(&output("", stderr)),
+            Some(PacmanError::Keyring(KeyringProblem::UnknownTrust))
+        );
+    }
+
+    #[test]
+    fn failed_downloads() {
+        let stderr = "\
+error: failed retrieving file 'foo-1.0-1-x86_64.pkg.tar.zst' from mirror.example.org : Operation too slow
+warning: failed to retrieve some files
+error: failed to commit transaction (failed to retrieve some files)
+";
+        assert_eq!(
+            PacmanError::parse(&output("", stderr)),
+            Some(PacmanError::DownloadFailed(vec![
+                "foo-1.0-1-x86_64.pkg.tar.zst".to_string()
+            ]))
+        );
+    }
+
+    #[test]
+    fn other_errors_and_no_errors() {
+        assert_eq!(
+            PacmanError::parse("error: no targets specified (use -h for help)\n"),
+            Some(PacmanError::Other(vec![
+                "error: no targets specified (use -h for help)".to_string()
+            ]))
+        );
+        assert_eq!(PacmanError::parse(" there is nothing to do\n"), None);
+    }
+}
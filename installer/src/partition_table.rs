@@ -21,6 +21,12 @@ pub struct PartitionTable {
     value_to_key: BTreeMap<String, String>,
 }
 
+impl Default for PartitionTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl PartitionTable {
     pub fn new() -> Self {
         PartitionTable {
@@ -35,7 +41,7 @@ impl PartitionTable {
         // Sort the vector based on the length of the keys
         // This is done so that nested mounts like /home/mount will be 
         // executed after /home is mounted
-        entries.sort_by(|a, b| a.0.len().cmp(&b.0.len()));
+        entries.sort_by_key(|a| a.0.len());
 
         entries.into_iter()
     }
@@ -49,13 +55,13 @@ impl PartitionTable {
     /// Key gets precedence
     pub fn remove(&mut self, key: Option<&str>, value: Option<&str>) -> Result<()> {
         if let Some(x) = key {
-             if let Ok(_) = self.remove_key(x) {
+             if self.remove_key(x).is_ok() {
                 return Ok(())
             }
         };
 
         if let Some(x) = value {
-             if let Ok(_) = self.remove_value(x) {
+             if self.remove_value(x).is_ok() {
                 return Ok(())
             }
         };
@@ -84,11 +90,7 @@ impl PartitionTable {
     }
 
     pub fn insert(&mut self, key: String, value: String) -> Result<()> {
-        let fmt_key = if key.starts_with('/') {
-            &key[1..]
-        } else {
-            &key[..]
-        };
+        let fmt_key = key.strip_prefix('/').unwrap_or(&key);
 
         if self.key_to_value.contains_key(fmt_key) || self.value_to_key.contains_key(&value) {
             Err(anyhow!(
@@ -0,0 +1,289 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use shell_iface::logger::Logger;
+
+use crate::{
+    base_installer::BaseInstaller,
+    engine::{OnFailure, Phase, Step, LOG_FILE},
+    essentials::Essentials,
+    filesystem_tasks::Filesystem,
+    install_packages,
+    pacman::{CacheSharing, Pacman},
+    secure_boot::SecureBootReport,
+    target::InstallTarget,
+    utils::{append_to_file, write_to_file, INSTALL_SUCCESS_FLAG},
+};
+
+/// The steps that use the passwords, they are left out of the checkpoint once these are done
+pub const SET_ROOT_PASSWORD: &str = "Set the root password";
+pub const CREATE_USERS: &str = "Create the users";
+
+/// What the TUI asks for that is not kept in the installers themselves.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InstallSettings {
+    pub timezone: String,
+    pub locale: String,
+    pub encoding: String,
+    /// Size of the swapfile in GB
+    pub swap_size: usize,
+    pub username: String,
+    pub password: String,
+    pub root_password: String,
+    pub hostname: String,
+}
+
+/// Everything the steps work on.
+pub struct InstallContext<'a, 'b> {
+    pub logger: &'a Logger,
+    pub filesystem: &'b mut Filesystem<'a>,
+    pub base_installer: &'b mut BaseInstaller<'a>,
+    pub essentials: &'b mut Essentials<'a>,
+    pub pacman: &'b mut Pacman<'a>,
+    pub settings: &'b InstallSettings,
+    pub target: InstallTarget,
+    pub secure_boot_report: Option<SecureBootReport>,
+}
+
+impl<'a, 'b> InstallContext<'a, 'b> {
+    pub fn new(
+        logger: &'a Logger,
+        filesystem: &'b mut Filesystem<'a>,
+        base_installer: &'b mut BaseInstaller<'a>,
+        essentials: &'b mut Essentials<'a>,
+        pacman: &'b mut Pacman<'a>,
+        settings: &'b InstallSettings,
+    ) -> InstallContext<'a, 'b> {
+        let target = filesystem.target.clone();
+        InstallContext {
+            logger,
+            filesystem,
+            base_installer,
+            essentials,
+            pacman,
+            settings,
+            target,
+            secure_boot_report: None,
+        }
+    }
+
+    fn is_container(&self) -> bool {
+        self.target.is_directory()
+    }
+
+    /// Opens a shell in the new system to fix a failed step by hand, run with sh -c.
+    /// Inside chroot the new system is / already. Before the base system is installed
+    /// there is nothing to chroot into, the shell opens in the live environment at its root.
+    pub fn shell_command(&self) -> String {
+        if self.essentials.in_chroot() {
+            return "cd / && exec bash".to_string();
+        }
+        let root = shell_words::quote(&self.target.root().to_string()).to_string();
+        if self.target.root().path("/usr/bin/bash").exists() {
+            format!("exec arch-chroot {} bash", root)
+        } else {
+            format!("cd {} && exec bash", root)
+        }
+    }
+
+    /// Where the installer is copied to, in the main user's home
+    pub fn installer_destination(&self) -> std::path::PathBuf {
+        self.target
+            .root()
+            .path(&format!("/home/{}/installer", self.settings.username))
+    }
+}
+
+pub type InstallStep<'a, 'b> = Step<InstallContext<'a, 'b>>;
+
+/// Every step of an install, in order.
+pub fn install_plan<'a, 'b>() -> Vec<InstallStep<'a, 'b>> {
+    vec![
+        /* Prepare */
+        InstallStep::new(Phase::Prepare, "Set up the installers", set_up_installers).replay(),
+        InstallStep::new(Phase::Prepare, "Configure pacman", |x| x.pacman.configure()).replay(),
+        // checked before touching the disks, a missing package would leave a half installed system
+        InstallStep::new(Phase::Prepare, "Check the offline packages", |x| {
+            x.pacman.prepare_offline()?;
+            x.pacman
+                .check_offline_packages(&install_packages(x.base_installer, x.essentials))
+        })
+        .when(|x| x.pacman.offline.is_some())
+        .replay(),
+        InstallStep::new(Phase::Prepare, "Pin the mirrorlist to the snapshot", |x| {
+            x.pacman.run_reflector()
+        })
+        .when(|x| x.pacman.offline.is_none() && x.pacman.snapshot.is_some())
+        .replay(),
+        // the live mirrorlist still works without it
+        InstallStep::new(Phase::Prepare, "Run reflector", |x| x.pacman.run_reflector())
+            .when(|x| x.pacman.offline.is_none() && x.pacman.snapshot.is_none())
+            .on_failure(OnFailure::Continue)
+            .replay(),
+        // pacstrap fails halfway with signature errors on an outdated keyring
+        InstallStep::new(Phase::Prepare, "Check the keyring", |x| {
+            let health = x.pacman.ensure_keyring()?;
+            x.logger.print(&health.to_string());
+            let _ = append_to_file(LOG_FILE, &format!("{}\n", health));
+            Ok(())
+        })
+        .replay(),
+        /* Disks */
+        // never again on resume, that would erase what was installed
+        InstallStep::new(Phase::Disks, "Format the partitions", |x| {
+            x.filesystem.format_partitions()
+        })
+        .when(|x| !x.is_container())
+        .requires(partitions_are_set),
+        InstallStep::new(Phase::Disks, "Mount the partitions", |x| {
+            x.filesystem.mount_partitions()
+        })
+        .when(|x| !x.is_container())
+        .requires(partitions_are_set)
+        .undo(|x| {
+            x.filesystem.try_unmount();
+            Ok(())
+        })
+        .replay(),
+        /* Base */
+        InstallStep::new(Phase::Base, "Copy the package cache", |x| {
+            x.base_installer.copy_host_cache()
+        })
+        .when(|x| x.pacman.cache_sharing == CacheSharing::Copy),
+        // offline packages are already on the disk
+        InstallStep::new(Phase::Base, "Download the packages", |x| {
+            let packages = install_packages(x.base_installer, x.essentials);
+            x.pacman.prefetch(x.target.root(), &packages)
+        })
+        .when(|x| x.pacman.prefetch && x.pacman.offline.is_none()),
+        InstallStep::new(Phase::Base, "Install the base system", |x| {
+            x.base_installer.base_packages_install()
+        }),
+        // containers get their mounts from the container manager
+        InstallStep::new(Phase::Base, "Generate fstab", |x| x.base_installer.genfstab())
+            .when(|x| !x.is_container()),
+        /* System */
+        InstallStep::new(Phase::System, "Enter chroot", |x| x.essentials.chroot())
+            .undo(|x| x.essentials.exit_chroot())
+            .replay(),
+        InstallStep::new(Phase::System, "Create the swapfile", |x| {
+            x.essentials.initialize_swap(x.settings.swap_size)
+        })
+        .when(|x| !x.is_container())
+        .done(|_| Path::new("/swapfile").exists()),
+        InstallStep::new(Phase::System, "Set the timezone", |x| {
+            x.essentials.set_timezones(&x.settings.timezone)
+        })
+        .requires(|x| {
+            if x.settings.timezone.is_empty() {
+                return Err(anyhow!("No timezone was picked"));
+            }
+            Ok(())
+        }),
+        InstallStep::new(Phase::System, "Generate the locale", |x| {
+            x.essentials
+                .gen_locale(&x.settings.locale, &x.settings.encoding)
+        })
+        .requires(|x| {
+            if x.settings.locale.is_empty() {
+                return Err(anyhow!("No locale was picked"));
+            }
+            Ok(())
+        }),
+        InstallStep::new(Phase::System, "Set the hostname", |x| {
+            x.essentials.set_hostname(&x.settings.hostname)
+        }),
+        InstallStep::new(Phase::System, SET_ROOT_PASSWORD, |x| {
+            x.essentials.set_password("root", &x.settings.root_password)
+        }),
+        InstallStep::new(Phase::System, "Configure pacman in the new system", |x| {
+            x.essentials.configure_pacman()
+        }),
+        InstallStep::new(Phase::System, "Install the essential packages", |x| {
+            x.essentials.install_essentials(None)?;
+            x.essentials.configure_reflector()
+        }),
+        InstallStep::new(Phase::System, "Install the bootloader", |x| {
+            x.essentials.install_bootloader()?;
+            x.essentials.mkinitcpio()
+        })
+        .when(|x| !x.is_container()),
+        InstallStep::new(Phase::System, "Set up Secure Boot", |x| {
+            x.secure_boot_report = x.essentials.setup_secure_boot()?;
+            Ok(())
+        })
+        .when(|x| !x.is_container() && x.essentials.secure_boot.is_some()),
+        InstallStep::new(Phase::System, CREATE_USERS, |x| {
+            x.essentials
+                .user_management(&x.settings.username, &x.settings.password)?;
+            for user in x.essentials.extra_users.clone() {
+                x.essentials.add_user(&user.name, &user.password)?;
+            }
+            Ok(())
+        }),
+        InstallStep::new(Phase::System, "Run the hooks", |x| x.essentials.run_hooks())
+            .when(|x| !x.essentials.hooks.is_empty()),
+        InstallStep::new(Phase::System, "Unpin the mirrorlist", |x| {
+            x.essentials.unpin_mirrorlist()
+        }),
+        InstallStep::new(Phase::System, "Leave chroot", |x| x.essentials.exit_chroot()).replay(),
+        /* Finish */
+        InstallStep::new(Phase::Finish, "Mark the install as done", |x| {
+            write_to_file(x.target.root().path(INSTALL_SUCCESS_FLAG), "true")
+        }),
+        // the system works without it, post install can be copied over by hand
+        InstallStep::new(Phase::Finish, "Copy the installer", copy_installer)
+            .on_failure(OnFailure::Continue),
+        InstallStep::new(Phase::Finish, "Pack the rootfs", |x| x.filesystem.pack_tarball())
+            .when(|x| x.target.tarball().is_some()),
+    ]
+}
+
+fn set_up_installers(context: &mut InstallContext) -> Result<()> {
+    let target = context.target.clone();
+    let pacman = &context.pacman;
+    let base_installer = &mut context.base_installer;
+    let essentials = &mut context.essentials;
+
+    base_installer.set_target(&target);
+    base_installer.set_kernels(&essentials.kernels);
+    base_installer.set_copy_host_keyring(
+        pacman.options.repos.iter().any(|x| x.key_file.is_some()),
+    );
+    essentials.set_pacman_options(&pacman.options);
+    essentials.set_target(&target);
+    base_installer.set_offline(pacman.offline.as_ref());
+    essentials.set_offline(pacman.offline.as_ref());
+    base_installer.set_cache_sharing(pacman.cache_sharing);
+    essentials.set_cache_sharing(pacman.cache_sharing);
+    essentials.set_snapshot(pacman.snapshot.as_ref());
+    essentials.set_mirrors(&pacman.mirrors);
+    Ok(())
+}
+
+fn partitions_are_set(context: &InstallContext) -> Result<()> {
+    match (context.filesystem.get_boot(), context.filesystem.get_root()) {
+        (Some(_), Some(_)) => Ok(()),
+        _ => Err(anyhow!("Boot or root is not set")),
+    }
+}
+
+fn copy_installer(context: &mut InstallContext) -> Result<()> {
+    let destination = context.installer_destination();
+    let executable = std::env::args()
+        .next()
+        .ok_or(anyhow!("Failed to get the executable name from arguments."))?;
+    std::fs::copy(executable, &destination).map_err(|e| {
+        anyhow!(
+            "{}. Please copy the file manually to {}",
+            e,
+            destination.display()
+        )
+    })?;
+    context.logger.print(&format!(
+        "Successfully copied the executable to {}.",
+        destination.display()
+    ));
+    Ok(())
+}
@@ -1,7 +1,14 @@
-use std::fs;
-
-use crate::{pacman::Pacman, utils::{sed, RICE_SCRIPT_URL}};
-use anyhow::Result;
+use std::{fmt::Display, fs};
+
+use crate::{
+    pacman::Pacman,
+    pacman_conf::edit_pacman_conf,
+    sync_db::{check_names, PackageStatus, SyncDb, SYNC_DIR},
+    target::TargetRoot,
+    target_fs::RootFs,
+    utils::RICE_SCRIPT_URL,
+};
+use anyhow::{anyhow, Result};
 use shell_iface::{logger::Logger, Shell};
 
 /// Preferred GUI
@@ -11,6 +18,39 @@ pub enum DesktopEnvironment {
     Hyprland,
 }
 
+impl DesktopEnvironment {
+    pub fn all() -> [DesktopEnvironment; 3] {
+        [
+            DesktopEnvironment::Gnome,
+            DesktopEnvironment::KDE,
+            DesktopEnvironment::Hyprland,
+        ]
+    }
+
+    pub fn packages(&self) -> Vec<&'static str> {
+        match self {
+            DesktopEnvironment::Gnome => vec!["gnome", "gnome-extra"],
+            DesktopEnvironment::KDE => vec!["plasma", "kde-applications-meta"],
+            DesktopEnvironment::Hyprland => vec!["hyprland-git", "hyprpaper"],
+        }
+    }
+
+    /// Installed with yay instead of pacman
+    pub fn is_aur(&self) -> bool {
+        matches!(self, DesktopEnvironment::Hyprland)
+    }
+}
+
+impl Display for DesktopEnvironment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DesktopEnvironment::Gnome => write!(f, "Gnome"),
+            DesktopEnvironment::KDE => write!(f, "KDE Plasma"),
+            DesktopEnvironment::Hyprland => write!(f, "Hyprland"),
+        }
+    }
+}
+
 /// PostInstall installs optional stuff.
 /// This is same as the postinstall.sh
 /// Calling PostInstall functions without the process running as superuser will fail.
@@ -34,10 +74,10 @@ impl<'a> PostInstall<'a> {
     }
 
     /// reads from a file and installs all the packages.
-    /// expects valid files without errors or invalid packages
     /// packages file uses pacman to install.
     /// aur packages file uses yay to install
     /// A valid file contains valid package names separated by a newline only
+    /// Every name is checked before anything is installed, see check_packages.
     pub fn install_additionals(
         &mut self,
         packages_file: &str,
@@ -46,7 +86,12 @@ impl<'a> PostInstall<'a> {
         self.shell.log("Installing packages:");
         self.shell.log("Parsing files");
         let parsed_file = fs::read_to_string(packages_file.trim())?;
-        let packages = parsed_file.split("\n").filter(|x| !x.is_empty()).collect::<Vec<&str>>();
+        let packages = parsed_file.split("\n").map(|x| x.trim()).filter(|x| !x.is_empty()).collect::<Vec<&str>>();
+        let parsed_aur_file = fs::read_to_string(aur_packages_file.trim())?;
+        let aur_packages = parsed_aur_file.split("\n").map(|x| x.trim()).filter(|x| !x.is_empty()).collect::<Vec<&str>>();
+
+        self.check_packages(&packages, &aur_packages)?;
+
         self.shell.log(&format!(
             "Installing packages with pacman: {}",
             parsed_file
@@ -60,14 +105,63 @@ impl<'a> PostInstall<'a> {
         }
 
         self.shell.log("Installing yay");
-        let parsed_file = fs::read_to_string(aur_packages_file.trim())?;
-        let aur_packages = parsed_file.split("\n").filter(|x| !x.is_empty()).collect::<Vec<&str>>();
         self.shell
-            .log(&format!("Installing packages with aur: {}", parsed_file));
+            .log(&format!("Installing packages with aur: {}", parsed_aur_file));
         self.pacman.yay().install(aur_packages)?;
         Ok(())
     }
 
+    /// Checks the names against the sync databases, and the AUR for those not in them.
+    /// Fails with every problem found, eg. typos with the closest names, so nothing is left
+    /// half installed. Names in the AUR packages file may be in the repositories too, yay
+    /// installs both.
+    pub fn check_packages(&mut self, packages: &[&str], aur_packages: &[&str]) -> Result<()> {
+        self.shell.log("Checking the package names.");
+        let db = SyncDb::load(&mut self.shell, SYNC_DIR)?;
+        let names = packages
+            .iter()
+            .chain(aur_packages.iter())
+            .copied()
+            .collect::<Vec<&str>>();
+        let (statuses, aur_error) = check_names(&mut self.shell, &db, &names);
+        if let Some(e) = &aur_error {
+            self.shell
+                .log(&format!("Could not check the names against the AUR: {}", e));
+        }
+
+        let mut problems = Vec::new();
+        for (name, is_aur_file) in packages
+            .iter()
+            .map(|x| (x, false))
+            .chain(aur_packages.iter().map(|x| (x, true)))
+        {
+            match &statuses[*name] {
+                PackageStatus::Aur if !is_aur_file => problems.push(format!(
+                    "{} is in the AUR, move it to the AUR packages file",
+                    name
+                )),
+                // without the AUR, the name might still be fine
+                PackageStatus::Unknown { .. } if is_aur_file && aur_error.is_some() => {}
+                PackageStatus::Unknown { suggestions } if suggestions.is_empty() => {
+                    problems.push(format!("{} was not found", name))
+                }
+                PackageStatus::Unknown { suggestions } => problems.push(format!(
+                    "{} was not found, did you mean {}?",
+                    name,
+                    suggestions.join(", ")
+                )),
+                _ => {}
+            }
+        }
+
+        if problems.is_empty() {
+            return Ok(());
+        }
+        self.shell
+            .log(&format!("Invalid package names: {}", problems.join("; ")));
+        Err(anyhow!("Invalid package names:\n{}", problems.join("\n")))
+    }
+
     pub fn setup_yay(&mut self) -> Result<()> {
         match self.shell.run_and_wait_with_args("rm", "-fr yay") {
             Ok(_) => {
@@ -98,25 +192,11 @@ impl<'a> PostInstall<'a> {
     pub fn install_desktop(&mut self, de: DesktopEnvironment) -> Result<()> {
         self.shell.log("Installing desktop environment");
 
-        match de {
-            DesktopEnvironment::Gnome => {
-                self.shell.log("Installing gnome");
-                self.pacman
-                    .pacman()
-                    .install(vec![&"gnome", &"gnome-extra"])?;
-            }
-            DesktopEnvironment::KDE => {
-                self.shell.log("Installing kde");
-                self.pacman
-                    .pacman()
-                    .install(vec![&"plasma", &"kde-applications-meta"])?;
-            }
-            DesktopEnvironment::Hyprland => {
-                self.shell.log("Installing hyprland");
-                self.pacman
-                    .yay()
-                    .install(vec![&"hyprland-git", &"hyprpaper"])?;
-            }
+        self.shell.log(&format!("Installing {}", de));
+        if de.is_aur() {
+            self.pacman.yay().install(de.packages())?;
+        } else {
+            self.pacman.pacman().install(de.packages())?;
         }
 
         Ok(())
@@ -127,8 +207,12 @@ impl<'a> PostInstall<'a> {
     pub fn misc_options(&mut self) -> Result<()> {
         self.shell.log("Running ParaPsychic specific settings...");
         self.shell.log("Setting up pacman in style");
-        sed("/etc/pacman.conf", 33, "ILoveCandy")?;
-        sed("/etc/pacman.conf", 34, "Color")?;
+        edit_pacman_conf(&mut RootFs::new(TargetRoot::host()), |conf| {
+            conf.set_option("Color", None);
+            conf.set_option_after("ILoveCandy", None, Some("Color"));
+            // the rice script wants multilib
+            conf.enable_repo("multilib")
+        })?;
 
         self.shell.log("Downloading ricing scripts...");
         self.shell.run_and_wait_with_args("curl", &format!("{} -o rice", RICE_SCRIPT_URL))?;
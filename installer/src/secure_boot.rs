@@ -0,0 +1,69 @@
+use std::fmt::Display;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// Secure Boot is set up with sbctl.
+/// Follows [this](https://wiki.archlinux.org/title/Unified_Extensible_Firmware_Interface/Secure_Boot#Assisted_process_with_sbctl)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SecureBoot {
+    /// Enrolls Microsoft's keys next to our own.
+    /// Some firmware and GPU option ROMs are signed only by Microsoft and won't load without them.
+    pub microsoft_keys: bool,
+}
+
+/// What `sbctl status --json` says about the firmware
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct SbctlStatus {
+    /// Whether the keys have been created
+    pub installed: bool,
+    pub setup_mode: bool,
+    pub secure_boot: bool,
+}
+
+impl SbctlStatus {
+    pub fn parse(json: &str) -> Result<SbctlStatus> {
+        Ok(serde_json::from_str(json)?)
+    }
+}
+
+/// The outcome of setting up Secure Boot, shown to the user at the end of the install.
+#[derive(Debug, Default)]
+pub struct SecureBootReport {
+    pub setup_mode: bool,
+    pub keys_enrolled: bool,
+    pub microsoft_keys: bool,
+    /// Every file signed, relative to the new system's /
+    pub signed: Vec<String>,
+}
+
+impl Display for SecureBootReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Secure Boot:")?;
+        writeln!(f, "Signed {} files:", self.signed.len())?;
+        for file in &self.signed {
+            writeln!(f, "  {}", file)?;
+        }
+
+        if self.keys_enrolled {
+            writeln!(
+                f,
+                "Keys enrolled{}. Turn Secure Boot on in the firmware settings after rebooting.",
+                if self.microsoft_keys {
+                    " along with Microsoft's keys"
+                } else {
+                    ""
+                }
+            )
+        } else {
+            writeln!(
+                f,
+                "Keys were NOT enrolled, the firmware is not in setup mode.
+Clear the Secure Boot keys (or select \"Reset to Setup Mode\") in the firmware settings,
+boot into the installed system and run `sbctl enroll-keys{}`.",
+                if self.microsoft_keys { " -m" } else { "" }
+            )
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use crate::sync_db::PackageSize;
+
+const MIB: u64 = 1024 * 1024;
+const GIB: u64 = 1024 * MIB;
+
+/// How big the install will be, from the sync databases, against the room the new system has.
+#[derive(Debug, Clone, Default)]
+pub struct SizeEstimate {
+    /// eg. base, kernel, essentials. Each only counts what the ones before did not.
+    pub sets: Vec<(String, PackageSize)>,
+    /// Desktops post install offers, each on top of all the sets
+    pub desktops: Vec<(String, PackageSize)>,
+    /// Bytes of the swapfile on /
+    pub swap: u64,
+    /// The downloads stay in the new system's package cache
+    pub cache_on_target: bool,
+    /// What / is on, eg. "/dev/sda2", and the bytes it can hold. None if unknown.
+    pub root_space: Option<(String, u64)>,
+}
+
+impl SizeEstimate {
+    pub fn download_size(&self) -> u64 {
+        self.sets.iter().map(|(_, x)| x.download_size).sum()
+    }
+
+    pub fn installed_size(&self) -> u64 {
+        self.sets.iter().map(|(_, x)| x.installed_size).sum()
+    }
+
+    /// Bytes the install takes on /, the swapfile and package cache included
+    pub fn required(&self) -> u64 {
+        let cache = if self.cache_on_target {
+            self.download_size()
+        } else {
+            0
+        };
+        self.installed_size() + self.swap + cache
+    }
+
+    /// None if the room on / is unknown
+    pub fn fits(&self) -> Option<bool> {
+        self.root_space
+            .as_ref()
+            .map(|(_, space)| self.required() <= *space)
+    }
+
+    /// Problems to show before the install starts, the most serious first.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if let (Some(false), Some((root, space))) = (self.fits(), &self.root_space) {
+            warnings.push(format!(
+                "WILL NOT FIT: the install needs {} but {} has {}",
+                format_size(self.required()),
+                root,
+                format_size(*space)
+            ));
+        }
+
+        if let Some((root, space)) = &self.root_space {
+            let left = space.saturating_sub(self.required());
+            for (name, size) in &self.desktops {
+                if size.installed_size > left {
+                    warnings.push(format!(
+                        "{} needs {} more, {} would only have {} left",
+                        name,
+                        format_size(size.installed_size),
+                        root,
+                        format_size(left)
+                    ));
+                }
+            }
+        }
+
+        let missing = self
+            .sets
+            .iter()
+            .flat_map(|(_, x)| x.missing.iter().cloned())
+            .collect::<Vec<String>>();
+        if !missing.is_empty() {
+            warnings.push(format!(
+                "Not in the sync databases, left out of the estimate: {}",
+                missing.join(", ")
+            ));
+        }
+        warnings
+    }
+}
+
+/// eg. "1.5 GiB" or "320 MiB"
+pub fn format_size(bytes: u64) -> String {
+    if bytes >= GIB {
+        format!("{:.1} GiB", bytes as f64 / GIB as f64)
+    } else {
+        format!("{} MiB", bytes / MIB)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn size(download_size: u64, installed_size: u64) -> PackageSize {
+        PackageSize {
+            packages: 1,
+            download_size,
+            installed_size,
+            missing: Vec::new(),
+        }
+    }
+
+    /// 1 GiB to download, 3 GiB installed, 2 GiB of swap
+    fn estimate() -> SizeEstimate {
+        SizeEstimate {
+            sets: vec![
+                ("base".to_string(), size(512 * MIB, 2 * GIB)),
+                ("essentials".to_string(), size(512 * MIB, GIB)),
+            ],
+            swap: 2 * GIB,
+            ..SizeEstimate::default()
+        }
+    }
+
+    #[test]
+    fn required_counts_swap_and_the_cache_on_target() {
+        let mut estimate = estimate();
+        assert_eq!(estimate.download_size(), GIB);
+        assert_eq!(estimate.required(), 5 * GIB);
+
+        estimate.cache_on_target = true;
+        assert_eq!(estimate.required(), 6 * GIB);
+    }
+
+    #[test]
+    fn unknown_space_gives_no_warning() {
+        let mut estimate = estimate();
+        estimate.desktops = vec![("KDE Plasma".to_string(), size(GIB, 4 * GIB))];
+        assert_eq!(estimate.fits(), None);
+        assert!(estimate.warnings().is_empty());
+    }
+
+    #[test]
+    fn warns_when_it_does_not_fit() {
+        let mut estimate = estimate();
+        estimate.root_space = Some(("/dev/sda2".to_string(), 5 * GIB));
+        assert_eq!(estimate.fits(), Some(true));
+        assert!(estimate.warnings().is_empty());
+
+        estimate.cache_on_target = true;
+        assert_eq!(estimate.fits(), Some(false));
+        assert_eq!(
+            estimate.warnings(),
+            ["WILL NOT FIT: the install needs 6.0 GiB but /dev/sda2 has 5.0 GiB"]
+        );
+    }
+
+    #[test]
+    fn warns_about_desktops_bigger_than_what_is_left() {
+        let mut estimate = estimate();
+        estimate.root_space = Some(("/dev/sda2".to_string(), 8 * GIB));
+        estimate.desktops = vec![
+            ("Xfce".to_string(), size(256 * MIB, 768 * MIB)),
+            ("KDE Plasma".to_string(), size(GIB, 4 * GIB)),
+        ];
+        assert_eq!(
+            estimate.warnings(),
+            ["KDE Plasma needs 4.0 GiB more, /dev/sda2 would only have 3.0 GiB left"]
+        );
+    }
+
+    #[test]
+    fn lists_the_missing_names_last() {
+        let mut estimate = estimate();
+        estimate.root_space = Some(("/dev/sda2".to_string(), GIB));
+        estimate.sets[0].1.missing = vec!["linux-foo".to_string()];
+        estimate.sets[1].1.missing = vec!["yay".to_string()];
+        let warnings = estimate.warnings();
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].starts_with("WILL NOT FIT"));
+        assert_eq!(
+            warnings[1],
+            "Not in the sync databases, left out of the estimate: linux-foo, yay"
+        );
+        assert_eq!(format_size(320 * MIB), "320 MiB");
+    }
+}
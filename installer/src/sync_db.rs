@@ -0,0 +1,530 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::Read,
+    path::Path,
+};
+
+use anyhow::{anyhow, Result};
+use flate2::read::GzDecoder;
+use shell_iface::Shell;
+
+/// Where pacman -Sy puts the repositories' databases
+pub const SYNC_DIR: &str = "/var/lib/pacman/sync";
+pub const AUR_RPC_URL: &str = "https://aur.archlinux.org/rpc/v5/info";
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+/// Names per AUR request, the URL gets too long otherwise
+const AUR_CHUNK: usize = 100;
+const MAX_SUGGESTIONS: usize = 3;
+
+/// A package as the sync database describes it, only what name checks and size
+/// estimates need.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncPackage {
+    pub name: String,
+    pub repo: String,
+    pub groups: Vec<String>,
+    /// Without versions, eg. "sh" for bash
+    pub provides: Vec<String>,
+    /// Without versions, eg. "glibc" for glibc>=2.38
+    pub depends: Vec<String>,
+    /// Bytes to download, %CSIZE%
+    pub download_size: u64,
+    /// Bytes on the disk once installed, %ISIZE%
+    pub installed_size: u64,
+}
+
+/// What a set of packages adds up to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PackageSize {
+    pub packages: usize,
+    pub download_size: u64,
+    pub installed_size: u64,
+    /// Names neither a package, a group nor provided by one
+    pub missing: Vec<String>,
+}
+
+/// What a name given to pacman or yay refers to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PackageStatus {
+    Package { repo: String },
+    Group,
+    /// Not a package of its own, pacman picks one of these
+    Provided { by: Vec<String> },
+    /// Not in the repositories, but in the AUR
+    Aur,
+    /// Nowhere to be found, with the closest names in the repositories
+    Unknown { suggestions: Vec<String> },
+}
+
+/// The packages and groups in the repositories pacman knows of,
+/// read straight from the sync databases so no network is needed.
+#[derive(Debug, Default)]
+pub struct SyncDb {
+    pub packages: Vec<SyncPackage>,
+}
+
+impl SyncDb {
+    /// Reads every database in the directory, eg. SYNC_DIR.
+    pub fn load(shell: &mut Shell, dir: &str) -> Result<SyncDb> {
+        let mut databases = fs::read_dir(dir)
+            .map_err(|e| anyhow!("Could not read {}: {}", dir, e))?
+            .filter_map(|x| x.ok())
+            .map(|x| x.path())
+            .filter(|x| x.extension().is_some_and(|x| x == "db"))
+            .collect::<Vec<_>>();
+        databases.sort();
+        if databases.is_empty() {
+            return Err(anyhow!(
+                "There are no databases in {}, run `pacman -Sy` first",
+                dir
+            ));
+        }
+
+        let mut db = SyncDb::default();
+        for path in databases {
+            let repo = match path.file_stem() {
+                Some(x) => x.to_string_lossy().to_string(),
+                None => continue,
+            };
+            let archive = decompress(shell, &path)?;
+            db.packages.extend(read_archive(&repo, &archive)?);
+        }
+        Ok(db)
+    }
+
+    /// Looks the name up in the repositories only. Names can have the repository, eg. extra/vim.
+    pub fn check(&self, name: &str) -> PackageStatus {
+        let (repo, name) = match name.split_once('/') {
+            Some((repo, name)) => (Some(repo), name),
+            None => (None, name),
+        };
+        let in_repo = |x: &&SyncPackage| repo.is_none_or(|repo| x.repo == repo);
+
+        if let Some(package) = self.packages.iter().filter(in_repo).find(|x| x.name == name) {
+            return PackageStatus::Package {
+                repo: package.repo.clone(),
+            };
+        }
+        if self
+            .packages
+            .iter()
+            .filter(in_repo)
+            .any(|x| x.groups.iter().any(|x| x == name))
+        {
+            return PackageStatus::Group;
+        }
+        let providers = self
+            .packages
+            .iter()
+            .filter(in_repo)
+            .filter(|x| x.provides.iter().any(|x| x == name))
+            .map(|x| x.name.clone())
+            .collect::<Vec<String>>();
+        if !providers.is_empty() {
+            return PackageStatus::Provided { by: providers };
+        }
+
+        PackageStatus::Unknown {
+            suggestions: self.suggestions(name),
+        }
+    }
+
+    /// Package and group names close to the misspelled one, closest first.
+    pub fn suggestions(&self, name: &str) -> Vec<String> {
+        let max_distance = (name.chars().count() / 3).max(1);
+        let mut names = self
+            .packages
+            .iter()
+            .flat_map(|x| std::iter::once(&x.name).chain(x.groups.iter()))
+            .collect::<HashSet<&String>>()
+            .into_iter()
+            .map(|x| (edit_distance(name, x), x))
+            .filter(|(distance, _)| *distance <= max_distance)
+            .collect::<Vec<(usize, &String)>>();
+        names.sort();
+        names
+            .into_iter()
+            .take(MAX_SUGGESTIONS)
+            .map(|(_, x)| x.to_string())
+            .collect()
+    }
+
+    /// Size of the packages with their dependencies, groups expanded.
+    /// Packages in counted are left out and the new ones are added to it,
+    /// so sets installed one after the other do not count shared dependencies twice.
+    pub fn size(&self, targets: &[String], counted: &mut HashSet<String>) -> PackageSize {
+        let mut size = PackageSize::default();
+        let mut queue = targets.to_vec();
+        while let Some(name) = queue.pop() {
+            let name = name.split_once('/').map(|(_, x)| x).unwrap_or(&name);
+            if counted.contains(name) {
+                continue;
+            }
+            let packages = match self.resolve(name) {
+                Some(x) => x,
+                None => {
+                    size.missing.push(name.to_string());
+                    continue;
+                }
+            };
+            for package in packages {
+                if !counted.insert(package.name.clone()) {
+                    continue;
+                }
+                size.packages += 1;
+                size.download_size += package.download_size;
+                size.installed_size += package.installed_size;
+                queue.extend(package.depends.iter().cloned());
+            }
+        }
+        size
+    }
+
+    /// The package by that name, the members of the group, or the first package providing it
+    fn resolve(&self, name: &str) -> Option<Vec<&SyncPackage>> {
+        if let Some(package) = self.packages.iter().find(|x| x.name == name) {
+            return Some(vec![package]);
+        }
+        let members = self
+            .packages
+            .iter()
+            .filter(|x| x.groups.iter().any(|x| x == name))
+            .collect::<Vec<&SyncPackage>>();
+        if !members.is_empty() {
+            return Some(members);
+        }
+        self.packages
+            .iter()
+            .find(|x| x.provides.iter().any(|x| x == name))
+            .map(|x| vec![x])
+    }
+}
+
+/// Checks names against the repositories, then the ones not found there against the AUR.
+/// The AUR check needs network; without it those names stay Unknown and an error is returned
+/// alongside, so the caller can decide whether that is fatal.
+pub fn check_names(
+    shell: &mut Shell,
+    db: &SyncDb,
+    names: &[&str],
+) -> (HashMap<String, PackageStatus>, Option<anyhow::Error>) {
+    let mut statuses = names
+        .iter()
+        .map(|x| (x.to_string(), db.check(x)))
+        .collect::<HashMap<String, PackageStatus>>();
+
+    let unknown = statuses
+        .iter()
+        .filter(|(_, x)| matches!(x, PackageStatus::Unknown { .. }))
+        .map(|(x, _)| x.clone())
+        .collect::<Vec<String>>();
+    if unknown.is_empty() {
+        return (statuses, None);
+    }
+
+    match aur_packages(shell, &unknown) {
+        Ok(found) => {
+            for name in found {
+                statuses.insert(name, PackageStatus::Aur);
+            }
+            (statuses, None)
+        }
+        Err(e) => (statuses, Some(e)),
+    }
+}
+
+/// The names that are packages in the AUR, asked from its RPC interface.
+pub fn aur_packages(shell: &mut Shell, names: &[String]) -> Result<HashSet<String>> {
+    let mut found = HashSet::new();
+    for chunk in names.chunks(AUR_CHUNK) {
+        let query = chunk
+            .iter()
+            .map(|x| format!("arg[]={}", url_encode(x)))
+            .collect::<Vec<String>>()
+            .join("&");
+        // -g, the brackets in arg[] are not a glob
+        let output = shell.run_with_args("curl", &format!("-sfg \"{}?{}\"", AUR_RPC_URL, query))?;
+        let response: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+        let results = response["results"]
+            .as_array()
+            .ok_or(anyhow!("Unexpected response from the AUR"))?;
+        found.extend(
+            results
+                .iter()
+                .filter_map(|x| x["Name"].as_str())
+                .map(|x| x.to_string()),
+        );
+    }
+    Ok(found)
+}
+
+/// The databases are tar archives, compressed with gzip (the default of repo-add) or zstd.
+fn decompress(shell: &mut Shell, path: &Path) -> Result<Vec<u8>> {
+    let bytes = fs::read(path)?;
+    if bytes.starts_with(&GZIP_MAGIC) {
+        let mut archive = Vec::new();
+        GzDecoder::new(bytes.as_slice()).read_to_end(&mut archive)?;
+        Ok(archive)
+    } else if bytes.starts_with(&ZSTD_MAGIC) {
+        Ok(shell
+            .run_with_args("zstd", &format!("-dcq \"{}\"", path.display()))?
+            .stdout)
+    } else {
+        Ok(bytes)
+    }
+}
+
+/// Every package directory in the archive has a desc file, eg. vim-9.1.0-1/desc
+fn read_archive(repo: &str, archive: &[u8]) -> Result<Vec<SyncPackage>> {
+    let mut packages = Vec::new();
+    for entry in tar::Archive::new(archive).entries()? {
+        let mut entry = entry?;
+        if !entry.path()?.ends_with("desc") {
+            continue;
+        }
+        let mut desc = String::new();
+        entry.read_to_string(&mut desc)?;
+        if let Some(package) = parse_desc(repo, &desc) {
+            packages.push(package);
+        }
+    }
+    Ok(packages)
+}
+
+/// Sections are a %KEY% line followed by a value per line, eg.
+/// %NAME%
+/// vim
+fn parse_desc(repo: &str, desc: &str) -> Option<SyncPackage> {
+    let mut package = SyncPackage {
+        repo: repo.to_string(),
+        ..Default::default()
+    };
+    let mut key = "";
+    for line in desc.lines().map(|x| x.trim()) {
+        if line.starts_with('%') && line.ends_with('%') {
+            key = line;
+            continue;
+        }
+        if line.is_empty() {
+            continue;
+        }
+        match key {
+            "%NAME%" => package.name = line.to_string(),
+            "%GROUPS%" => package.groups.push(line.to_string()),
+            "%PROVIDES%" => package.provides.push(without_version(line)),
+            "%DEPENDS%" => package.depends.push(without_version(line)),
+            "%CSIZE%" => package.download_size = line.parse().unwrap_or(0),
+            "%ISIZE%" => package.installed_size = line.parse().unwrap_or(0),
+            _ => {}
+        }
+    }
+
+    if package.name.is_empty() {
+        None
+    } else {
+        Some(package)
+    }
+}
+
+/// eg. libfoo.so=1-64 or glibc>=2.38
+fn without_version(line: &str) -> String {
+    line.split(['=', '<', '>']).next().unwrap_or(line).to_string()
+}
+
+/// Levenshtein distance
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<char>>();
+    let mut previous = (0..=b.len()).collect::<Vec<usize>>();
+    for (i, x) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, y) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(x != *y);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Package names are mostly safe in a URL, but can have a +
+fn url_encode(name: &str) -> String {
+    name.chars()
+        .map(|x| match x {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' | '~' => x.to_string(),
+            _ => format!("%{:02X}", x as u32),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+    use shell_iface::logger::Logger;
+
+    const VIM: &str = "\
+%FILENAME%
+vim-9.1.0-1-x86_64.pkg.tar.zst
+
+%NAME%
+vim
+
+%CSIZE%
+2000
+
+%ISIZE%
+5000
+
+%DEPENDS%
+glibc>=2.38
+vim-runtime=9.1.0-1
+libxcrypt
+
+%PROVIDES%
+xxd
+";
+    const VIM_RUNTIME: &str = "%NAME%\nvim-runtime\n\n%CSIZE%\n700\n\n%ISIZE%\n3000\n";
+    const GLIBC: &str = "%NAME%\nglibc\n\n%CSIZE%\n10\n\n%ISIZE%\n100\n";
+    const LIBXCRYPT: &str = "%NAME%\nlibxcrypt\n\n%DEPENDS%\nglibc\n\n%CSIZE%\n1\n\n%ISIZE%\n2\n";
+    const BASE_DEVEL: &str = "%NAME%\nmake\n\n%GROUPS%\nbase-devel\n\n%CSIZE%\n5\n\n%ISIZE%\n6\n";
+
+    /// A database the way repo-add makes it, a gzipped tar of one directory per package
+    fn database(packages: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        for (dir, desc) in packages {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(desc.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, format!("{}/desc", dir), desc.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    fn core() -> SyncDb {
+        let archive = database(&[
+            ("vim-9.1.0-1", VIM),
+            ("vim-runtime-9.1.0-1", VIM_RUNTIME),
+            ("glibc-2.39-1", GLIBC),
+            ("libxcrypt-4.4.36-1", LIBXCRYPT),
+            ("make-4.4.1-2", BASE_DEVEL),
+        ]);
+        let mut decompressed = Vec::new();
+        GzDecoder::new(archive.as_slice())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        SyncDb {
+            packages: read_archive("core", &decompressed).unwrap(),
+        }
+    }
+
+    #[test]
+    fn parses_desc() {
+        assert_eq!(
+            parse_desc("extra", VIM),
+            Some(SyncPackage {
+                name: "vim".to_string(),
+                repo: "extra".to_string(),
+                groups: Vec::new(),
+                provides: vec!["xxd".to_string()],
+                depends: vec![
+                    "glibc".to_string(),
+                    "vim-runtime".to_string(),
+                    "libxcrypt".to_string()
+                ],
+                download_size: 2000,
+                installed_size: 5000,
+            })
+        );
+        assert_eq!(parse_desc("extra", "%FILENAME%\nfoo.pkg.tar.zst\n"), None);
+    }
+
+    #[test]
+    fn loads_the_databases_in_a_directory() {
+        let dir = std::env::temp_dir().join(format!("2lazy4arch-sync-db-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("core.db"), database(&[("glibc-2.39-1", GLIBC)])).unwrap();
+        fs::write(dir.join("extra.db"), database(&[("vim-9.1.0-1", VIM)])).unwrap();
+        fs::write(dir.join("extra.db.sig"), "not a database").unwrap();
+
+        let logger = Logger::new(false);
+        let mut shell = Shell::new("SyncDb", &logger);
+        let db = SyncDb::load(&mut shell, &dir.to_string_lossy());
+        let empty = SyncDb::load(&mut shell, "/nonexistent");
+        fs::remove_dir_all(&dir).unwrap();
+
+        let db = db.unwrap();
+        assert_eq!(
+            db.packages
+                .iter()
+                .map(|x| (x.repo.as_str(), x.name.as_str()))
+                .collect::<Vec<_>>(),
+            [("core", "glibc"), ("extra", "vim")]
+        );
+        assert!(empty.is_err());
+    }
+
+    #[test]
+    fn checks_names() {
+        let db = core();
+        assert_eq!(
+            db.check("core/vim"),
+            PackageStatus::Package {
+                repo: "core".to_string()
+            }
+        );
+        assert_eq!(db.check("base-devel"), PackageStatus::Group);
+        assert_eq!(
+            db.check("xxd"),
+            PackageStatus::Provided {
+                by: vec!["vim".to_string()]
+            }
+        );
+        assert_eq!(
+            db.check("vin"),
+            PackageStatus::Unknown {
+                suggestions: vec!["vim".to_string()]
+            }
+        );
+        assert!(matches!(db.check("extra/vim"), PackageStatus::Unknown { .. }));
+    }
+
+    #[test]
+    fn sizes_count_dependencies_once() {
+        let db = core();
+        let mut counted = HashSet::new();
+        assert_eq!(
+            db.size(&["vim".to_string(), "nope".to_string()], &mut counted),
+            PackageSize {
+                packages: 4,
+                download_size: 2000 + 700 + 10 + 1,
+                installed_size: 5000 + 3000 + 100 + 2,
+                missing: vec!["nope".to_string()],
+            }
+        );
+
+        // glibc is already counted with vim
+        assert_eq!(
+            db.size(&["base-devel".to_string(), "glibc".to_string()], &mut counted),
+            PackageSize {
+                packages: 1,
+                download_size: 5,
+                installed_size: 6,
+                missing: Vec::new(),
+            }
+        );
+    }
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(edit_distance("vim", "vim"), 0);
+        assert_eq!(edit_distance("firefx", "firefox"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(url_encode("gtk+3"), "gtk%2B3");
+    }
+}
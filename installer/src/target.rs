@@ -0,0 +1,90 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use crate::utils::DEFAULT_TARGET_ROOT;
+
+/// The new system's / as seen from the running process.
+/// Every path inside the target is built from this instead of hard-coding /mnt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetRoot(PathBuf);
+
+impl TargetRoot {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        TargetRoot(root.into())
+    }
+
+    /// The running system's own /.
+    /// This is what the target looks like once we are chrooted into it.
+    pub fn host() -> Self {
+        TargetRoot::new("/")
+    }
+
+    /// Resolves an absolute path of the new system.
+    /// eg. /etc/hostname becomes /mnt/etc/hostname
+    pub fn path(&self, path: &str) -> PathBuf {
+        self.0.join(path.trim_start_matches('/'))
+    }
+
+    pub fn as_path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Default for TargetRoot {
+    fn default() -> Self {
+        TargetRoot::new(DEFAULT_TARGET_ROOT)
+    }
+}
+
+impl Display for TargetRoot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0.display())
+    }
+}
+
+/// Where the new system gets installed.
+#[derive(Debug, Clone)]
+pub enum InstallTarget {
+    /// Formats the selected partitions and mounts them at root.
+    Partitions { root: TargetRoot },
+    /// Installs into a plain directory, eg. /var/lib/machines/dev.
+    /// Used for CI containers and systemd-nspawn machines.
+    /// Partitioning, bootloader, swap and fstab are skipped.
+    Directory {
+        root: TargetRoot,
+        /// Packs the finished rootfs into this tarball if set.
+        tarball: Option<String>,
+    },
+}
+
+impl Default for InstallTarget {
+    fn default() -> Self {
+        InstallTarget::Partitions {
+            root: TargetRoot::default(),
+        }
+    }
+}
+
+impl InstallTarget {
+    /// The path the new system's / lives at in the live environment.
+    pub fn root(&self) -> &TargetRoot {
+        match self {
+            InstallTarget::Partitions { root } => root,
+            InstallTarget::Directory { root, .. } => root,
+        }
+    }
+
+    /// Whether this is a container rootfs instead of a bootable system.
+    pub fn is_directory(&self) -> bool {
+        matches!(self, InstallTarget::Directory { .. })
+    }
+
+    pub fn tarball(&self) -> Option<&str> {
+        match self {
+            InstallTarget::Partitions { .. } => None,
+            InstallTarget::Directory { tarball, .. } => tarball.as_deref(),
+        }
+    }
+}
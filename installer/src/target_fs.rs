@@ -0,0 +1,369 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, OpenOptions, Permissions},
+    io::Write,
+    os::unix::{
+        self,
+        fs::{OpenOptionsExt, PermissionsExt},
+    },
+    path::Path,
+};
+
+use anyhow::{anyhow, Result};
+use nix::unistd::Uid;
+
+use crate::target::TargetRoot;
+
+/// Permissions and ownership of a file written into the new system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileMode {
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl FileMode {
+    /// Regular configuration files, eg. /etc/hostname
+    pub const CONFIG: FileMode = FileMode {
+        mode: 0o644,
+        uid: 0,
+        gid: 0,
+    };
+
+    /// Files only root may read, eg. /etc/doas.conf
+    pub const ROOT_ONLY: FileMode = FileMode {
+        mode: 0o400,
+        uid: 0,
+        gid: 0,
+    };
+}
+
+/// All writes to the new system's configuration files go through this.
+/// Paths are absolute paths of the new system, eg. /etc/hostname,
+/// no matter where the new system is mounted or whether we are chrooted.
+/// Every file that gets changed is recorded.
+/// Send, the install can run on a thread of its own.
+pub trait TargetFs: Send {
+    /// Reads a file. Returns None if it does not exist.
+    fn read(&self, path: &str) -> Result<Option<String>>;
+
+    /// Replaces the whole file with content.
+    /// The file is never seen half written.
+    fn write(&mut self, path: &str, content: &str, mode: FileMode) -> Result<()>;
+
+    /// Points link at target, replacing whatever was there.
+    fn symlink(&mut self, target: &str, link: &str) -> Result<()>;
+
+    /// Files changed so far, in the order they were first touched.
+    fn touched(&self) -> &[String];
+
+    /// Changes where the new system is found, eg. to / after chrooting into it.
+    /// Does nothing for filesystems that are not backed by a real root.
+    fn set_root(&mut self, _root: TargetRoot) {}
+
+    /// Replaces the first line starting with prefix, or appends line if there is none.
+    /// eg. keeps a single LANG= in locale.conf however often it is called.
+    fn replace_line(&mut self, path: &str, prefix: &str, line: &str, mode: FileMode) -> Result<()> {
+        let existing = self.read(path)?.unwrap_or_default();
+        let mut replaced = false;
+        let mut lines = existing
+            .lines()
+            .map(|x| {
+                if !replaced && x.trim_start().starts_with(prefix) {
+                    replaced = true;
+                    line.to_string()
+                } else {
+                    x.to_string()
+                }
+            })
+            .collect::<Vec<String>>();
+
+        if !replaced {
+            lines.push(line.to_string());
+        }
+
+        let content = format!("{}\n", lines.join("\n"));
+        if content == existing {
+            return Ok(());
+        }
+        self.write(path, &content, mode)
+    }
+
+    /// Appends line unless the file already contains it.
+    fn append_if_absent(&mut self, path: &str, line: &str, mode: FileMode) -> Result<()> {
+        let existing = self.read(path)?.unwrap_or_default();
+        if existing.lines().any(|x| x.trim() == line.trim()) {
+            return Ok(());
+        }
+
+        let mut content = existing;
+        if !content.is_empty() && !content.ends_with('\n') {
+            content.push('\n');
+        }
+        content.push_str(line);
+        content.push('\n');
+        self.write(path, &content, mode)
+    }
+}
+
+/// Makes sure the path is absolute, eg. etc/hosts becomes /etc/hosts
+fn normalize(path: &str) -> String {
+    format!("/{}", path.trim_start_matches('/'))
+}
+
+fn record(touched: &mut Vec<String>, path: &str) {
+    if !touched.iter().any(|x| x == path) {
+        touched.push(path.to_string());
+    }
+}
+
+/// TargetFs backed by the new system on disk.
+/// Writes go to a temporary file next to the destination which is renamed over it.
+pub struct RootFs {
+    root: TargetRoot,
+    touched: Vec<String>,
+}
+
+impl RootFs {
+    pub fn new(root: TargetRoot) -> RootFs {
+        RootFs {
+            root,
+            touched: Vec::new(),
+        }
+    }
+
+    /// Temporary sibling of path that gets renamed over it.
+    fn temporary(path: &Path) -> Result<std::path::PathBuf> {
+        let name = match path.file_name() {
+            Some(x) => x.to_string_lossy(),
+            None => return Err(anyhow!("{} is not a file", path.display())),
+        };
+        Ok(path.with_file_name(format!(".{}.2lazy4arch", name)))
+    }
+}
+
+impl TargetFs for RootFs {
+    fn read(&self, path: &str) -> Result<Option<String>> {
+        match fs::read_to_string(self.root.path(path)) {
+            Ok(x) => Ok(Some(x)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(anyhow!(e)),
+        }
+    }
+
+    fn write(&mut self, path: &str, content: &str, mode: FileMode) -> Result<()> {
+        let path = normalize(path);
+        let destination = self.root.path(&path);
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let temporary = RootFs::temporary(&destination)?;
+        {
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .mode(mode.mode)
+                .open(&temporary)?;
+            file.write_all(content.as_bytes())?;
+            file.sync_all()?;
+        }
+
+        // the umask might have taken some bits away
+        fs::set_permissions(&temporary, Permissions::from_mode(mode.mode))?;
+        if Uid::effective().is_root() {
+            unix::fs::chown(&temporary, Some(mode.uid), Some(mode.gid))?;
+        }
+        fs::rename(&temporary, &destination)?;
+
+        record(&mut self.touched, &path);
+        Ok(())
+    }
+
+    fn symlink(&mut self, target: &str, link: &str) -> Result<()> {
+        let link = normalize(link);
+        let destination = self.root.path(&link);
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let temporary = RootFs::temporary(&destination)?;
+        let _ = fs::remove_file(&temporary);
+        unix::fs::symlink(target, &temporary)?;
+        fs::rename(&temporary, &destination)?;
+
+        record(&mut self.touched, &link);
+        Ok(())
+    }
+
+    fn touched(&self) -> &[String] {
+        &self.touched
+    }
+
+    fn set_root(&mut self, root: TargetRoot) {
+        self.root = root;
+    }
+}
+
+/// A file or symlink in a MemoryFs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MemoryNode {
+    File { content: String, mode: FileMode },
+    Symlink(String),
+}
+
+/// TargetFs that lives entirely in memory.
+/// Lets the file writing steps run without a real system to write to.
+#[derive(Debug, Default)]
+pub struct MemoryFs {
+    nodes: BTreeMap<String, MemoryNode>,
+    touched: Vec<String>,
+}
+
+impl MemoryFs {
+    pub fn new() -> MemoryFs {
+        MemoryFs::default()
+    }
+
+    /// Adds a file as if it was already on the system.
+    /// Does not count as touched.
+    pub fn with_file(mut self, path: &str, content: &str) -> MemoryFs {
+        self.nodes.insert(
+            normalize(path),
+            MemoryNode::File {
+                content: content.to_string(),
+                mode: FileMode::CONFIG,
+            },
+        );
+        self
+    }
+
+    pub fn get(&self, path: &str) -> Option<&MemoryNode> {
+        self.nodes.get(&normalize(path))
+    }
+
+    pub fn nodes(&self) -> &BTreeMap<String, MemoryNode> {
+        &self.nodes
+    }
+}
+
+impl TargetFs for MemoryFs {
+    fn read(&self, path: &str) -> Result<Option<String>> {
+        match self.get(path) {
+            Some(MemoryNode::File { content, .. }) => Ok(Some(content.clone())),
+            Some(MemoryNode::Symlink(target)) => self.read(target),
+            None => Ok(None),
+        }
+    }
+
+    fn write(&mut self, path: &str, content: &str, mode: FileMode) -> Result<()> {
+        let path = normalize(path);
+        self.nodes.insert(
+            path.clone(),
+            MemoryNode::File {
+                content: content.to_string(),
+                mode,
+            },
+        );
+        record(&mut self.touched, &path);
+        Ok(())
+    }
+
+    fn symlink(&mut self, target: &str, link: &str) -> Result<()> {
+        let link = normalize(link);
+        self.nodes
+            .insert(link.clone(), MemoryNode::Symlink(target.to_string()));
+        record(&mut self.touched, &link);
+        Ok(())
+    }
+
+    fn touched(&self) -> &[String] {
+        &self.touched
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn content(target_fs: &MemoryFs, path: &str) -> String {
+        target_fs.read(path).unwrap().unwrap_or_default()
+    }
+
+    #[test]
+    fn replace_line_keeps_a_single_line() {
+        let mut target_fs = MemoryFs::new().with_file("/etc/locale.conf", "LANG=C\nLC_TIME=C\n");
+        target_fs
+            .replace_line("/etc/locale.conf", "LANG=", "LANG=en_US.UTF-8", FileMode::CONFIG)
+            .unwrap();
+        target_fs
+            .replace_line("etc/locale.conf", "LANG=", "LANG=en_US.UTF-8", FileMode::CONFIG)
+            .unwrap();
+        target_fs
+            .replace_line("/etc/vconsole.conf", "KEYMAP=", "KEYMAP=de", FileMode::CONFIG)
+            .unwrap();
+
+        assert_eq!(
+            content(&target_fs, "/etc/locale.conf"),
+            "LANG=en_US.UTF-8\nLC_TIME=C\n"
+        );
+        assert_eq!(content(&target_fs, "/etc/vconsole.conf"), "KEYMAP=de\n");
+        assert_eq!(
+            target_fs.touched(),
+            ["/etc/locale.conf", "/etc/vconsole.conf"]
+        );
+    }
+
+    #[test]
+    fn append_if_absent_appends_once() {
+        let mut target_fs = MemoryFs::new().with_file("/etc/fstab", "# no newline");
+        for _ in 0..2 {
+            target_fs
+                .append_if_absent("/etc/fstab", "/swapfile none swap defaults 0 0", FileMode::CONFIG)
+                .unwrap();
+        }
+        assert_eq!(
+            content(&target_fs, "/etc/fstab"),
+            "# no newline\n/swapfile none swap defaults 0 0\n"
+        );
+    }
+
+    #[test]
+    fn memory_fs_follows_symlinks() {
+        let mut target_fs = MemoryFs::new().with_file("/usr/share/zoneinfo/Asia/Kolkata", "IST");
+        target_fs
+            .symlink("/usr/share/zoneinfo/Asia/Kolkata", "/etc/localtime")
+            .unwrap();
+        assert_eq!(
+            target_fs.get("/etc/localtime"),
+            Some(&MemoryNode::Symlink(
+                "/usr/share/zoneinfo/Asia/Kolkata".to_string()
+            ))
+        );
+        assert_eq!(content(&target_fs, "/etc/localtime"), "IST");
+        assert_eq!(target_fs.read("/etc/missing").unwrap(), None);
+    }
+
+    #[test]
+    fn root_fs_writes_below_the_root() {
+        let dir = std::env::temp_dir().join(format!("2lazy4arch-root-fs-{}", std::process::id()));
+        let mut target_fs = RootFs::new(TargetRoot::new(&dir));
+
+        let written = target_fs
+            .write("/etc/doas.conf", "permit :wheel\n", FileMode::ROOT_ONLY)
+            .and_then(|_| target_fs.symlink("/usr/bin/doas", "/usr/bin/sudo"))
+            .and_then(|_| target_fs.read("/etc/doas.conf"));
+        let mode = fs::metadata(dir.join("etc/doas.conf")).map(|x| x.permissions().mode() & 0o777);
+        let link = fs::read_link(dir.join("usr/bin/sudo"));
+        let leftovers = fs::read_dir(dir.join("etc")).map(|x| x.count());
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(written.unwrap(), Some("permit :wheel\n".to_string()));
+        assert_eq!(mode.unwrap(), 0o400);
+        assert_eq!(link.unwrap(), Path::new("/usr/bin/doas"));
+        // the temporary file was renamed over the destination
+        assert_eq!(leftovers.unwrap(), 1);
+        assert_eq!(target_fs.touched(), ["/etc/doas.conf", "/usr/bin/sudo"]);
+    }
+}
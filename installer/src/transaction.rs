@@ -0,0 +1,330 @@
+use std::collections::HashMap;
+
+use shell_iface::{progress::Progress, Shell};
+
+/// Where a copy of the sync databases is kept to size a pacstrap before it runs
+const SIZES_DBPATH: &str = "/tmp/2lazy4arch-sizes";
+
+/// Turns the output of a pacman transaction into progress events, line by line.
+/// Works on pacman's plain output, which it prints when stdout is not a terminal.
+#[derive(Debug, Default)]
+pub struct TransactionProgress {
+    /// Download size of each package, by "name-version"
+    sizes: HashMap<String, u64>,
+    /// From "Packages (N)", 0 until pacman has resolved the transaction
+    packages: usize,
+    downloaded: usize,
+    bytes: u64,
+    total_bytes: u64,
+    processed: usize,
+}
+
+impl TransactionProgress {
+    pub fn new(sizes: HashMap<String, u64>) -> TransactionProgress {
+        TransactionProgress {
+            sizes,
+            ..Default::default()
+        }
+    }
+
+    pub fn parse_line(&mut self, line: &str) -> Option<Progress> {
+        let line = line.trim();
+
+        if line.starts_with(":: Synchronizing package databases")
+            || line.starts_with("resolving dependencies")
+            || line.starts_with("looking for conflicting packages")
+        {
+            return Some(Progress::Resolving);
+        }
+
+        if let Some(rest) = line.strip_prefix("Packages (") {
+            self.packages = rest.split(')').next()?.trim().parse().ok()?;
+            return None;
+        }
+
+        if let Some(size) = line.strip_prefix("Total Download Size:") {
+            self.total_bytes = parse_size(size)?;
+            return None;
+        }
+
+        if let Some(file) = line.strip_suffix(" downloading...") {
+            // the databases are downloaded before pacman knows the packages
+            if self.packages == 0 {
+                return Some(Progress::Resolving);
+            }
+            let package = package_name(file);
+            self.downloaded += 1;
+            self.bytes += self.size_of(&package);
+            return Some(Progress::Downloading {
+                package,
+                done: self.downloaded,
+                total: self.packages,
+                bytes: self.bytes,
+                total_bytes: self.total_bytes,
+            });
+        }
+
+        for verb in ["installing", "upgrading", "reinstalling", "downgrading", "removing"] {
+            let package = match line
+                .strip_prefix(verb)
+                .and_then(|x| x.strip_prefix(' '))
+                .and_then(|x| x.strip_suffix("..."))
+            {
+                Some(x) => x.to_string(),
+                None => continue,
+            };
+            self.processed += 1;
+            let (current, total) = (self.processed, self.packages.max(self.processed));
+            return Some(if verb == "removing" {
+                Progress::Removing {
+                    package,
+                    current,
+                    total,
+                }
+            } else {
+                Progress::Installing {
+                    package,
+                    current,
+                    total,
+                }
+            });
+        }
+
+        // hooks, eg. "(3/7) Reloading system manager configuration..."
+        let (count, hook) = line.strip_prefix('(')?.split_once(')')?;
+        let (current, total) = count.split_once('/')?;
+        Some(Progress::RunningHooks {
+            hook: hook.trim().trim_end_matches("...").to_string(),
+            current: current.trim().parse().ok()?,
+            total: total.trim().parse().ok()?,
+        })
+    }
+
+    fn size_of(&self, package: &str) -> u64 {
+        if let Some(size) = self.sizes.get(package) {
+            return *size;
+        }
+        // the file name has the architecture after the version
+        package
+            .rsplit_once('-')
+            .and_then(|(x, _)| self.sizes.get(x))
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+/// Download sizes of everything a transaction would download, by "name-version".
+/// args are put before -Sp, eg. a --config or --dbpath. packages are separated by spaces.
+/// Empty if pacman cannot tell, the progress then counts packages only.
+pub fn download_sizes(shell: &mut Shell, args: &str, packages: &str) -> HashMap<String, u64> {
+    let output = match shell.run_with_args(
+        "pacman",
+        &format!(
+            "{}-Sp --noconfirm --print-format \"%n-%v %s\" {}",
+            args, packages
+        ),
+    ) {
+        Ok(x) => x,
+        Err(e) => {
+            shell.log(&format!("Could not get the download sizes: {}", e));
+            return HashMap::new();
+        }
+    };
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|x| {
+            let (package, size) = x.trim().split_once(' ')?;
+            Some((package.to_string(), size.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+/// Download sizes for pacstrap, which installs into an empty system.
+/// Resolved against a copy of the live environment's sync databases with nothing
+/// installed, so dependencies the live environment already has are counted too.
+pub fn pacstrap_download_sizes(shell: &mut Shell, packages: &[String]) -> HashMap<String, u64> {
+    let _ = std::fs::remove_dir_all(SIZES_DBPATH);
+    if let Err(e) = std::fs::create_dir_all(SIZES_DBPATH) {
+        shell.log(&format!("Could not create {}: {}", SIZES_DBPATH, e));
+        return HashMap::new();
+    }
+    if let Err(e) =
+        shell.run_with_args("cp", &format!("-r /var/lib/pacman/sync {}", SIZES_DBPATH))
+    {
+        shell.log(&format!("Could not copy the sync databases: {}", e));
+        return HashMap::new();
+    }
+
+    let sizes = download_sizes(
+        shell,
+        &format!("--dbpath {} ", SIZES_DBPATH),
+        &packages.join(" "),
+    );
+    let _ = std::fs::remove_dir_all(SIZES_DBPATH);
+    sizes
+}
+
+/// Strips the extension, eg. "linux-6.8.9.arch1-1-x86_64.pkg.tar.zst" to "linux-6.8.9.arch1-1-x86_64"
+fn package_name(file: &str) -> String {
+    let file = file.trim();
+    match file.find(".pkg.tar") {
+        Some(index) => file[..index].to_string(),
+        None => file.to_string(),
+    }
+}
+
+/// eg. "123.45 MiB"
+fn parse_size(size: &str) -> Option<u64> {
+    let (value, unit) = size.trim().split_once(' ')?;
+    let value = value.trim().parse::<f64>().ok()?;
+    let multiplier = match unit.trim() {
+        "B" => 1.0,
+        "KiB" => 1024.0,
+        "MiB" => 1024.0 * 1024.0,
+        "GiB" => 1024.0 * 1024.0 * 1024.0,
+        "TiB" => 1024.0 * 1024.0 * 1024.0 * 1024.0,
+        _ => return None,
+    };
+    Some((value * multiplier) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// pacman -Syu --noconfirm foo, with stdout not a terminal
+    const INSTALL: &str = "\
+:: Synchronizing package databases...
+ core downloading...
+ extra downloading...
+resolving dependencies...
+looking for conflicting packages...
+
+Packages (2) libfoo-1.0-1  foo-2.0-1
+
+Total Download Size:   1.50 MiB
+Total Installed Size:  4.00 MiB
+
+:: Proceed with installation? [Y/n] 
+:: Retrieving packages...
+ libfoo-1.0-1-x86_64.pkg.tar.zst downloading...
+ foo-2.0-1-any.pkg.tar.zst downloading...
+checking keyring...
+checking package integrity...
+loading package files...
+checking for file conflicts...
+:: Processing package changes...
+installing libfoo...
+installing foo...
+Optional dependencies for foo
+    bar: for the bar support
+:: Running post-transaction hooks...
+(1/2) Arming ConditionNeedsUpdate...
+(2/2) Updating the info directory file...
+";
+
+    fn parse(sizes: &[(&str, u64)], output: &str) -> Vec<Progress> {
+        let sizes = sizes.iter().map(|(x, y)| (x.to_string(), *y)).collect();
+        let mut progress = TransactionProgress::new(sizes);
+        output.lines().filter_map(|x| progress.parse_line(x)).collect()
+    }
+
+    #[test]
+    fn follows_an_install() {
+        let progress = parse(
+            &[("libfoo-1.0-1", 1024 * 1024), ("foo-2.0-1", 512 * 1024)],
+            INSTALL,
+        );
+        assert_eq!(
+            progress,
+            [
+                Progress::Resolving,
+                Progress::Resolving,
+                Progress::Resolving,
+                Progress::Resolving,
+                Progress::Resolving,
+                Progress::Downloading {
+                    package: "libfoo-1.0-1-x86_64".to_string(),
+                    done: 1,
+                    total: 2,
+                    bytes: 1024 * 1024,
+                    total_bytes: 1536 * 1024,
+                },
+                Progress::Downloading {
+                    package: "foo-2.0-1-any".to_string(),
+                    done: 2,
+                    total: 2,
+                    bytes: 1536 * 1024,
+                    total_bytes: 1536 * 1024,
+                },
+                Progress::Installing {
+                    package: "libfoo".to_string(),
+                    current: 1,
+                    total: 2,
+                },
+                Progress::Installing {
+                    package: "foo".to_string(),
+                    current: 2,
+                    total: 2,
+                },
+                Progress::RunningHooks {
+                    hook: "Arming ConditionNeedsUpdate".to_string(),
+                    current: 1,
+                    total: 2,
+                },
+                Progress::RunningHooks {
+                    hook: "Updating the info directory file".to_string(),
+                    current: 2,
+                    total: 2,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_sizes_count_packages_only() {
+        let progress = parse(&[], INSTALL);
+        assert!(progress.contains(&Progress::Downloading {
+            package: "foo-2.0-1-any".to_string(),
+            done: 2,
+            total: 2,
+            bytes: 0,
+            total_bytes: 1536 * 1024,
+        }));
+    }
+
+    #[test]
+    fn follows_a_removal() {
+        let output = "\
+checking dependencies...
+
+Packages (1) foo-2.0-1
+
+Total Removed Size:  4.00 MiB
+
+:: Do you want to remove these packages? [Y/n] 
+:: Processing package changes...
+removing foo...
+";
+        assert_eq!(
+            parse(&[], output),
+            [Progress::Removing {
+                package: "foo".to_string(),
+                current: 1,
+                total: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn parses_sizes_and_file_names() {
+        assert_eq!(parse_size("1.50 MiB"), Some(1536 * 1024));
+        assert_eq!(parse_size(" 12 B"), Some(12));
+        assert_eq!(parse_size("1.0 XiB"), None);
+        assert_eq!(
+            package_name("linux-6.8.9.arch1-1-x86_64.pkg.tar.zst"),
+            "linux-6.8.9.arch1-1-x86_64"
+        );
+    }
+}
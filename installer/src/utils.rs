@@ -1,17 +1,19 @@
 use std::{
     fs::{self, OpenOptions},
-    io::{Write, self, BufReader, BufRead},
+    io::Write,
+    path::Path,
 };
 
 use anyhow::{anyhow, Result};
 
 pub const RICE_SCRIPT_URL : &str = "";
 pub const INSTALL_SUCCESS_FLAG: &str = "/var/tmp/2lazy4archinstallationflag";
+pub const DEFAULT_TARGET_ROOT: &str = "/mnt";
 
 /// Opens a file, writes the content.
 /// Creates the file if the file does not exist.
-pub fn write_to_file(path: &str, content: &str) -> Result<()> {
-    let mut file = match OpenOptions::new().write(true).create(true).open(path) {
+pub fn write_to_file<P: AsRef<Path>>(path: P, content: &str) -> Result<()> {
+    let mut file = match OpenOptions::new().write(true).create(true).truncate(true).open(path) {
         Ok(x) => x,
         Err(e) => {
             return Err(anyhow!(e));
@@ -28,7 +30,7 @@ pub fn write_to_file(path: &str, content: &str) -> Result<()> {
 /// Opens a file, appends the content.
 /// Creates the file if the file does not exist.
 /// Adds a newline before appending just to be sure.
-pub fn append_to_file(path: &str, content: &str) -> Result<()> {
+pub fn append_to_file<P: AsRef<Path>>(path: P, content: &str) -> Result<()> {
     let mut file = match OpenOptions::new().append(true).create(true).open(path) {
         Ok(x) => x,
         Err(e) => {
@@ -43,29 +45,6 @@ pub fn append_to_file(path: &str, content: &str) -> Result<()> {
     Ok(())
 }
 
-/// Glorified sed function to insert lines at specified line number.
-/// This can in theory do the same stuff as append and write.
-pub fn sed(file_path: &str, line_number: usize, line_content: &str) -> io::Result<()> {
-    let file = fs::File::open(file_path)?;
-    let reader = BufReader::new(file);
-
-    let mut lines = reader.lines().collect::<io::Result<Vec<String>>>()?;
-
-    if line_number <= lines.len() {
-        lines.insert(line_number - 1, line_content.to_string());
-    } else {
-        lines.push(line_content.to_string());
-    }
-
-    let mut new_file = fs::File::create(file_path)?;
-
-    for line in lines {
-        writeln!(new_file, "{}", line)?;
-    }
-
-    Ok(())
-}
-
 /// Checks whether the processor is Intel or AMD.
 /// Returns None if none of them.
 pub fn get_processor_make() -> Option<String> {
@@ -80,26 +59,27 @@ pub fn get_processor_make() -> Option<String> {
     if processor_info.contains("GenuineIntel") {
         return Some(String::from("intel"));
     }
-   return None;
+   None
 }
 
-/// Get UUID of root
+/// Get UUID of root from the given fstab
 /// This might fail if:
 /// - the fstab is not generated
 /// - the fstab is not generated with UUIDs using genfstab
 /// - the root's UUID is not in fstab
-pub fn get_uuid_root() -> Result<String> {
-    let fstab = match fs::read_to_string("/etc/fstab") {
-        Ok(x) => x.lines().filter(|x|{
-            let line = x.trim();
-            return !(line.starts_with("#") || line.is_empty())
-        }).collect::<Vec<&str>>().iter().map(|l| {
-            l.to_string()
-        }).collect::<Vec<String>>(),
-        Err(_) => {
-            return Err(anyhow!("Could not open fstab"));
-        },
-    };
+pub fn get_uuid_root<P: AsRef<Path>>(fstab_path: P) -> Result<String> {
+    match fs::read_to_string(fstab_path) {
+        Ok(x) => find_uuid_root(&x),
+        Err(_) => Err(anyhow!("Could not open fstab")),
+    }
+}
+
+/// Same as get_uuid_root, but for an fstab that has already been read.
+pub fn find_uuid_root(fstab: &str) -> Result<String> {
+    let fstab = fstab.lines().filter(|x|{
+        let line = x.trim();
+        !(line.starts_with("#") || line.is_empty())
+    }).collect::<Vec<&str>>();
     
     for line in fstab {
         let row = line.split("\t").map(|x| {
@@ -109,10 +89,7 @@ pub fn get_uuid_root() -> Result<String> {
         if row.len() > 2 {
             //println!("{:#?}", row);
             if row[1] == "/"{
-                match row[0].split("=").last() {
-                    Some(x) => return Ok(x.to_string()),
-                    None => {},
-                };
+                if let Some(x) = row[0].split("=").last() { return Ok(x.to_string()) };
             }
         }
     }
@@ -1,11 +1,14 @@
 use std::{
     fmt::Debug,
-    process::{Child, Command, ExitStatus, Output, Stdio}, io::Write,
+    process::{Child, Command, ExitStatus, Output, Stdio}, io::{self, BufRead, BufReader, Read, Write},
+    thread,
 };
 
 use anyhow::{anyhow, Result};
-use logger::Logger;
+use logger::{CommandFailure, Logger};
+use progress::Progress;
 pub mod logger;
+pub mod progress;
 
 /// Defines the mode at which it is running
 /// Shell in Debug does not run the actual command.
@@ -93,6 +96,71 @@ impl<'a> Shell<'a> {
         self.logger.debug(&self.identifier, msg);
     }
 
+    /// The shell's logger, eg. to report progress from a callback while the shell is busy
+    pub fn logger(&self) -> &'a Logger {
+        self.logger
+    }
+
+    /// Reports the progress of a package transaction using the shell's logger
+    pub fn progress(&self, progress: &Progress) {
+        self.logger.progress(progress);
+    }
+
+    /// Tells the logger a program is starting, eg. to show it in a TUI
+    fn started(&self, cmd: &str, args: &str) {
+        self.logger.progress(&Progress::Command(
+            format!("{} {}", cmd, args).trim_end().to_string(),
+        ));
+    }
+
+    /// Waits for the program. If the logger captures output, stdout and stderr go to it
+    /// line by line and the program gets no stdin, stderr is also returned.
+    /// Otherwise the program has the terminal and the returned stderr is empty.
+    fn status(&self, command: &mut Command) -> io::Result<(ExitStatus, String)> {
+        if !self.logger.captures_output() {
+            return Ok((command.status()?, String::new()));
+        }
+
+        let mut child = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let logger = self.logger;
+        let stdout = child.stdout.take();
+        let stderr = child.stderr.take();
+        let stderr = thread::scope(|s| {
+            // read stderr on its own thread, a full pipe would block the program
+            let stderr_reader = stderr.map(|pipe| {
+                s.spawn(move || {
+                    let mut stderr = String::new();
+                    for line in BufReader::new(pipe).lines().map_while(|x| x.ok()) {
+                        show(logger, &line, true);
+                        stderr.push_str(&line);
+                        stderr.push('\n');
+                    }
+                    stderr
+                })
+            });
+            if let Some(pipe) = stdout {
+                show_lines(logger, pipe, false);
+            }
+            match stderr_reader {
+                Some(x) => x.join().unwrap_or_default(),
+                None => String::new(),
+            }
+        });
+        Ok((child.wait()?, stderr))
+    }
+
+    /// Hands the program that failed to the logger, see Logger::take_command_failure
+    fn failed(&self, cmd: &str, args: &str, stderr: &[u8]) {
+        self.logger.command_failed(CommandFailure {
+            command: format!("{} {}", cmd, args).trim_end().to_string(),
+            stderr: String::from_utf8_lossy(stderr).to_string(),
+        });
+    }
+
     /// Run the program without stdin.
     /// Collect stdout and stderr and store it in Output.
     /// Raises error if exited with non-zero code.
@@ -105,9 +173,11 @@ impl<'a> Shell<'a> {
             self.set_last_command(cmd, &output.status, None, None);
             return Ok(output);
         }
+        self.started(cmd, args);
         let output = Command::new(cmd).args(args_vec).output()?;
 
         if !output.status.success() {
+            self.failed(cmd, args, &output.stderr);
             self.log(&format!(
                 "{}: {} {:#?} failed. Exited with non-zero exit code",
                 self.identifier.to_uppercase(),
@@ -144,9 +214,11 @@ impl<'a> Shell<'a> {
             self.set_last_command(cmd, &output.status, None, None);
             return Ok(output);
         }
+        self.started(cmd, args);
         let output = Command::new(cmd).args(args_vec).current_dir(dir).output()?;
 
         if !output.status.success() {
+            self.failed(cmd, args, &output.stderr);
             self.log(&format!(
                 "{}: {} {:#?} failed. Exited with non-zero exit code",
                 self.identifier.to_uppercase(),
@@ -180,9 +252,11 @@ impl<'a> Shell<'a> {
             self.set_last_command(cmd, &output.status, None, None);
             return Ok(output);
         }
+        self.started(cmd, "");
         let output = Command::new(cmd).output()?;
 
         if !output.status.success() {
+            self.failed(cmd, "", &output.stderr);
             return Err(anyhow!(
                 "{}: {} failed. Exited with non-zero exit code",
                 self.identifier.to_uppercase(),
@@ -209,9 +283,11 @@ impl<'a> Shell<'a> {
             self.set_last_command(cmd, &output.status, None, None);
             return Ok(output);
         }
+        self.started(cmd, "");
         let output = Command::new(cmd).output()?;
 
         if !output.status.success() {
+            self.failed(cmd, "", &output.stderr);
             return Err(anyhow!(
                 "{}: {} failed. Exited with non-zero exit code",
                 self.identifier.to_uppercase(),
@@ -239,9 +315,11 @@ impl<'a> Shell<'a> {
             return Ok(output);
         }
 
-        let status = Command::new(cmd).status()?;
+        self.started(cmd, "");
+        let (status, stderr) = self.status(&mut Command::new(cmd))?;
 
         if !status.success() {
+            self.failed(cmd, "", stderr.as_bytes());
             return Err(anyhow!(
                 "{}: {} failed. Exited with non-zero exit code",
                 self.identifier.to_uppercase(),
@@ -265,9 +343,11 @@ impl<'a> Shell<'a> {
             return Ok(output);
         }
 
-        let status = Command::new(cmd).current_dir(dir).status()?;
+        self.started(cmd, "");
+        let (status, stderr) = self.status(Command::new(cmd).current_dir(dir))?;
 
         if !status.success() {
+            self.failed(cmd, "", stderr.as_bytes());
             return Err(anyhow!(
                 "{}: {} failed. Exited with non-zero exit code",
                 self.identifier.to_uppercase(),
@@ -290,9 +370,11 @@ impl<'a> Shell<'a> {
             self.set_last_command(cmd, &status, None, None);
             return Ok(status);
         }
-        let status = Command::new(cmd).args(args_vec).status()?;
+        self.started(cmd, args);
+        let (status, stderr) = self.status(Command::new(cmd).args(args_vec))?;
 
         if !status.success() {
+            self.failed(cmd, args, stderr.as_bytes());
             return Err(anyhow!(
                 "{}: {} failed. Exited with non-zero exit code",
                 self.identifier.to_uppercase(),
@@ -304,6 +386,144 @@ impl<'a> Shell<'a> {
         Ok(status)
     }
 
+    /// Same as run_and_wait_with_args, but does not raise an error on non-zero exit.
+    /// The output is left on the terminal, eg. for programs that prompt without a newline.
+    pub fn run_and_wait_with_args_unchecked(&mut self, cmd: &str, args: &str) -> Result<ExitStatus> {
+        let args_vec = shell_words::split(args)?;
+        if let RunMode::Debug = &self.build_mode {
+            println!("Running Shell in Test Mode: Command: {}", cmd);
+            let status = Command::new("echo").arg("dummy").status()?;
+            self.set_last_command(cmd, &status, None, None);
+            return Ok(status);
+        }
+        self.started(cmd, args);
+        let (status, stderr) = self.status(Command::new(cmd).args(args_vec))?;
+        if !status.success() {
+            self.failed(cmd, args, stderr.as_bytes());
+        }
+
+        self.set_last_command(cmd, &status, None, None);
+        Ok(status)
+    }
+
+    /// Run the program with given args with stdin, keeping a copy of stderr.
+    /// stderr is still shown line by line as the program writes it.
+    /// Does not raise an error on non-zero exit, the caller decides from the status and stderr.
+    pub fn run_and_wait_with_args_capturing_stderr(
+        &mut self,
+        cmd: &str,
+        args: &str,
+    ) -> Result<(ExitStatus, String)> {
+        let args_vec = shell_words::split(args)?;
+        if let RunMode::Debug = &self.build_mode {
+            println!("Running Shell in Test Mode: Command: {}", cmd);
+            let status = Command::new("echo").arg("dummy").status()?;
+            self.set_last_command(cmd, &status, None, None);
+            return Ok((status, String::new()));
+        }
+
+        self.started(cmd, args);
+        let mut command = Command::new(cmd);
+        command.args(args_vec).stderr(Stdio::piped());
+        if self.logger.captures_output() {
+            command.stdin(Stdio::null()).stdout(Stdio::piped());
+        }
+        let mut child = command.spawn()?;
+
+        let logger = self.logger;
+        let stdout = child.stdout.take();
+        let mut stderr = String::new();
+        thread::scope(|s| -> Result<()> {
+            // stdout is only piped when captured, read on its own thread so neither pipe fills up
+            if let Some(pipe) = stdout {
+                s.spawn(move || show_lines(logger, pipe, false));
+            }
+            if let Some(pipe) = child.stderr.take() {
+                for line in BufReader::new(pipe).lines() {
+                    let line = line?;
+                    show(logger, &line, true);
+                    stderr.push_str(&line);
+                    stderr.push('\n');
+                }
+            }
+            Ok(())
+        })?;
+        let status = child.wait()?;
+        if !status.success() {
+            self.failed(cmd, args, stderr.as_bytes());
+        }
+
+        self.set_last_command(cmd, &status, None, Some(&stderr.as_bytes().to_vec()));
+        Ok((status, stderr))
+    }
+
+    /// Run the program with given args with stdin, handing each line of stdout to on_line.
+    /// stdout and stderr are still shown line by line, stderr is kept like in
+    /// run_and_wait_with_args_capturing_stderr.
+    /// Does not raise an error on non-zero exit, the caller decides from the status and stderr.
+    pub fn run_and_wait_with_args_reading_output<F: FnMut(&str)>(
+        &mut self,
+        cmd: &str,
+        args: &str,
+        mut on_line: F,
+    ) -> Result<(ExitStatus, String)> {
+        let args_vec = shell_words::split(args)?;
+        if let RunMode::Debug = &self.build_mode {
+            println!("Running Shell in Test Mode: Command: {}", cmd);
+            let status = Command::new("echo").arg("dummy").status()?;
+            self.set_last_command(cmd, &status, None, None);
+            return Ok((status, String::new()));
+        }
+
+        self.started(cmd, args);
+        let mut command = Command::new(cmd);
+        command
+            .args(args_vec)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        if self.logger.captures_output() {
+            command.stdin(Stdio::null());
+        }
+        let mut child = command.spawn()?;
+
+        let logger = self.logger;
+        let stdout = child.stdout.take();
+        let stderr = child.stderr.take();
+        let stderr = thread::scope(|s| -> Result<String> {
+            // read stderr on its own thread, a full pipe would block the program
+            let stderr_reader = stderr.map(|pipe| {
+                s.spawn(move || {
+                    let mut stderr = String::new();
+                    for line in BufReader::new(pipe).lines().map_while(|x| x.ok()) {
+                        show(logger, &line, true);
+                        stderr.push_str(&line);
+                        stderr.push('\n');
+                    }
+                    stderr
+                })
+            });
+
+            if let Some(pipe) = stdout {
+                for line in BufReader::new(pipe).lines() {
+                    let line = line?;
+                    show(logger, &line, false);
+                    on_line(&line);
+                }
+            }
+            Ok(match stderr_reader {
+                Some(x) => x.join().unwrap_or_default(),
+                None => String::new(),
+            })
+        })?;
+        let status = child.wait()?;
+        if !status.success() {
+            self.failed(cmd, args, stderr.as_bytes());
+        }
+
+        self.set_last_command(cmd, &status, None, Some(&stderr.as_bytes().to_vec()));
+        Ok((status, stderr))
+    }
+
     /// Run the program with given args with stdin inside a directory
     /// Only status is returned, not the output.
     /// Raises error if exited with non-zero code.
@@ -315,9 +535,11 @@ impl<'a> Shell<'a> {
             self.set_last_command(cmd, &status, None, None);
             return Ok(status);
         }
-        let status = Command::new(cmd).args(args_vec).current_dir(dir).status()?;
+        self.started(cmd, args);
+        let (status, stderr) = self.status(Command::new(cmd).args(args_vec).current_dir(dir))?;
 
         if !status.success() {
+            self.failed(cmd, args, stderr.as_bytes());
             return Err(anyhow!(
                 "{}: {} failed. Exited with non-zero exit code",
                 self.identifier.to_uppercase(),
@@ -452,3 +674,19 @@ impl<'a> Shell<'a> {
         Ok(child)
     }
 }
+
+/// Shows a line a program wrote, through the logger if it captures output
+fn show(logger: &Logger, line: &str, is_stderr: bool) {
+    if is_stderr && !logger.captures_output() {
+        eprintln!("{}", line);
+    } else {
+        logger.print(line);
+    }
+}
+
+/// Shows every line read from a pipe of a program
+fn show_lines<R: Read>(logger: &Logger, pipe: R, is_stderr: bool) {
+    for line in BufReader::new(pipe).lines().map_while(|x| x.ok()) {
+        show(logger, &line, is_stderr);
+    }
+}
@@ -1,16 +1,95 @@
 use std::{
+    fmt::Debug,
     fs::OpenOptions,
     io::Write,
+    sync::Mutex,
 };
 
-#[derive(Default, Debug)]
+use crate::progress::Progress;
+
+/// Called with every progress event of a package transaction
+pub type ProgressCallback = Box<dyn Fn(&Progress) + Send + Sync>;
+/// Called with every line meant for the terminal, see Logger::on_output
+pub type OutputCallback = Box<dyn Fn(&str) + Send + Sync>;
+
+/// A program that exited with an error
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommandFailure {
+    /// eg. "pacstrap -K /mnt base"
+    pub command: String,
+    /// Empty if it went straight to the terminal
+    pub stderr: String,
+}
+
+#[derive(Default)]
 pub struct Logger {
     is_debug: bool,
+    on_progress: Option<ProgressCallback>,
+    on_output: Option<OutputCallback>,
+    /// The last program that failed, see take_command_failure
+    command_failure: Mutex<Option<CommandFailure>>,
+}
+
+impl Debug for Logger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Logger")
+            .field("is_debug", &self.is_debug)
+            .field("on_progress", &self.on_progress.is_some())
+            .field("on_output", &self.on_output.is_some())
+            .finish()
+    }
 }
 
 impl Logger {
     pub fn new(is_debug: bool) -> Logger{
-        Logger { is_debug}
+        Logger {
+            is_debug,
+            on_progress: None,
+            on_output: None,
+            command_failure: Mutex::new(None),
+        }
+    }
+
+    /// Sets what is done with progress events, eg. drawing a progress bar.
+    /// Without it they are dropped, the output of pacman is shown as is.
+    pub fn on_progress<F: Fn(&Progress) + Send + Sync + 'static>(&mut self, callback: F) {
+        self.on_progress = Some(Box::new(callback));
+    }
+
+    pub fn progress(&self, progress: &Progress) {
+        if let Some(callback) = &self.on_progress {
+            callback(progress);
+        }
+    }
+
+    /// Takes the messages and the output of the programs the shells run instead of the terminal,
+    /// eg. to show them inside a TUI. The programs then get no stdin.
+    pub fn on_output<F: Fn(&str) + Send + Sync + 'static>(&mut self, callback: F) {
+        self.on_output = Some(Box::new(callback));
+    }
+
+    pub fn captures_output(&self) -> bool {
+        self.on_output.is_some()
+    }
+
+    /// Shows a line to the user, on stdout unless the output is captured
+    pub fn print(&self, line: &str) {
+        match &self.on_output {
+            Some(callback) => callback(line),
+            None => println!("{}", line),
+        }
+    }
+
+    /// Keeps the program that failed until it is taken, the shells call this
+    pub fn command_failed(&self, failure: CommandFailure) {
+        if let Ok(mut x) = self.command_failure.lock() {
+            *x = Some(failure);
+        }
+    }
+
+    /// The last program that failed since the last call, eg. to show why an install step failed
+    pub fn take_command_failure(&self) -> Option<CommandFailure> {
+        self.command_failure.lock().ok().and_then(|mut x| x.take())
     }
 
     pub fn debug(&self, origin: &str, msg: &str) {
@@ -19,7 +98,7 @@ impl Logger {
         }
 
         let content = format!("{}: {}", origin.to_uppercase(), msg);
-        println!("{}", content);
+        self.print(&content);
         let _ = append_to_file("shell_log.txt", &content);
     }
 }
@@ -46,7 +125,7 @@ pub fn append_to_file(path: &str, content: &str) -> Result<(), String> {
 /// Opens a file, writes the content.
 /// Creates the file if the file does not exist.
 pub fn write_to_file(path: &str, content: &str) -> Result<(), String> {
-    let mut file = match OpenOptions::new().write(true).create(true).open(path) {
+    let mut file = match OpenOptions::new().write(true).create(true).truncate(true).open(path) {
         Ok(x) => x,
         Err(e) => {
             return Err(e.to_string());
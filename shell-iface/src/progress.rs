@@ -0,0 +1,132 @@
+/// How far along the install, or a package transaction in it, is.
+/// Transactions are read from the output of pacman, pacstrap or yay while they run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Progress {
+    /// Syncing the databases, resolving dependencies and checking for conflicts
+    Resolving,
+    /// A package started downloading. bytes includes it.
+    Downloading {
+        package: String,
+        done: usize,
+        total: usize,
+        bytes: u64,
+        total_bytes: u64,
+    },
+    /// A package is being installed, upgraded or reinstalled, the current-th of total
+    Installing {
+        package: String,
+        current: usize,
+        total: usize,
+    },
+    Removing {
+        package: String,
+        current: usize,
+        total: usize,
+    },
+    /// A pre or post transaction hook, eg. mkinitcpio
+    RunningHooks {
+        hook: String,
+        current: usize,
+        total: usize,
+    },
+    /// The transaction went through
+    Done,
+    /// A step of the install started, the current-th of total, eg. "Install the base system"
+    Step {
+        phase: String,
+        name: String,
+        current: usize,
+        total: usize,
+    },
+    /// The current-th step is over, status says how, eg. "skipped"
+    StepFinished {
+        current: usize,
+        total: usize,
+        status: String,
+        failed: bool,
+    },
+    /// A shell started a program, eg. "pacstrap -K /mnt base"
+    Command(String),
+}
+
+impl Progress {
+    /// Fraction of the current step that is done, None if it cannot be known
+    pub fn ratio(&self) -> Option<f64> {
+        let (current, total) = match self {
+            Progress::Downloading {
+                bytes, total_bytes, ..
+            } if *total_bytes > 0 => return Some((*bytes as f64 / *total_bytes as f64).min(1.0)),
+            Progress::Downloading { done, total, .. } => (*done, *total),
+            Progress::Installing { current, total, .. }
+            | Progress::Removing { current, total, .. }
+            | Progress::RunningHooks { current, total, .. }
+            | Progress::Step { current, total, .. }
+            | Progress::StepFinished { current, total, .. } => (*current, *total),
+            Progress::Resolving | Progress::Command(_) => return None,
+            Progress::Done => return Some(1.0),
+        };
+        if total == 0 {
+            None
+        } else {
+            Some((current as f64 / total as f64).min(1.0))
+        }
+    }
+
+    /// eg. "installing linux (12/40)"
+    pub fn label(&self) -> String {
+        match self {
+            Progress::Resolving => "resolving dependencies".to_string(),
+            Progress::Downloading {
+                package,
+                done,
+                total,
+                bytes,
+                total_bytes,
+            } => {
+                if *total_bytes > 0 {
+                    format!(
+                        "downloading {} ({}/{}, {:.1}/{:.1} MiB)",
+                        package,
+                        done,
+                        total,
+                        *bytes as f64 / MIB,
+                        *total_bytes as f64 / MIB
+                    )
+                } else {
+                    format!("downloading {} ({}/{})", package, done, total)
+                }
+            }
+            Progress::Installing {
+                package,
+                current,
+                total,
+            } => format!("installing {} ({}/{})", package, current, total),
+            Progress::Removing {
+                package,
+                current,
+                total,
+            } => format!("removing {} ({}/{})", package, current, total),
+            Progress::RunningHooks {
+                hook,
+                current,
+                total,
+            } => format!("{} ({}/{})", hook, current, total),
+            Progress::Done => "done".to_string(),
+            Progress::Step {
+                phase,
+                name,
+                current,
+                total,
+            } => format!("[{}/{}] {}: {}", current, total, phase, name),
+            Progress::StepFinished {
+                current,
+                total,
+                status,
+                ..
+            } => format!("[{}/{}] {}", current, total, status),
+            Progress::Command(command) => format!("$ {}", command),
+        }
+    }
+}
+
+const MIB: f64 = 1024.0 * 1024.0;
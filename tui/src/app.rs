@@ -2,9 +2,14 @@ use std::rc::Rc;
 
 use installer::{
     base_installer::BaseInstaller,
+    config::{DiskConfig, InstallConfig},
     essentials::{Bootloader, Essentials, SuperUserUtility},
     filesystem_tasks::Filesystem,
+    mirrorlist::FALLBACK_COUNTRIES,
+    offline::OfflineSource,
     pacman::Pacman,
+    size_estimate::SizeEstimate,
+    utils::DEFAULT_TARGET_ROOT,
 };
 use ratatui::widgets::ListState;
 use shell_iface::logger::Logger;
@@ -29,8 +34,17 @@ pub enum SubScreens {
     EraseEFI,
     EraseHome,
     MountExtraPartition,
-    MountExtraPartition__Insert,
+    MountExtraPartitionInsert,
     ConfirmPartitions,
+    SetupTargetDirectory,
+    SetupTargetRoot,
+
+    /* Pacman */
+    SetupMirrors,
+    SetupPacmanOptions,
+    SetupCustomRepo,
+    SetupOfflineSource,
+    SetupArchiveSnapshot,
 
     /* Essentials */
     SetupSwap,
@@ -39,6 +53,9 @@ pub enum SubScreens {
     SetupHostname,
     SetupRootPassword,
     SetupBootloader,
+    SetupKernels,
+    SetupKernelCmdline,
+    SetupSecureBoot,
     SetupSuperUserUtility,
     SetupUser,
 
@@ -61,14 +78,15 @@ pub struct App<'a> {
     /* Lists */
     pub filesystem_drives_list: Rc<Vec<String>>,
     pub filesystem_partitions_list: Rc<Vec<String>>,
-    pub reflector_countries: Rc<Vec<&'a str>>,
+    pub reflector_countries: Rc<Vec<String>>,
+    /// Whether reflector was asked for the countries already
+    pub reflector_countries_loaded: bool,
     pub timezones: Rc<Vec<&'a str>>,
     pub swap_sizes_list: Rc<Vec<usize>>,
     pub locales_list: Rc<Vec<&'a str>>,
     pub text_controller: String,
 
     /* Selection and Method parameters */
-    pub selected_reflector_country: String,
     pub selected_timezone: String,
     pub selected_locale: String,
     pub selected_encoding: String,
@@ -77,6 +95,26 @@ pub struct App<'a> {
     pub password: String,
     pub root_password: String,
     pub hostname: String,
+    pub target_directory: String,
+    pub target_tarball: String,
+    pub target_root: String,
+    pub cmdline_resume: String,
+    pub cmdline_cryptdevice: String,
+    pub cmdline_extra: String,
+    pub mirror_server: String,
+    pub repo_name: String,
+    pub repo_server: String,
+    pub repo_sig_level: String,
+    pub repo_key_file: String,
+    pub offline_path: String,
+    pub offline_repo_name: String,
+    pub offline_is_cache: bool,
+    pub snapshot_date: String,
+    pub snapshot_keep_pinned: bool,
+    /// Worked out when the confirmation screen opens, the error if it could not be
+    pub size_estimate: Option<Result<SizeEstimate, String>>,
+    /// Where the config was exported to from the confirmation screen, or why it was not
+    pub export_status: Option<String>,
 
     /* Configuration state */
     pub filesystem: Filesystem<'a>,
@@ -101,17 +139,16 @@ impl<'a> App<'a> {
             text_controller: String::new(),
             error_console: String::new(),
             redraw_next_frame: false,
-            filesystem: Filesystem::new(&logger),
-            base_installer: BaseInstaller::new(&logger),
-            pacman: Pacman::new(&logger),
-            essentials: Essentials::new(&logger, Bootloader::Grub, SuperUserUtility::Sudo),
+            filesystem: Filesystem::new(logger),
+            base_installer: BaseInstaller::new(logger),
+            pacman: Pacman::new(logger),
+            essentials: Essentials::new(logger, Bootloader::Grub, SuperUserUtility::Sudo),
             filesystem_drives_list: Rc::new(Vec::new()),
             filesystem_partitions_list: Rc::new(Vec::new()),
             filesystem_setup_complete: false,
             pacman_setup_complete: false,
             essentials_setup_complete: false,
 
-            selected_reflector_country: String::new(),
             selected_timezone: String::new(),
             selected_locale: String::new(),
             selected_encoding: String::new(),
@@ -120,82 +157,31 @@ impl<'a> App<'a> {
             password: String::new(),
             root_password: String::new(),
             hostname: String::new(),
+            target_directory: String::new(),
+            target_tarball: String::new(),
+            target_root: DEFAULT_TARGET_ROOT.to_string(),
+            cmdline_resume: String::new(),
+            cmdline_cryptdevice: String::new(),
+            cmdline_extra: String::new(),
+            mirror_server: String::new(),
+            repo_name: String::new(),
+            repo_server: String::new(),
+            repo_sig_level: String::new(),
+            repo_key_file: String::new(),
+            offline_path: String::new(),
+            offline_repo_name: String::new(),
+            offline_is_cache: false,
+            snapshot_date: String::new(),
+            snapshot_keep_pinned: false,
+            size_estimate: None,
+            export_status: None,
             start_installation: false,
 
             swap_sizes_list: Rc::new(vec![1, 2, 4, 8, 16, 32, 64]),
-            reflector_countries: Rc::new(vec![
-                "India",
-                "Australia",
-                "Austria",
-                "Azerbaijan",
-                "Bangladesh",
-                "Belarus",
-                "Belgium",
-                "Bosnia and Herzegovina",
-                "Brazil",
-                "Bulgaria",
-                "Cambodia",
-                "Canada",
-                "Chile",
-                "China",
-                "Colombia",
-                "Croatia",
-                "Czechia",
-                "Denmark",
-                "Ecuador",
-                "Estonia",
-                "Finland",
-                "France",
-                "Georgia",
-                "Germany",
-                "Greece",
-                "Hong Kong",
-                "Hungary",
-                "Iceland",
-                "India",
-                "Indonesia",
-                "Iran",
-                "Israel",
-                "Italy",
-                "Japan",
-                "Kazakhstan",
-                "Kenya",
-                "Latvia",
-                "Lithuania",
-                "Luxembourg",
-                "Mauritius",
-                "Mexico",
-                "Moldova",
-                "Monaco",
-                "Netherlands",
-                "New Caledonia",
-                "New Zealand",
-                "North Macedonia",
-                "Norway",
-                "Paraguay",
-                "Poland",
-                "Portugal",
-                "Romania",
-                "Russia",
-                "Réunion",
-                "Serbia",
-                "Singapore",
-                "Slovakia",
-                "Slovenia",
-                "South Africa",
-                "South Korea",
-                "Spain",
-                "Sweden",
-                "Switzerland",
-                "Taiwan",
-                "Thailand",
-                "Türkiye",
-                "Ukraine",
-                "United Kingdom",
-                "United States",
-                "Uzbekistan",
-                "Vietnam",
-            ]),
+            reflector_countries: Rc::new(
+                FALLBACK_COUNTRIES.iter().map(|x| x.to_string()).collect(),
+            ),
+            reflector_countries_loaded: false,
             timezones: Rc::new(vec![
                 "Asia/Kolkata",
                 "Africa/Abidjan",
@@ -1293,3 +1279,86 @@ impl<'a> App<'a> {
         }
     }
 }
+
+impl<'a> App<'a> {
+    /// Fills every screen in from a config, eg. one a teammate exported.
+    /// Disks that the config would wipe are left for the filesystem screen to partition.
+    /// Sections that are complete are marked as set up, passwords left out are asked for again.
+    pub fn load_config(&mut self, config: &InstallConfig) {
+        config.apply_settings(&mut self.base_installer, &mut self.essentials, &mut self.pacman);
+        self.pacman_setup_complete = true;
+
+        match &config.disk {
+            DiskConfig::Wipe { device, .. } => {
+                self.error_console = format!(
+                    "The config wipes {}, partition it on the filesystem screen instead.",
+                    device
+                );
+            }
+            disk => {
+                match disk {
+                    DiskConfig::Directory { path, tarball } => {
+                        self.target_directory = path.clone();
+                        self.target_tarball = tarball.clone().unwrap_or_default();
+                    }
+                    DiskConfig::Partitions { target_root, .. } => {
+                        self.target_root = target_root
+                            .clone()
+                            .unwrap_or(DEFAULT_TARGET_ROOT.to_string());
+                    }
+                    _ => {}
+                }
+                match config.apply_disk(&mut self.filesystem) {
+                    Ok(_) => self.filesystem_setup_complete = true,
+                    Err(e) => self.error_console = e.to_string(),
+                }
+            }
+        }
+
+        self.selected_timezone = config.timezone.clone();
+        self.selected_locale = config.locale.clone();
+        self.selected_encoding = config.encoding.clone();
+        self.swap_size = config.swap;
+        self.hostname = config.hostname.clone();
+        self.root_password = config.root_password.clone();
+        if let Some(user) = config.users.first() {
+            self.username = user.name.clone();
+            self.password = user.password.clone();
+        }
+        self.essentials_setup_complete = !self.username.is_empty()
+            && !self.password.is_empty()
+            && !self.root_password.is_empty();
+
+        let cmdline = &config.kernel_cmdline;
+        self.cmdline_resume = cmdline.resume.clone().unwrap_or_default();
+        self.cmdline_cryptdevice = cmdline.cryptdevice.clone().unwrap_or_default();
+        self.cmdline_extra = cmdline.extra.join(" ");
+        if let Some(snapshot) = &config.snapshot {
+            self.snapshot_date = snapshot.date.clone();
+            self.snapshot_keep_pinned = snapshot.keep_pinned;
+        }
+        if let Some(offline) = &config.offline {
+            self.offline_path = offline.path().to_string();
+            self.offline_is_cache = matches!(offline, OfflineSource::Cache { .. });
+            if let OfflineSource::Repo { name, .. } = offline {
+                self.offline_repo_name = name.clone();
+            }
+        }
+    }
+
+    /// Writes what was picked on every screen to a config, passwords left out.
+    pub fn export_config(&self, path: &str) -> anyhow::Result<()> {
+        InstallConfig::from_installer(
+            &self.filesystem,
+            &self.essentials,
+            &self.pacman,
+            &self.selected_timezone,
+            &self.selected_locale,
+            &self.selected_encoding,
+            self.swap_size,
+            &self.username,
+            &self.hostname,
+        )?
+        .save(path)
+    }
+}
@@ -1,5 +1,9 @@
 use crossterm::event::{KeyCode, KeyEvent};
-use installer::essentials::{Bootloader, SuperUserUtility};
+use installer::{
+    essentials::{Bootloader, SuperUserUtility},
+    kernel::Kernel,
+    secure_boot::SecureBoot,
+};
 
 use crate::app::{App, Screens, SubScreens};
 
@@ -11,11 +15,12 @@ pub fn essentials_events(app: &mut App, key: KeyEvent) {
         SubScreens::SetupHostname => setup_hostname_events(app, key),
         SubScreens::SetupRootPassword => setup_root_password_events(app, key),
         SubScreens::SetupBootloader => setup_boot_loader_events(app, key),
+        SubScreens::SetupKernels => setup_kernels_events(app, key),
+        SubScreens::SetupKernelCmdline => setup_kernel_cmdline_events(app, key),
+        SubScreens::SetupSecureBoot => setup_secure_boot_events(app, key),
         SubScreens::SetupUser => setup_user_events(app, key),
         SubScreens::SetupSuperUserUtility => setup_super_user_events(app, key),
-        SubScreens::None => match key.code {
-            _ => app.current_sub_screen = SubScreens::SetupSwap,
-        },
+        SubScreens::None => app.current_sub_screen = SubScreens::SetupSwap,
         _ => {}
     }
 }
@@ -27,12 +32,11 @@ fn setup_user_events(app: &mut App<'_>, key: KeyEvent) {
         KeyCode::Up => {
             match app.list_selection.selected() {
                 Some(x) => {
-                    let index: usize;
-                    if x == 0 {
-                        index = total_list_item - 1;
+                    let index = if x == 0 {
+                        total_list_item - 1
                     } else {
-                        index = x - 1;
-                    }
+                        x - 1
+                    };
                     app.list_selection.select(Some(index));
                 }
                 None => {
@@ -91,12 +95,11 @@ fn setup_super_user_events(app: &mut App<'_>, key: KeyEvent) {
         KeyCode::Up | KeyCode::Char('k') => {
             match app.list_selection.selected() {
                 Some(x) => {
-                    let index: usize;
-                    if x == 0 {
-                        index = total_list_item - 1;
+                    let index = if x == 0 {
+                        total_list_item - 1
                     } else {
-                        index = x - 1;
-                    }
+                        x - 1
+                    };
                     app.list_selection.select(Some(index));
                 }
                 None => {
@@ -124,6 +127,181 @@ fn setup_super_user_events(app: &mut App<'_>, key: KeyEvent) {
             app.current_sub_screen = SubScreens::SetupUser;
             app.list_selection.select(Some(0));
         }
+        KeyCode::Esc | KeyCode::Char('q') => {
+            app.current_sub_screen = SubScreens::SetupSecureBoot;
+            app.list_selection.select(Some(0));
+        }
+        _ => {}
+    }
+}
+
+fn setup_secure_boot_events(app: &mut App<'_>, key: KeyEvent) {
+    let total_list_item = 3;
+    match key.code {
+        KeyCode::Up | KeyCode::Char('k') => {
+            match app.list_selection.selected() {
+                Some(x) => {
+                    let index = if x == 0 {
+                        total_list_item - 1
+                    } else {
+                        x - 1
+                    };
+                    app.list_selection.select(Some(index));
+                }
+                None => {
+                    app.list_selection.select(Some(0));
+                }
+            };
+        }
+        KeyCode::Down | KeyCode::Char('j') => {
+            match app.list_selection.selected() {
+                Some(x) => {
+                    app.list_selection.select(Some((x + 1) % total_list_item));
+                }
+                None => {
+                    app.list_selection.select(Some(0));
+                }
+            };
+        }
+        KeyCode::Enter => {
+            let selection = app.list_selection.selected().unwrap();
+            app.essentials.secure_boot = match selection {
+                0 => None,
+                1 => Some(SecureBoot {
+                    microsoft_keys: true,
+                }),
+                _ => Some(SecureBoot {
+                    microsoft_keys: false,
+                }),
+            };
+            app.current_sub_screen = SubScreens::SetupSuperUserUtility;
+            app.list_selection.select(Some(0));
+        }
+        KeyCode::Esc | KeyCode::Char('q') => {
+            app.current_sub_screen = SubScreens::SetupKernelCmdline;
+            app.list_selection.select(Some(0));
+        }
+        _ => {}
+    }
+}
+
+fn setup_kernel_cmdline_events(app: &mut App<'_>, key: KeyEvent) {
+    // 4 toggles, 3 text fields and Continue
+    let total_list_item = 8;
+    let selection = app.list_selection.selected().unwrap_or(0);
+    let cmdline = &mut app.essentials.kernel_cmdline;
+    match key.code {
+        KeyCode::Up => {
+            let index = if selection == 0 {
+                total_list_item - 1
+            } else {
+                selection - 1
+            };
+            app.list_selection.select(Some(index));
+        }
+        KeyCode::Down | KeyCode::Tab => {
+            app.list_selection.select(Some((selection + 1) % total_list_item));
+        }
+        KeyCode::Enter | KeyCode::Char(' ') if selection < 4 => match selection {
+            0 => cmdline.quiet = !cmdline.quiet,
+            1 => cmdline.splash = !cmdline.splash,
+            2 => cmdline.nvidia_modeset = !cmdline.nvidia_modeset,
+            _ => cmdline.serial_console = !cmdline.serial_console,
+        },
+        KeyCode::Char(x) => match selection {
+            4 => app.cmdline_resume.push(x),
+            5 => app.cmdline_cryptdevice.push(x),
+            6 => app.cmdline_extra.push(x),
+            _ => {}
+        },
+        KeyCode::Backspace => match selection {
+            4 => {
+                app.cmdline_resume.pop();
+            }
+            5 => {
+                app.cmdline_cryptdevice.pop();
+            }
+            6 => {
+                app.cmdline_extra.pop();
+            }
+            _ => {}
+        },
+        KeyCode::Enter if selection < total_list_item - 1 => {
+            app.list_selection.select(Some(selection + 1));
+        }
+        KeyCode::Enter => {
+            let optional = |x: &str| {
+                let x = x.trim();
+                if x.is_empty() {
+                    None
+                } else {
+                    Some(x.to_string())
+                }
+            };
+            cmdline.resume = optional(&app.cmdline_resume);
+            cmdline.cryptdevice = optional(&app.cmdline_cryptdevice);
+            cmdline.set_extra(&app.cmdline_extra);
+            app.current_sub_screen = SubScreens::SetupSecureBoot;
+            app.list_selection.select(Some(0));
+        }
+        KeyCode::Esc => {
+            app.current_sub_screen = SubScreens::SetupKernels;
+            app.list_selection.select(Some(0));
+        }
+        _ => {}
+    }
+}
+
+fn setup_kernels_events(app: &mut App<'_>, key: KeyEvent) {
+    // every kernel and Continue
+    let total_list_item = Kernel::ALL.len() + 1;
+    match key.code {
+        KeyCode::Up | KeyCode::Char('k') => {
+            match app.list_selection.selected() {
+                Some(x) => {
+                    let index = if x == 0 {
+                        total_list_item - 1
+                    } else {
+                        x - 1
+                    };
+                    app.list_selection.select(Some(index));
+                }
+                None => {
+                    app.list_selection.select(Some(0));
+                }
+            };
+        }
+        KeyCode::Down | KeyCode::Char('j') => {
+            match app.list_selection.selected() {
+                Some(x) => {
+                    app.list_selection.select(Some((x + 1) % total_list_item));
+                }
+                None => {
+                    app.list_selection.select(Some(0));
+                }
+            };
+        }
+        KeyCode::Enter | KeyCode::Char(' ') => {
+            let selection = app.list_selection.selected().unwrap();
+            if selection == total_list_item - 1 {
+                if app.essentials.kernels.is_empty() {
+                    app.error_console = "Select at least one kernel.".to_string();
+                    return;
+                }
+                app.current_sub_screen = SubScreens::SetupKernelCmdline;
+                app.list_selection.select(Some(0));
+                return;
+            }
+
+            // toggle, kernels are kept in the order they were selected
+            let kernel = Kernel::ALL[selection];
+            match app.essentials.kernels.iter().position(|x| *x == kernel) {
+                Some(x) => {
+                    app.essentials.kernels.remove(x);
+                }
+                None => app.essentials.kernels.push(kernel),
+            }
+        }
         KeyCode::Esc | KeyCode::Char('q') => {
             app.current_sub_screen = SubScreens::SetupBootloader;
             app.list_selection.select(Some(0));
@@ -133,17 +311,16 @@ fn setup_super_user_events(app: &mut App<'_>, key: KeyEvent) {
 }
 
 fn setup_boot_loader_events(app: &mut App<'_>, key: KeyEvent) {
-    let total_list_item = 2;
+    let total_list_item = 3;
     match key.code {
         KeyCode::Up | KeyCode::Char('k') => {
             match app.list_selection.selected() {
                 Some(x) => {
-                    let index: usize;
-                    if x == 0 {
-                        index = total_list_item - 1;
+                    let index = if x == 0 {
+                        total_list_item - 1
                     } else {
-                        index = x - 1;
-                    }
+                        x - 1
+                    };
                     app.list_selection.select(Some(index));
                 }
                 None => {
@@ -168,7 +345,8 @@ fn setup_boot_loader_events(app: &mut App<'_>, key: KeyEvent) {
             } else {
                 app.essentials.bootloader = Bootloader::SystemDBoot;
             }
-            app.current_sub_screen = SubScreens::SetupSuperUserUtility;
+            app.essentials.unified_kernel_images = selection == 2;
+            app.current_sub_screen = SubScreens::SetupKernels;
             app.list_selection.select(Some(0));
         }
         KeyCode::Esc | KeyCode::Char('q') => {
@@ -225,12 +403,11 @@ fn setup_locale_events(app: &mut App<'_>, key: KeyEvent) {
         KeyCode::Up | KeyCode::Char('k') => {
             match app.list_selection.selected() {
                 Some(x) => {
-                    let index: usize;
-                    if x == 0 {
-                        index = total_list_item - 1;
+                    let index = if x == 0 {
+                        total_list_item - 1
                     } else {
-                        index = x - 1;
-                    }
+                        x - 1
+                    };
                     app.list_selection.select(Some(index));
                 }
                 None => {
@@ -251,7 +428,7 @@ fn setup_locale_events(app: &mut App<'_>, key: KeyEvent) {
         KeyCode::Enter => {
             let selection = app.list_selection.selected().unwrap();
             let (locale, encoding) = app.locales_list[selection].split_once(' ').unwrap();
-            app.selected_encoding = locale.to_string();
+            app.selected_locale = locale.to_string();
             app.selected_encoding = encoding.to_string();
             app.current_sub_screen = SubScreens::SetupHostname;
             app.list_selection.select(Some(0));
@@ -270,12 +447,11 @@ fn setup_timezone_events(app: &mut App<'_>, key: KeyEvent) {
         KeyCode::Up | KeyCode::Char('k') => {
             match app.list_selection.selected() {
                 Some(x) => {
-                    let index: usize;
-                    if x == 0 {
-                        index = total_list_item - 1;
+                    let index = if x == 0 {
+                        total_list_item - 1
                     } else {
-                        index = x - 1;
-                    }
+                        x - 1
+                    };
                     app.list_selection.select(Some(index));
                 }
                 None => {
@@ -297,7 +473,15 @@ fn setup_timezone_events(app: &mut App<'_>, key: KeyEvent) {
             let selection = app.list_selection.selected().unwrap();
             app.selected_timezone = app.timezones[selection].to_string();
             app.current_sub_screen = SubScreens::SelectLocale;
-            app.list_selection.select(Some(0));
+            let index = app
+                .locales_list
+                .iter()
+                .position(|x| {
+                    x.split_once(' ')
+                        == Some((app.selected_locale.as_str(), app.selected_encoding.as_str()))
+                })
+                .unwrap_or(0);
+            app.list_selection.select(Some(index));
         }
         KeyCode::Esc | KeyCode::Char('q') => {
             app.current_sub_screen = SubScreens::SetupSwap;
@@ -313,12 +497,11 @@ pub fn setup_swap_events(app: &mut App, key: KeyEvent) {
         KeyCode::Up | KeyCode::Char('k') => {
             match app.list_selection.selected() {
                 Some(x) => {
-                    let index: usize;
-                    if x == 0 {
-                        index = total_list_item - 1;
+                    let index = if x == 0 {
+                        total_list_item - 1
                     } else {
-                        index = x - 1;
-                    }
+                        x - 1
+                    };
                     app.list_selection.select(Some(index));
                 }
                 None => {
@@ -340,7 +523,13 @@ pub fn setup_swap_events(app: &mut App, key: KeyEvent) {
             let selection = app.list_selection.selected().unwrap();
             app.swap_size = app.swap_sizes_list[selection];
             app.current_sub_screen = SubScreens::SelectTimezone;
-            app.list_selection.select(Some(0));
+            // starts on the one picked before, eg. from a loaded config
+            let index = app
+                .timezones
+                .iter()
+                .position(|x| *x == app.selected_timezone)
+                .unwrap_or(0);
+            app.list_selection.select(Some(index));
         }
         KeyCode::Esc | KeyCode::Char('q') => {
             app.current_screen = Screens::StartScreen;
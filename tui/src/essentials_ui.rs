@@ -6,6 +6,8 @@ use ratatui::{
     Frame,
 };
 
+use installer::kernel::Kernel;
+
 use crate::{
     app::{App, SubScreens},
     ui_utils::{centered_rect, show_none_screen},
@@ -19,6 +21,9 @@ pub fn essentials_ui(f: &mut Frame<'_>, chunk: Rect, app: &mut App<'_>) {
         SubScreens::SetupHostname => setup_hostname_ui(f, chunk, app),
         SubScreens::SetupRootPassword => setup_root_password_ui(f, chunk, app),
         SubScreens::SetupBootloader => setup_bootloader_ui(f, chunk, app),
+        SubScreens::SetupKernels => setup_kernels_ui(f, chunk, app),
+        SubScreens::SetupKernelCmdline => setup_kernel_cmdline_ui(f, chunk, app),
+        SubScreens::SetupSecureBoot => setup_secure_boot_ui(f, chunk, app),
         SubScreens::SetupUser => setup_user_ui(f, chunk, app),
         SubScreens::SetupSuperUserUtility => setup_superuser_ui(f, chunk, app),
         _ => show_none_screen(f, chunk, "Additional Configuration"),
@@ -94,7 +99,11 @@ fn setup_root_password_ui(f: &mut Frame<'_>, chunk: Rect, app: &mut App<'_>) {
 }
 
 fn setup_bootloader_ui(f: &mut Frame<'_>, chunk: Rect, app: &mut App<'_>) {
-    let list = List::new(vec!["Grub", "systemdBoot"])
+    let list = List::new(vec![
+        "Grub",
+        "systemdBoot",
+        "systemdBoot with unified kernel images",
+    ])
         .block(
             Block::default()
                 .title("Select the bootloader: ")
@@ -107,6 +116,78 @@ fn setup_bootloader_ui(f: &mut Frame<'_>, chunk: Rect, app: &mut App<'_>) {
     f.render_stateful_widget(list, chunk, &mut app.list_selection);
 }
 
+fn setup_kernels_ui(f: &mut Frame<'_>, chunk: Rect, app: &mut App<'_>) {
+    let mut items = Kernel::ALL
+        .iter()
+        .map(|x| {
+            let position = app.essentials.kernels.iter().position(|k| k == x);
+            match position {
+                Some(0) => format!("[x] {} (default)", x),
+                Some(_) => format!("[x] {}", x),
+                None => format!("[ ] {}", x),
+            }
+        })
+        .collect::<Vec<String>>();
+    items.push("Continue".to_string());
+
+    let list = List::new(items)
+        .block(
+            Block::default()
+                .title("Select one or more kernels, the first one selected boots by default: ")
+                .borders(Borders::ALL),
+        )
+        .highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+        .highlight_symbol(">>")
+        .repeat_highlight_symbol(true);
+
+    f.render_stateful_widget(list, chunk, &mut app.list_selection);
+}
+
+fn setup_kernel_cmdline_ui(f: &mut Frame<'_>, chunk: Rect, app: &mut App<'_>) {
+    let cmdline = &app.essentials.kernel_cmdline;
+    let toggle = |x: bool| if x { "[x]" } else { "[ ]" };
+    let items = vec![
+        format!("{} quiet", toggle(cmdline.quiet)),
+        format!("{} splash", toggle(cmdline.splash)),
+        format!("{} nvidia-drm.modeset=1", toggle(cmdline.nvidia_modeset)),
+        format!("{} console=ttyS0", toggle(cmdline.serial_console)),
+        format!("resume=      {}", app.cmdline_resume),
+        format!("cryptdevice= {}", app.cmdline_cryptdevice),
+        format!("extra:       {}", app.cmdline_extra),
+        "Continue".to_string(),
+    ];
+
+    let list = List::new(items)
+        .block(
+            Block::default()
+                .title("Kernel parameters, Enter toggles, type to fill the fields: ")
+                .borders(Borders::ALL),
+        )
+        .highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+        .highlight_symbol(">>")
+        .repeat_highlight_symbol(true);
+
+    f.render_stateful_widget(list, chunk, &mut app.list_selection);
+}
+
+fn setup_secure_boot_ui(f: &mut Frame<'_>, chunk: Rect, app: &mut App<'_>) {
+    let list = List::new(vec![
+        "Disabled",
+        "Sign with own keys and enroll Microsoft's keys too (recommended)",
+        "Sign with own keys only (some firmware and GPUs may fail to boot)",
+    ])
+    .block(
+        Block::default()
+            .title("Secure Boot (keys are enrolled only if the firmware is in setup mode): ")
+            .borders(Borders::ALL),
+    )
+    .highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+    .highlight_symbol(">>")
+    .repeat_highlight_symbol(true);
+
+    f.render_stateful_widget(list, chunk, &mut app.list_selection);
+}
+
 fn setup_user_ui(f: &mut Frame<'_>, chunk: Rect, app: &mut App<'_>) {
     let username_ui = Paragraph::new(Line::from(app.username.clone()))
         .style(Style::default().fg(Color::Yellow))
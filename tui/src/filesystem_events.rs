@@ -1,7 +1,7 @@
 use crate::app::{App, Screens, SubScreens};
 use crossterm::event::KeyCode;
 use crossterm::event::KeyEvent;
-use installer::utils::is_valid_mount_point;
+use installer::{target::InstallTarget, utils::is_valid_mount_point};
 use std::rc::Rc;
 
 pub fn filesystem_screen_events(app: &mut App, key: KeyEvent) {
@@ -11,13 +11,13 @@ pub fn filesystem_screen_events(app: &mut App, key: KeyEvent) {
         SubScreens::MountHome => mount_home_events(app, key),
         SubScreens::MountRoot => mount_root_events(app, key),
         SubScreens::MountExtraPartition => mount_extra_partitions(app, key),
-        SubScreens::MountExtraPartition__Insert => insert_extra_partitions(app, key),
+        SubScreens::MountExtraPartitionInsert => insert_extra_partitions(app, key),
         SubScreens::ConfirmPartitions => confirm_partitions_events(app, key),
         SubScreens::EraseEFI => erase_efi_events(app, key),
         SubScreens::EraseHome => erase_home_events(app, key),
-        SubScreens::None => match key.code {
-            _ => app.current_sub_screen = SubScreens::Partitioning,
-        },
+        SubScreens::SetupTargetDirectory => setup_target_directory_events(app, key),
+        SubScreens::SetupTargetRoot => setup_target_root_events(app, key),
+        SubScreens::None => app.current_sub_screen = SubScreens::Partitioning,
         _ => app.current_sub_screen = SubScreens::Partitioning,
     }
 }
@@ -28,12 +28,11 @@ fn partitioning_events(app: &mut App, key: KeyEvent) {
         KeyCode::Up | KeyCode::Char('k') => {
             match app.list_selection.selected() {
                 Some(x) => {
-                    let index: usize;
-                    if x == 0 {
-                        index = total_list_item - 1;
+                    let index = if x == 0 {
+                        total_list_item - 1
                     } else {
-                        index = x - 1;
-                    }
+                        x - 1
+                    };
                     app.list_selection.select(Some(index));
                 }
                 None => {
@@ -54,6 +53,12 @@ fn partitioning_events(app: &mut App, key: KeyEvent) {
         KeyCode::Enter => {
             let selection = app.list_selection.selected().unwrap();
             if selection == total_list_item - 1 {
+                app.current_sub_screen = SubScreens::SetupTargetDirectory;
+                app.list_selection.select(Some(0));
+            } else if selection == total_list_item - 2 {
+                if app.filesystem.target.is_directory() {
+                    app.filesystem.target = InstallTarget::default();
+                }
                 let list = app
                     .filesystem
                     .lsblk()
@@ -111,12 +116,11 @@ fn mount_boot_events(app: &mut App, key: KeyEvent) {
         KeyCode::Up | KeyCode::Char('k') => {
             match app.list_selection.selected() {
                 Some(x) => {
-                    let index: usize;
-                    if x == 0 {
-                        index = total_list_item - 1;
+                    let index = if x == 0 {
+                        total_list_item - 1
                     } else {
-                        index = x - 1;
-                    }
+                        x - 1
+                    };
                     app.list_selection.select(Some(index));
                 }
                 None => {
@@ -166,12 +170,11 @@ fn mount_root_events(app: &mut App, key: KeyEvent) {
         KeyCode::Up | KeyCode::Char('k') => {
             match app.list_selection.selected() {
                 Some(x) => {
-                    let index: usize;
-                    if x == 0 {
-                        index = total_list_item - 1;
+                    let index = if x == 0 {
+                        total_list_item - 1
                     } else {
-                        index = x - 1;
-                    }
+                        x - 1
+                    };
                     app.list_selection.select(Some(index));
                 }
                 None => {
@@ -224,12 +227,11 @@ fn mount_home_events(app: &mut App, key: KeyEvent) {
         KeyCode::Up | KeyCode::Char('k') => {
             match app.list_selection.selected() {
                 Some(x) => {
-                    let index: usize;
-                    if x == 0 {
-                        index = total_list_item - 1;
+                    let index = if x == 0 {
+                        total_list_item - 1
                     } else {
-                        index = x - 1;
-                    }
+                        x - 1
+                    };
                     app.list_selection.select(Some(index));
                 }
                 None => {
@@ -348,12 +350,11 @@ fn mount_extra_partitions(app: &mut App, key: KeyEvent) {
         KeyCode::Up | KeyCode::Char('k') => {
             match app.list_selection.selected() {
                 Some(x) => {
-                    let index: usize;
-                    if x == 0 {
-                        index = total_list_item - 1;
+                    let index = if x == 0 {
+                        total_list_item - 1
                     } else {
-                        index = x - 1;
-                    }
+                        x - 1
+                    };
                     app.list_selection.select(Some(index));
                 }
                 None => {
@@ -397,15 +398,13 @@ fn mount_extra_partitions(app: &mut App, key: KeyEvent) {
             // Add new partition
             if selected_index == app.filesystem.partitions.iter().len() {
                 app.list_selection.select(Some(0));
-                app.current_sub_screen = SubScreens::MountExtraPartition__Insert;
+                app.current_sub_screen = SubScreens::MountExtraPartitionInsert;
             }
-            // Continue after completion
+            // Pick where the partitions get mounted
             else if selected_index == app.filesystem.partitions.iter().len() + 1 {
-                app.filesystem_setup_complete = true;
                 app.tab_selection = 0;
                 app.list_selection.select(Some(0));
-                app.current_screen = Screens::StartScreen;
-                app.current_sub_screen = SubScreens::None;
+                app.current_sub_screen = SubScreens::SetupTargetRoot;
             }
         }
         KeyCode::Esc | KeyCode::Char('q') => {
@@ -423,12 +422,11 @@ fn insert_extra_partitions(app: &mut App, key: KeyEvent) {
         KeyCode::Up | KeyCode::Char('k') if app.tab_selection == 1 => {
             match app.list_selection.selected() {
                 Some(x) => {
-                    let index: usize;
-                    if x == 0 {
-                        index = total_list_item - 1;
+                    let index = if x == 0 {
+                        total_list_item - 1
                     } else {
-                        index = x - 1;
-                    }
+                        x - 1
+                    };
                     app.list_selection.select(Some(index));
                 }
                 None => {
@@ -496,3 +494,103 @@ fn insert_extra_partitions(app: &mut App, key: KeyEvent) {
         _ => {}
     }
 }
+
+fn setup_target_directory_events(app: &mut App, key: KeyEvent) {
+    let total_list_item = 2;
+    match key.code {
+        KeyCode::Up => {
+            match app.list_selection.selected() {
+                Some(x) => {
+                    let index = if x == 0 {
+                        total_list_item - 1
+                    } else {
+                        x - 1
+                    };
+                    app.list_selection.select(Some(index));
+                }
+                None => {
+                    app.list_selection.select(Some(0));
+                }
+            };
+        }
+        KeyCode::Down | KeyCode::Tab => {
+            match app.list_selection.selected() {
+                Some(x) => {
+                    app.list_selection.select(Some((x + 1) % total_list_item));
+                }
+                None => {
+                    app.list_selection.select(Some(0));
+                }
+            };
+        }
+        KeyCode::Char(x) => {
+            let selection = app.list_selection.selected().unwrap();
+            if selection == 0 {
+                app.target_directory.push(x);
+            } else {
+                app.target_tarball.push(x);
+            }
+        }
+        KeyCode::Backspace => {
+            let selection = app.list_selection.selected().unwrap();
+            if selection == 0 {
+                app.target_directory.pop();
+            } else {
+                app.target_tarball.pop();
+            }
+        }
+        KeyCode::Enter => {
+            let selection = app.list_selection.selected().unwrap();
+            if selection == 0 {
+                app.list_selection.select(Some(1));
+                return;
+            }
+
+            let tarball = app.target_tarball.clone();
+            if let Err(e) = app
+                .filesystem
+                .set_target_directory(&app.target_directory.clone(), Some(&tarball))
+            {
+                app.error_console = e.to_string();
+                return;
+            }
+
+            app.filesystem_setup_complete = true;
+            app.list_selection.select(Some(0));
+            app.current_screen = Screens::StartScreen;
+            app.current_sub_screen = SubScreens::None;
+        }
+        KeyCode::Esc => {
+            app.current_sub_screen = SubScreens::Partitioning;
+            app.list_selection.select(Some(0));
+        }
+        _ => {}
+    }
+}
+
+fn setup_target_root_events(app: &mut App, key: KeyEvent) {
+    match key.code {
+        KeyCode::Char(x) => {
+            app.target_root.push(x);
+        }
+        KeyCode::Backspace => {
+            app.target_root.pop();
+        }
+        KeyCode::Enter => {
+            if let Err(e) = app.filesystem.set_target_root(&app.target_root.clone()) {
+                app.error_console = e.to_string();
+                return;
+            }
+
+            app.filesystem_setup_complete = true;
+            app.list_selection.select(Some(0));
+            app.current_screen = Screens::StartScreen;
+            app.current_sub_screen = SubScreens::None;
+        }
+        KeyCode::Esc => {
+            app.current_sub_screen = SubScreens::MountExtraPartition;
+            app.list_selection.select(Some(0));
+        }
+        _ => {}
+    }
+}
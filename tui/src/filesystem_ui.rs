@@ -17,8 +17,10 @@ pub fn filesystem_screen_ui(f: &mut Frame, chunk: Rect, app: &mut App) {
         SubScreens::EraseEFI => erase_efi_ui(f, chunk, app),
         SubScreens::EraseHome => erase_home_ui(f, chunk, app),
         SubScreens::MountExtraPartition => mount_extra_partitions(f, chunk, app),
-        SubScreens::MountExtraPartition__Insert => mount_extra_partitions_insert(f, chunk, app),
+        SubScreens::MountExtraPartitionInsert => mount_extra_partitions_insert(f, chunk, app),
         SubScreens::ConfirmPartitions => confirm_partitions_ui(f, chunk, app),
+        SubScreens::SetupTargetDirectory => setup_target_directory_ui(f, chunk, app),
+        SubScreens::SetupTargetRoot => setup_target_root_ui(f, chunk, app),
         _ => show_none_screen(f, chunk, "Filesystem"),
     }
 }
@@ -418,3 +420,84 @@ pub fn confirm_partitions_ui(f: &mut Frame, chunk: Rect, app: &mut App) {
     f.render_widget(yes_msg, action_btn_layout[0]);
     f.render_widget(no_msg, action_btn_layout[1]);
 }
+
+pub fn setup_target_directory_ui(f: &mut Frame, chunk: Rect, app: &mut App) {
+    let directory_ui = Paragraph::new(Line::from(app.target_directory.clone()))
+        .style(Style::default().fg(Color::Yellow))
+        .alignment(Alignment::Left)
+        .block(
+            Block::default()
+                .title("Directory to install into (eg. /var/lib/machines/dev): ")
+                .borders(Borders::ALL)
+                .style(Style::default().fg(match app.list_selection.selected() {
+                    Some(0) => Color::Yellow,
+                    _ => Color::Red,
+                })),
+        );
+
+    let tarball_ui = Paragraph::new(Line::from(app.target_tarball.clone()))
+        .style(Style::default().fg(Color::Yellow))
+        .alignment(Alignment::Left)
+        .block(
+            Block::default()
+                .title("Pack into tarball (leave empty to skip): ")
+                .borders(Borders::ALL)
+                .style(Style::default().fg(match app.list_selection.selected() {
+                    Some(1) => Color::Yellow,
+                    _ => Color::Red,
+                })),
+        );
+
+    let msg = Paragraph::new(Line::from(
+        "Partitioning, bootloader, swap and fstab are skipped. Nothing will be formatted.",
+    ))
+    .style(Style::default().fg(Color::Yellow))
+    .block(
+        Block::default()
+            .borders(Borders::ALL)
+            .style(Style::default().fg(Color::Red)),
+    );
+
+    let layout = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(3),
+            Constraint::Length(3),
+            Constraint::Length(3),
+        ])
+        .split(centered_rect(70, 40, chunk));
+
+    f.render_widget(directory_ui, layout[0]);
+    f.render_widget(tarball_ui, layout[1]);
+    f.render_widget(msg, layout[2]);
+}
+
+pub fn setup_target_root_ui(f: &mut Frame, chunk: Rect, app: &mut App) {
+    let root_ui = Paragraph::new(Line::from(app.target_root.clone()))
+        .style(Style::default().fg(Color::Yellow))
+        .alignment(Alignment::Left)
+        .block(
+            Block::default()
+                .title("Mount the partitions at (eg. /mnt): ")
+                .borders(Borders::ALL)
+                .style(Style::default().fg(Color::Yellow)),
+        );
+
+    let msg = Paragraph::new(Line::from(
+        "The new system is built here before rebooting into it.",
+    ))
+    .style(Style::default().fg(Color::Yellow))
+    .block(
+        Block::default()
+            .borders(Borders::ALL)
+            .style(Style::default().fg(Color::Red)),
+    );
+
+    let layout = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(3), Constraint::Length(3)])
+        .split(centered_rect(70, 40, chunk));
+
+    f.render_widget(root_ui, layout[0]);
+    f.render_widget(msg, layout[1]);
+}
@@ -2,12 +2,24 @@ use crossterm::event::{KeyCode, KeyEvent};
 
 use crate::app::{App, Screens, SubScreens};
 
+/// Written to the working directory, eg. /root on the ISO
+const EXPORT_PATH: &str = "2lazy4arch.toml";
+
 pub fn install_screen_events(app: &mut App, key: KeyEvent) {
     if let SubScreens::ConfirmInstallation = app.current_sub_screen {
         match key.code {
             KeyCode::Char('y') => {
                 app.current_sub_screen = SubScreens::StartInstallation;
             }
+            KeyCode::Char('e') => {
+                let path = std::env::current_dir()
+                    .map(|x| x.join(EXPORT_PATH).display().to_string())
+                    .unwrap_or(EXPORT_PATH.to_string());
+                app.export_status = Some(match app.export_config(&path) {
+                    Ok(_) => format!("Exported to {}, passwords left out", path),
+                    Err(e) => format!("Export failed: {}", e),
+                });
+            }
             _ => {
                 app.current_screen = Screens::StartScreen;
                 app.list_selection.select(Some(0));
@@ -0,0 +1,165 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use installer::{
+    engine::{Phase, StepFailure},
+    plan::install_plan,
+};
+use shell_iface::progress::Progress;
+
+/// Lines of output kept for the log on the progress screen, all of it is in log.txt
+const LOG_LINES: usize = 1000;
+
+/// Sent from the install thread to the progress screen.
+pub enum InstallEvent {
+    Progress(Progress),
+    /// A line of output of a program, or a message of the installer
+    Output(String),
+    /// A step failed, the install waits for a Recovery to be sent back
+    StepFailed(StepFailure),
+    /// The install is over, with the error if it failed
+    Finished(Result<(), String>),
+}
+
+pub enum StepState {
+    Pending,
+    Running,
+    Finished { status: String, failed: bool },
+}
+
+pub struct StepLine {
+    pub phase: Phase,
+    pub name: &'static str,
+    pub state: StepState,
+}
+
+/// What the progress screen shows, put together from the events of the install.
+pub struct InstallProgress {
+    /// Every step of the plan, in order
+    pub steps: Vec<StepLine>,
+    /// The last program that was started
+    pub command: String,
+    /// Where the package transaction of the current step is, if it has one
+    pub transaction: Option<Progress>,
+    pub log: VecDeque<String>,
+    pub started: Instant,
+    /// The step that failed, while the install waits to be told what to do
+    pub failure: Option<StepFailure>,
+    /// How the install ended and how long it took
+    pub outcome: Option<(Result<(), String>, Duration)>,
+}
+
+impl InstallProgress {
+    pub fn new() -> InstallProgress {
+        InstallProgress {
+            steps: install_plan()
+                .iter()
+                .map(|x| StepLine {
+                    phase: x.phase,
+                    name: x.name,
+                    state: StepState::Pending,
+                })
+                .collect(),
+            command: String::new(),
+            transaction: None,
+            log: VecDeque::new(),
+            started: Instant::now(),
+            failure: None,
+            outcome: None,
+        }
+    }
+
+    pub fn apply(&mut self, event: InstallEvent) {
+        match event {
+            InstallEvent::Progress(Progress::Step { current, .. }) => {
+                if let Some(x) = self.steps.get_mut(current - 1) {
+                    x.state = StepState::Running;
+                }
+                self.transaction = None;
+            }
+            InstallEvent::Progress(Progress::StepFinished {
+                current,
+                status,
+                failed,
+                ..
+            }) => {
+                if let Some(x) = self.steps.get_mut(current - 1) {
+                    x.state = StepState::Finished { status, failed };
+                }
+                self.transaction = None;
+            }
+            InstallEvent::Progress(Progress::Command(x)) => self.command = x,
+            InstallEvent::Progress(Progress::Done) => self.transaction = None,
+            InstallEvent::Progress(x) => self.transaction = Some(x),
+            InstallEvent::Output(line) => {
+                self.log.push_back(line);
+                if self.log.len() > LOG_LINES {
+                    self.log.pop_front();
+                }
+            }
+            InstallEvent::StepFailed(failure) => {
+                if let Some(x) = self.steps.iter_mut().find(|x| x.name == failure.name) {
+                    x.state = StepState::Finished {
+                        status: "failed".to_string(),
+                        failed: true,
+                    };
+                }
+                self.failure = Some(failure);
+            }
+            InstallEvent::Finished(result) => {
+                self.outcome = Some((result, self.started.elapsed()));
+            }
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.outcome.is_some()
+    }
+
+    /// Stops counting once the install is over
+    pub fn elapsed(&self) -> Duration {
+        match &self.outcome {
+            Some((_, x)) => *x,
+            None => self.started.elapsed(),
+        }
+    }
+
+    /// The step being run, or the last one that was
+    pub fn current_step(&self) -> Option<&StepLine> {
+        self.steps
+            .iter()
+            .rev()
+            .find(|x| !matches!(x.state, StepState::Pending))
+    }
+
+    /// How many steps ended as done, skipped and failed, for the summary
+    pub fn counts(&self) -> (usize, usize, usize) {
+        let mut counts = (0, 0, 0);
+        for step in &self.steps {
+            match &step.state {
+                StepState::Finished { failed: true, .. } => counts.2 += 1,
+                StepState::Finished { status, .. } if status == "skipped" => counts.1 += 1,
+                StepState::Finished { .. } => counts.0 += 1,
+                _ => {}
+            }
+        }
+        counts
+    }
+}
+
+/// eg. 01:05:09 or 12:34
+pub fn format_elapsed(elapsed: Duration) -> String {
+    let seconds = elapsed.as_secs();
+    if seconds >= 3600 {
+        format!(
+            "{:02}:{:02}:{:02}",
+            seconds / 3600,
+            seconds % 3600 / 60,
+            seconds % 60
+        )
+    } else {
+        format!("{:02}:{:02}", seconds / 60, seconds % 60)
+    }
+}
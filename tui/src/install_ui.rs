@@ -1,14 +1,20 @@
 use ratatui::{
-    layout::{Alignment, Rect},
-    style::{Color, Style},
-    text::Text,
-    widgets::{Block, Borders, Paragraph, Wrap},
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Span, Text},
+    widgets::{Block, Borders, Clear, Gauge, Paragraph, Wrap},
     Frame,
 };
 
+use installer::{
+    cmdline::InitramfsHooks, pacman::CacheSharing, size_estimate::format_size,
+    target::InstallTarget,
+};
+
 use crate::{
     app::{App, SubScreens},
-    ui_utils::show_none_screen,
+    install_progress::{format_elapsed, InstallProgress, StepState},
+    ui_utils::{centered_rect, show_none_screen},
 };
 
 pub fn install_screen_ui(f: &mut Frame<'_>, chunk: Rect, app: &mut App<'_>) {
@@ -23,17 +29,23 @@ pub fn install_confirm_screen_ui(f: &mut Frame<'_>, chunk: Rect, app: &mut App<'
     let setting_text = format!(
         "
 Filesystem:
-/boot: {} | erase: {}
-/root: {} | erase: true
-/home: {} | erase: {}
+{}
 
 ---
 Pacman:
-Mirror country: {}
+Mirrors: {}
+{}
+
+---
+Size:
+{}
 
 ---
 Misc Settings:
 bootloader: {}
+kernels: {}
+kernel cmdline: {}
+secure boot: {}
 superuser utility: {}
 swap space: {} GB
 locale: {} {}
@@ -43,23 +55,34 @@ hostname: {}
 username: {}
 
 [Y] to install
+[E] to export these settings as a config
+{}
         ",
-        app.filesystem.get_boot().unwrap(),
-        app.filesystem.format_boot,
-        app.filesystem.get_root().unwrap(),
-        match app.filesystem.get_home() {
-            Some(x) => x,
-            None => "No home partition selected".to_string(),
-        },
-        match app.filesystem.get_home() {
-            Some(_) => app.filesystem.format_home.to_string(),
-            None => "no".to_string(),
-        },
-        app.selected_reflector_country,
+        filesystem_summary(app),
+        mirrors_summary(app),
+        pacman_summary(app),
+        size_summary(app),
         match app.essentials.bootloader {
             installer::essentials::Bootloader::Grub => "Grub",
+            installer::essentials::Bootloader::SystemDBoot
+                if app.essentials.unified_kernel_images =>
+            {
+                "systemd boot (unified kernel images)"
+            }
             installer::essentials::Bootloader::SystemDBoot => "systemd boot",
         },
+        app.essentials
+            .kernels
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<String>>()
+            .join(", "),
+        app.essentials.kernel_cmdline.render(InitramfsHooks::Busybox),
+        match &app.essentials.secure_boot {
+            None => "disabled",
+            Some(x) if x.microsoft_keys => "own keys + Microsoft keys",
+            Some(_) => "own keys only",
+        },
         match app.essentials.super_user_utility {
             installer::essentials::SuperUserUtility::Sudo => "sudo",
             installer::essentials::SuperUserUtility::Doas => "doas",
@@ -70,9 +93,20 @@ username: {}
         app.selected_timezone,
         app.hostname,
         app.username,
+        app.export_status.as_deref().unwrap_or(""),
     );
 
-    let text = Text::from(setting_text);
+    // warnings go first, in red, so they are seen without scrolling
+    let mut text = Text::default();
+    if let Some(Ok(estimate)) = &app.size_estimate {
+        for warning in estimate.warnings() {
+            text.lines.push(Line::styled(
+                warning,
+                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            ));
+        }
+    }
+    text.extend(Text::from(setting_text));
     let settings = Paragraph::new(text)
         .wrap(Wrap { trim: true })
         .scroll((1, 1))
@@ -86,7 +120,380 @@ username: {}
     f.render_widget(settings, chunk);
 }
 
+fn mirrors_summary(app: &App<'_>) -> String {
+    let mirrors = &app.pacman.mirrors;
+    let mut summary = Vec::new();
+    if mirrors.uses_reflector() {
+        summary.push(if mirrors.countries.is_empty() {
+            "every country".to_string()
+        } else {
+            mirrors.countries.join(", ")
+        });
+        if mirrors.https_only {
+            summary.push("https only".to_string());
+        }
+        if let Some(x) = mirrors.max_age {
+            summary.push(format!("synced within {} hours", x));
+        }
+        if let Some(x) = mirrors.count {
+            summary.push(format!("fastest {}", x));
+        }
+    }
+    if !mirrors.servers.is_empty() {
+        summary.push(format!("{} servers given by hand", mirrors.servers.len()));
+    }
+    summary.join(", ")
+}
+
+fn pacman_summary(app: &App<'_>) -> String {
+    let options = &app.pacman.options;
+    let mut enabled = Vec::new();
+    if options.color {
+        enabled.push("Color".to_string());
+    }
+    if let Some(x) = options.parallel_downloads {
+        enabled.push(format!("ParallelDownloads = {}", x));
+    }
+    if options.verbose_pkg_lists {
+        enabled.push("VerbosePkgLists".to_string());
+    }
+    if options.candy {
+        enabled.push("ILoveCandy".to_string());
+    }
+    if options.multilib {
+        enabled.push("multilib".to_string());
+    }
+    for repo in &options.repos {
+        enabled.push(format!("[{}]", repo.name));
+    }
+
+    let mut lines = vec![if enabled.is_empty() {
+        "pacman.conf: unchanged".to_string()
+    } else {
+        format!("pacman.conf: {}", enabled.join(", "))
+    }];
+    match app.pacman.cache_sharing {
+        CacheSharing::Separate => {}
+        CacheSharing::Copy => lines.push("live package cache: copied".to_string()),
+        CacheSharing::Bind => lines.push("live package cache: shared".to_string()),
+    }
+    if app.pacman.prefetch {
+        lines.push("packages downloaded before installing".to_string());
+    }
+    if let Some(x) = &app.pacman.snapshot {
+        lines.push(format!(
+            "Arch Linux Archive snapshot: {}{}",
+            x.date,
+            if x.keep_pinned { ", kept pinned" } else { "" }
+        ));
+    }
+    if let Some(x) = &app.pacman.offline {
+        lines.push(format!("Offline install from {}", x.path()));
+    }
+    lines.join("\n")
+}
+
+fn size_summary(app: &App<'_>) -> String {
+    let estimate = match &app.size_estimate {
+        Some(Ok(x)) => x,
+        Some(Err(e)) => return format!("could not be estimated: {}", e),
+        None => return "not estimated".to_string(),
+    };
+
+    let mut lines = estimate
+        .sets
+        .iter()
+        .map(|(name, size)| {
+            format!(
+                "{}: {} packages, {} download, {} installed",
+                name,
+                size.packages,
+                format_size(size.download_size),
+                format_size(size.installed_size)
+            )
+        })
+        .collect::<Vec<String>>();
+    if estimate.swap > 0 {
+        lines.push(format!("swapfile: {}", format_size(estimate.swap)));
+    }
+    lines.push(format!(
+        "total: {} download, {} on /{}",
+        format_size(estimate.download_size()),
+        format_size(estimate.required()),
+        if estimate.cache_on_target {
+            " (package cache included)"
+        } else {
+            ""
+        }
+    ));
+    match &estimate.root_space {
+        Some((root, space)) => lines.push(format!("available: {} on {}", format_size(*space), root)),
+        None => lines.push("available: unknown".to_string()),
+    }
+    for (name, size) in &estimate.desktops {
+        lines.push(format!(
+            "after reboot, {} adds {}",
+            name,
+            format_size(size.installed_size)
+        ));
+    }
+    lines.join("\n")
+}
+
+fn filesystem_summary(app: &App<'_>) -> String {
+    match &app.filesystem.target {
+        InstallTarget::Directory { root, tarball } => format!(
+            "directory: {} | tarball: {}
+no partitions, bootloader, swap or fstab",
+            root,
+            tarball.as_deref().unwrap_or("none")
+        ),
+        InstallTarget::Partitions { root } => format!(
+            "mounted at: {}
+/boot: {} | erase: {}
+/root: {} | erase: true
+/home: {} | erase: {}",
+            root,
+            app.filesystem.get_boot().unwrap(),
+            app.filesystem.format_boot,
+            app.filesystem.get_root().unwrap(),
+            match app.filesystem.get_home() {
+                Some(x) => x,
+                None => "No home partition selected".to_string(),
+            },
+            match app.filesystem.get_home() {
+                Some(_) => app.filesystem.format_home.to_string(),
+                None => "no".to_string(),
+            },
+        ),
+    }
+}
+
 pub fn install_start_screen_ui(f: &mut Frame<'_>, chunk: Rect, _: &mut App<'_>) {
     let settings = Paragraph::new("Press Y to start installation? Although unlikely, I am not responsible if this installer does some damages to your system :)");
     f.render_widget(settings, chunk);
 }
+
+/// The whole screen while installing: the steps, what is running and the output.
+/// Drawn without the App, the install thread has its installers.
+pub fn install_progress_ui(f: &mut Frame<'_>, progress: &InstallProgress) {
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(3),
+            Constraint::Min(1),
+            Constraint::Length(3),
+        ])
+        .split(f.size());
+    let body = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(35), Constraint::Percentage(65)])
+        .split(chunks[1]);
+    let right = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(5),
+            Constraint::Length(3),
+            Constraint::Min(3),
+        ])
+        .split(body[1]);
+
+    let title = Paragraph::new(Text::styled(
+        "2Lazy4Arch: Install Arch Fast",
+        Style::default().fg(Color::Green),
+    ))
+    .block(Block::default().borders(Borders::ALL));
+    f.render_widget(title, chunks[0]);
+
+    steps_ui(f, body[0], progress);
+    current_step_ui(f, right[0], progress);
+
+    let (ratio, label) = match &progress.transaction {
+        Some(x) => (x.ratio().unwrap_or(0.0), x.label()),
+        None => (0.0, String::new()),
+    };
+    let gauge = Gauge::default()
+        .block(Block::default().title("Packages").borders(Borders::ALL))
+        .gauge_style(Style::default().fg(Color::Green))
+        .ratio(ratio)
+        .label(label);
+    f.render_widget(gauge, right[1]);
+
+    // the newest lines that fit, the rest is in log.txt
+    let height = right[2].height.saturating_sub(2) as usize;
+    let log = progress
+        .log
+        .iter()
+        .skip(progress.log.len().saturating_sub(height))
+        .map(|x| Line::from(x.as_str()))
+        .collect::<Vec<Line>>();
+    let log = Paragraph::new(log)
+        .style(Style::default().fg(Color::Gray))
+        .block(Block::default().title("Output").borders(Borders::ALL));
+    f.render_widget(log, right[2]);
+
+    let footer = match &progress.outcome {
+        None if progress.failure.is_some() => Span::styled(
+            "(r) retry / (s) skip / (c) shell in the new system / (a) abort and undo",
+            Style::default().fg(Color::Red),
+        ),
+        None => Span::styled(
+            format!(
+                "Elapsed {} | installing, please wait",
+                format_elapsed(progress.elapsed())
+            ),
+            Style::default().fg(Color::Yellow),
+        ),
+        Some((Ok(_), elapsed)) => Span::styled(
+            format!(
+                "Installed in {} | (q) or (enter) to quit",
+                format_elapsed(*elapsed)
+            ),
+            Style::default().fg(Color::Green),
+        ),
+        Some((Err(_), elapsed)) => Span::styled(
+            format!(
+                "Failed after {}, see log.txt | (q) or (enter) to quit",
+                format_elapsed(*elapsed)
+            ),
+            Style::default().fg(Color::Red),
+        ),
+    };
+    f.render_widget(
+        Paragraph::new(Line::from(footer)).block(Block::default().borders(Borders::ALL)),
+        chunks[2],
+    );
+
+    if progress.failure.is_some() {
+        failure_ui(f, centered_rect(80, 70, chunks[1]), progress);
+    }
+}
+
+/// What failed and what the failing program said, over the rest while the install waits
+fn failure_ui(f: &mut Frame<'_>, chunk: Rect, progress: &InstallProgress) {
+    let failure = match &progress.failure {
+        Some(x) => x,
+        None => return,
+    };
+    let mut text = Text::default();
+    text.lines.push(Line::styled(
+        format!("{}: {} failed", failure.phase, failure.name),
+        Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+    ));
+    text.lines.push(Line::from(failure.error.as_str()));
+    if let Some(command) = &failure.command {
+        text.lines.push(Line::from(""));
+        text.lines.push(Line::styled(
+            format!("$ {}", command.command),
+            Style::default().fg(Color::Yellow),
+        ));
+        // the end of stderr says what went wrong, the rest is in log.txt
+        let stderr = command.stderr.lines().collect::<Vec<&str>>();
+        for line in stderr.iter().skip(stderr.len().saturating_sub(15)) {
+            text.lines.push(Line::from(*line));
+        }
+    }
+    text.lines.push(Line::from(""));
+    if !failure.optional {
+        text.lines.push(Line::styled(
+            "The install needs this step, skip it only if it was done by hand.",
+            Style::default().fg(Color::Yellow),
+        ));
+    }
+    text.lines.push(Line::from(
+        "(r) retry / (s) skip / (c) shell in the new system / (a) abort and undo",
+    ));
+
+    let popup = Paragraph::new(text)
+        .wrap(Wrap { trim: false })
+        .block(
+            Block::default()
+                .title("Step failed")
+                .borders(Borders::ALL)
+                .style(Style::default().fg(Color::Red)),
+        );
+    f.render_widget(Clear, chunk);
+    f.render_widget(popup, chunk);
+}
+
+/// The checklist, grouped by phase, scrolled to keep the current step in view
+fn steps_ui(f: &mut Frame<'_>, chunk: Rect, progress: &InstallProgress) {
+    let mut lines = Vec::new();
+    let mut current_line = 0;
+    for (i, step) in progress.steps.iter().enumerate() {
+        if i == 0 || progress.steps[i - 1].phase != step.phase {
+            lines.push(Line::styled(
+                step.phase.to_string(),
+                Style::default().add_modifier(Modifier::BOLD),
+            ));
+        }
+        let (mark, status, color) = match &step.state {
+            StepState::Pending => ("  ", String::new(), Color::DarkGray),
+            StepState::Running => {
+                current_line = lines.len();
+                (">>", String::new(), Color::Yellow)
+            }
+            StepState::Finished {
+                failed: true,
+                status,
+            } if status.starts_with("skipped") => {
+                ("✘ ", " (skipped after failing)".to_string(), Color::Red)
+            }
+            StepState::Finished { failed: true, .. } => ("✘ ", " (failed)".to_string(), Color::Red),
+            StepState::Finished { status, .. } if status == "done" => {
+                ("✔ ", String::new(), Color::Green)
+            }
+            StepState::Finished { status, .. } => {
+                ("✔ ", format!(" ({})", status), Color::DarkGray)
+            }
+        };
+        lines.push(Line::styled(
+            format!(" {} {}{}", mark, step.name, status),
+            Style::default().fg(color),
+        ));
+    }
+
+    let height = chunk.height.saturating_sub(2) as usize;
+    let scroll = current_line.saturating_sub(height / 2) as u16;
+    let steps = Paragraph::new(lines)
+        .scroll((scroll, 0))
+        .block(Block::default().title("Steps").borders(Borders::ALL));
+    f.render_widget(steps, chunk);
+}
+
+/// The step and program that are running, or the summary once the install is over
+fn current_step_ui(f: &mut Frame<'_>, chunk: Rect, progress: &InstallProgress) {
+    let (done, skipped, failed) = progress.counts();
+    let (text, color) = match &progress.outcome {
+        None => (
+            format!(
+                "{}\n{}",
+                progress
+                    .current_step()
+                    .map(|x| format!("{}: {}", x.phase, x.name))
+                    .unwrap_or("Starting".to_string()),
+                progress.command.replace('\n', " ")
+            ),
+            Color::Yellow,
+        ),
+        Some((Ok(_), _)) => (
+            format!(
+                "Installation finished: {} steps done, {} skipped.\nSee the output for what to do next.",
+                done, skipped
+            ),
+            Color::Green,
+        ),
+        Some((Err(e), _)) => (
+            format!(
+                "Installation failed: {}\n{} steps done, {} skipped, {} failed. See the output and log.txt for what went wrong.",
+                e, done, skipped, failed
+            ),
+            Color::Red,
+        ),
+    };
+    let current = Paragraph::new(text)
+        .wrap(Wrap { trim: true })
+        .style(Style::default().fg(color))
+        .block(Block::default().title("Current").borders(Borders::ALL));
+    f.render_widget(current, chunk);
+}
@@ -4,6 +4,7 @@ mod essentials_ui;
 mod filesystem_events;
 mod filesystem_ui;
 mod install_events;
+mod install_progress;
 mod install_ui;
 mod pacman_events;
 mod pacman_ui;
@@ -23,20 +24,54 @@ use crossterm::terminal::{enable_raw_mode, EnterAlternateScreen};
 use essentials_events::essentials_events;
 use filesystem_events::filesystem_screen_events;
 use install_events::{install_screen_events, start_install_screen_events};
-use installer::install;
+use install_progress::{format_elapsed, InstallEvent, InstallProgress};
+use install_ui::install_progress_ui;
+use installer::config::InstallConfig;
+use installer::plan::InstallSettings;
+use installer::engine::{Recovery, StepFailure};
+use installer::{install, install_from_config, resume};
 use installer::utils::INSTALL_SUCCESS_FLAG;
 use pacman_events::pacman_screen_events;
 use post_install::run_post_install;
 use ratatui::backend::{Backend, CrosstermBackend};
 use ratatui::Terminal;
 use shell_iface::logger::Logger;
+use shell_iface::progress::Progress;
 use start_screen_events::start_screen_events;
 use std::error::Error;
 use std::fs::File;
 use std::io::{self, Read};
 use std::path::Path;
+use std::process::Command;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
 
 fn main() -> Result<(), Box<dyn Error>> {
+    // toolazy4arch install --config team.toml
+    let args: Vec<String> = std::env::args().collect();
+    // toolazy4arch resume /dev/sda2
+    if let (Some("resume"), Some(root)) = (args.get(1).map(|x| x.as_str()), args.get(2)) {
+        let mut logger = Logger::new(false);
+        logger.on_progress(print_progress);
+        if let Err(e) = resume(root, &logger) {
+            eprintln!("\nResuming failed: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    let config = config_path(&args);
+    if let Some((true, path)) = &config {
+        let mut logger = Logger::new(false);
+        logger.on_progress(print_progress);
+        File::create("log.txt")?;
+        if let Err(e) = install_from_config(path, &logger) {
+            eprintln!("\nInstallation failed: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     // if this is an installed system
     let file_path = INSTALL_SUCCESS_FLAG;
     if check_if_installed(file_path) {
@@ -54,9 +89,79 @@ fn main() -> Result<(), Box<dyn Error>> {
     let mut terminal = Terminal::new(backend)?;
 
     // create app and run it
-    let logger = Logger::new(false);
+    // everything the shells print goes to the progress screen instead of over the TUI
+    let (sender, events) = mpsc::channel();
+    let mut logger = Logger::new(false);
+    let progress_sender = sender.clone();
+    logger.on_progress(move |x| {
+        let _ = progress_sender.send(InstallEvent::Progress(x.clone()));
+    });
+    let output_sender = sender.clone();
+    logger.on_output(move |x| {
+        let _ = output_sender.send(InstallEvent::Output(x.to_string()));
+    });
     let mut app = App::new(&logger);
+    if let Some((false, path)) = &config {
+        match InstallConfig::load(path) {
+            Ok(x) => app.load_config(&x),
+            Err(e) => app.error_console = e.to_string(),
+        }
+    }
     let res = run_app(&mut terminal, &mut app);
+
+    let mut progress = InstallProgress::new();
+    let mut log_error = None;
+    let res = match res {
+        Ok(true) => match File::create("log.txt") {
+            Ok(_) => {
+                let settings = InstallSettings {
+                    timezone: app.selected_timezone.clone(),
+                    locale: app.selected_locale.clone(),
+                    encoding: app.selected_encoding.clone(),
+                    swap_size: app.swap_size,
+                    username: app.username.clone(),
+                    password: app.password.clone(),
+                    root_password: app.root_password.clone(),
+                    hostname: app.hostname.clone(),
+                };
+                // what was logged while the settings were picked
+                events.try_iter().for_each(drop);
+
+                // a failed step waits for the progress screen to send back what to do
+                let (decide, decisions) = mpsc::channel();
+                let failure_sender = sender.clone();
+                let mut ask = move |failure: &StepFailure| {
+                    let _ = failure_sender.send(InstallEvent::StepFailed(failure.clone()));
+                    decisions.recv().unwrap_or(Recovery::Abort)
+                };
+                thread::scope(|s| {
+                    s.spawn(|| {
+                        let result = install(
+                            &mut app.filesystem,
+                            &mut app.base_installer,
+                            &mut app.essentials,
+                            &mut app.pacman,
+                            &settings,
+                            &logger,
+                            Some(&mut ask),
+                        );
+                        let result = result.map_err(|e| e.to_string());
+                        let _ = sender.send(InstallEvent::Finished(result));
+                    });
+                    let result = run_install_screen(&mut terminal, &mut progress, &events, &decide);
+                    // a step still waiting for an answer aborts instead of blocking the scope
+                    drop(decide);
+                    result
+                })
+            }
+            Err(e) => {
+                log_error = Some(e);
+                Ok(())
+            }
+        },
+        Ok(false) => Ok(()),
+        Err(e) => Err(e),
+    };
     let _ = terminal.clear();
 
     disable_raw_mode()?;
@@ -67,41 +172,59 @@ fn main() -> Result<(), Box<dyn Error>> {
     )?;
     terminal.show_cursor()?;
 
-    if let Ok(do_install) = res {
-        if do_install {
-            println!("\nStarting installation");
+    if let Err(err) = res {
+        println!("{err:?}");
+    }
+    if let Some(e) = log_error {
+        eprintln!("Failed to create file: {}", e);
+    }
+    // the last messages stay on the terminal, eg. what to do after rebooting
+    if let Some((result, elapsed)) = &progress.outcome {
+        for line in progress.log.iter().skip(progress.log.len().saturating_sub(20)) {
+            println!("{}", line);
+        }
+        match result {
+            Ok(_) => println!("\nInstalled in {}.", format_elapsed(*elapsed)),
+            Err(e) => println!("\nInstallation failed: {}\nThe full log is in log.txt.", e),
+        }
+    }
 
-            // Create or open the file
-            match File::create("log.txt") {
-                Ok(file) => file,
-                Err(e) => {
-                    eprintln!("Failed to create file: {}", e);
-                    return Ok(());
-                }
-            };
+    Ok(())
+}
 
-            // install here
-            install(
-                &mut app.filesystem,
-                &mut app.base_installer,
-                &mut app.essentials,
-                &mut app.pacman,
-                &app.selected_reflector_country,
-                &app.selected_timezone,
-                &app.selected_locale,
-                &app.selected_encoding,
-                app.swap_size,
-                &app.username,
-                &app.password,
-                &app.root_password,
-                &app.hostname,
-            );
+/// `install --config <path>` installs without the TUI, `--config <path>` fills the TUI in.
+/// The bool is whether the install is unattended.
+fn config_path(args: &[String]) -> Option<(bool, String)> {
+    match args.get(1..) {
+        Some([command, flag, path, ..]) if command == "install" && flag == "--config" => {
+            Some((true, path.clone()))
         }
-    } else if let Err(err) = res {
-        println!("{err:?}");
+        Some([flag, path, ..]) if flag == "--config" => Some((false, path.clone())),
+        _ => None,
     }
+}
 
-    Ok(())
+/// Installs without the TUI print the progress as lines of text.
+fn print_progress(progress: &Progress) {
+    const WIDTH: usize = 30;
+    let ratio = match progress {
+        Progress::Resolving
+        | Progress::Done
+        | Progress::StepFinished { .. }
+        | Progress::Command(_) => return,
+        Progress::Step { .. } => {
+            println!("\n==> {}", progress.label());
+            return;
+        }
+        _ => progress.ratio().unwrap_or(0.0),
+    };
+    let filled = (ratio * WIDTH as f64) as usize;
+    println!(
+        "[{}{}] {}",
+        "#".repeat(filled),
+        "-".repeat(WIDTH - filled),
+        progress.label()
+    );
 }
 
 fn check_if_installed(file_path: &str) -> bool {
@@ -123,7 +246,69 @@ fn check_if_installed(file_path: &str) -> bool {
         }
     }
 
-    return false;
+    false
+}
+
+/// Shows the progress the install thread sends until it is over and the user quits.
+/// Keys do nothing while it runs, stopping halfway would leave a broken system.
+/// When a step fails, the user picks what to do and it is sent back with decide.
+fn run_install_screen<B: Backend + io::Write>(
+    terminal: &mut Terminal<B>,
+    progress: &mut InstallProgress,
+    events: &Receiver<InstallEvent>,
+    decide: &Sender<Recovery>,
+) -> io::Result<()> {
+    terminal.clear()?;
+    loop {
+        for event in events.try_iter() {
+            progress.apply(event);
+        }
+        terminal.draw(|f| install_progress_ui(f, progress))?;
+
+        // redraws at least every 100ms for the elapsed time
+        if !event::poll(Duration::from_millis(100))? {
+            continue;
+        }
+        let key = match event::read()? {
+            Event::Key(key) if key.kind == KeyEventKind::Press => key,
+            _ => continue,
+        };
+        if let Some(failure) = &progress.failure {
+            let recovery = match key.code {
+                KeyCode::Char('r') => Recovery::Retry,
+                KeyCode::Char('s') => Recovery::Skip,
+                KeyCode::Char('a') => Recovery::Abort,
+                KeyCode::Char('c') => {
+                    if let Some(command) = &failure.shell {
+                        open_shell(terminal, command)?;
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            progress.failure = None;
+            let _ = decide.send(recovery);
+        } else if progress.is_finished()
+            && matches!(key.code, KeyCode::Char('q') | KeyCode::Enter | KeyCode::Esc)
+        {
+            return Ok(());
+        }
+    }
+}
+
+/// Gives the terminal to a shell in the new system until it exits, then takes it back.
+fn open_shell<B: Backend + io::Write>(terminal: &mut Terminal<B>, command: &str) -> io::Result<()> {
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen, DisableMouseCapture)?;
+    terminal.show_cursor()?;
+    println!("Fix what made the step fail, then type exit to go back to the installer.");
+    if let Err(e) = Command::new("sh").arg("-c").arg(command).status() {
+        eprintln!("Could not open a shell: {}", e);
+    }
+
+    enable_raw_mode()?;
+    execute!(terminal.backend_mut(), EnterAlternateScreen, EnableMouseCapture)?;
+    terminal.clear()
 }
 
 fn run_app<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> io::Result<bool> {
@@ -1,19 +1,34 @@
 use crate::app::{App,Screens, SubScreens};
+use installer::{
+    archive::ArchiveSnapshot, mirrorlist::validate_server, offline::OfflineSource,
+    pacman::CacheSharing, pacman_conf::Repo,
+};
 use crossterm::event::KeyEvent;
 use crossterm::event::KeyCode;
 
 pub fn pacman_screen_events(app: &mut App, key: KeyEvent) {
-   let total_list_item = app.reflector_countries.len();
-    match key.code{
+    match app.current_sub_screen {
+        SubScreens::SetupMirrors => setup_mirrors_events(app, key),
+        SubScreens::SetupPacmanOptions => setup_pacman_options_events(app, key),
+        SubScreens::SetupCustomRepo => setup_custom_repo_events(app, key),
+        SubScreens::SetupOfflineSource => setup_offline_source_events(app, key),
+        SubScreens::SetupArchiveSnapshot => setup_archive_snapshot_events(app, key),
+        _ => select_mirror_country_events(app, key),
+    }
+}
+
+fn select_mirror_country_events(app: &mut App, key: KeyEvent) {
+    // Continue and every country
+    let total_list_item = app.reflector_countries.len() + 1;
+    match key.code {
         KeyCode::Up | KeyCode::Char('k') => {
             match app.list_selection.selected() {
                 Some(x) => {
-                    let index: usize;
-                    if x == 0 {
-                        index = total_list_item - 1;
+                    let index = if x == 0 {
+                        total_list_item - 1
                     } else {
-                        index = x - 1;
-                    }
+                        x - 1
+                    };
                     app.list_selection.select(Some(index));
                 }
                 None => {
@@ -31,13 +46,23 @@ pub fn pacman_screen_events(app: &mut App, key: KeyEvent) {
                 }
             };
         }
-        KeyCode::Enter => {
+        KeyCode::Enter | KeyCode::Char(' ') => {
             let selected_index = app.list_selection.selected().unwrap();
-            let selected = app.reflector_countries.clone()[selected_index];
-            app.selected_reflector_country = String::from(selected);
-            app.current_screen = Screens::StartScreen;
-            app.list_selection.select(Some(0));
-            app.pacman_setup_complete = true;
+            if selected_index == 0 {
+                app.current_sub_screen = SubScreens::SetupMirrors;
+                app.list_selection.select(Some(0));
+                return;
+            }
+
+            // toggles the country
+            let selected = app.reflector_countries[selected_index - 1].clone();
+            let countries = &mut app.pacman.mirrors.countries;
+            match countries.iter().position(|x| *x == selected) {
+                Some(x) => {
+                    countries.remove(x);
+                }
+                None => countries.push(selected),
+            }
         }
         KeyCode::Esc | KeyCode::Char('q') => {
             app.current_screen = Screens::StartScreen;
@@ -47,3 +72,364 @@ pub fn pacman_screen_events(app: &mut App, key: KeyEvent) {
         _ => {}
     }
 }
+
+fn setup_mirrors_events(app: &mut App, key: KeyEvent) {
+    // https only, age, count, every server given by hand, the new server field and Continue
+    let server_count = app.pacman.mirrors.servers.len();
+    let total_list_item = 5 + server_count;
+    let selection = app.list_selection.selected().unwrap_or(0);
+    let new_server = 3 + server_count;
+    let mirrors = &mut app.pacman.mirrors;
+    match key.code {
+        KeyCode::Up => {
+            let index = if selection == 0 {
+                total_list_item - 1
+            } else {
+                selection - 1
+            };
+            app.list_selection.select(Some(index));
+        }
+        KeyCode::Down | KeyCode::Tab => {
+            app.list_selection.select(Some((selection + 1) % total_list_item));
+        }
+        KeyCode::Char(x) if selection == new_server => app.mirror_server.push(x),
+        KeyCode::Backspace if selection == new_server => {
+            app.mirror_server.pop();
+        }
+        KeyCode::Enter | KeyCode::Char(' ') => match selection {
+            0 => mirrors.https_only = !mirrors.https_only,
+            // cycles through off, 6, 12, 24, 48 hours
+            1 => {
+                mirrors.max_age = match mirrors.max_age {
+                    None => Some(6),
+                    Some(x) if x >= 48 => None,
+                    Some(x) => Some(x * 2),
+                }
+            }
+            // cycles through all, 5, 10, 20, 50
+            2 => {
+                mirrors.count = match mirrors.count {
+                    None => Some(5),
+                    Some(5) => Some(10),
+                    Some(10) => Some(20),
+                    Some(20) => Some(50),
+                    Some(_) => None,
+                }
+            }
+            // selecting a server removes it
+            x if x < new_server => {
+                mirrors.servers.remove(x - 3);
+            }
+            x if x == new_server => {
+                let server = app.mirror_server.trim().to_string();
+                if let Err(e) = validate_server(&server) {
+                    app.error_console = e.to_string();
+                    return;
+                }
+                if !mirrors.servers.contains(&server) {
+                    mirrors.servers.push(server);
+                }
+                app.mirror_server.clear();
+                app.error_console.clear();
+                app.list_selection.select(Some(new_server + 1));
+            }
+            _ => {
+                app.current_sub_screen = SubScreens::SetupPacmanOptions;
+                app.list_selection.select(Some(0));
+            }
+        },
+        KeyCode::Esc => {
+            app.current_sub_screen = SubScreens::None;
+            app.list_selection.select(Some(0));
+        }
+        _ => {}
+    }
+}
+
+fn setup_pacman_options_events(app: &mut App, key: KeyEvent) {
+    // Color, ParallelDownloads, VerbosePkgLists, ILoveCandy, multilib, package cache,
+    // prefetch, keyring refresh, archive snapshot, every added repository,
+    // Add repository, Offline install and Continue
+    let repo_count = app.pacman.options.repos.len();
+    let total_list_item = 12 + repo_count;
+    let options = &mut app.pacman.options;
+    match key.code {
+        KeyCode::Up | KeyCode::Char('k') => {
+            match app.list_selection.selected() {
+                Some(x) => {
+                    let index = if x == 0 {
+                        total_list_item - 1
+                    } else {
+                        x - 1
+                    };
+                    app.list_selection.select(Some(index));
+                }
+                None => {
+                    app.list_selection.select(Some(0));
+                }
+            };
+        }
+        KeyCode::Down | KeyCode::Char('j') => {
+            match app.list_selection.selected() {
+                Some(x) => {
+                    app.list_selection.select(Some((x + 1) % total_list_item));
+                }
+                None => {
+                    app.list_selection.select(Some(0));
+                }
+            };
+        }
+        KeyCode::Enter | KeyCode::Char(' ') => {
+            match app.list_selection.selected().unwrap() {
+                0 => options.color = !options.color,
+                // cycles through unchanged, 5, 10, 15, 20
+                1 => {
+                    options.parallel_downloads = match options.parallel_downloads {
+                        None => Some(5),
+                        Some(x) if x >= 20 => None,
+                        Some(x) => Some(x + 5),
+                    }
+                }
+                2 => options.verbose_pkg_lists = !options.verbose_pkg_lists,
+                3 => options.candy = !options.candy,
+                4 => options.multilib = !options.multilib,
+                5 => {
+                    app.pacman.cache_sharing = match app.pacman.cache_sharing {
+                        CacheSharing::Separate => CacheSharing::Copy,
+                        CacheSharing::Copy => CacheSharing::Bind,
+                        CacheSharing::Bind => CacheSharing::Separate,
+                    }
+                }
+                6 => app.pacman.prefetch = !app.pacman.prefetch,
+                7 => app.pacman.refresh_keyring = !app.pacman.refresh_keyring,
+                8 => {
+                    app.current_sub_screen = SubScreens::SetupArchiveSnapshot;
+                    app.list_selection.select(Some(0));
+                }
+                // selecting a repository removes it
+                x if x < 9 + repo_count => {
+                    options.repos.remove(x - 9);
+                }
+                x if x == 9 + repo_count => {
+                    app.current_sub_screen = SubScreens::SetupCustomRepo;
+                    app.list_selection.select(Some(0));
+                }
+                x if x == 10 + repo_count => {
+                    app.current_sub_screen = SubScreens::SetupOfflineSource;
+                    app.list_selection.select(Some(0));
+                }
+                _ => {
+                    app.current_screen = Screens::StartScreen;
+                    app.current_sub_screen = SubScreens::None;
+                    app.list_selection.select(Some(0));
+                    app.pacman_setup_complete = true;
+                }
+            }
+        }
+        KeyCode::Esc | KeyCode::Char('q') => {
+            app.current_sub_screen = SubScreens::SetupMirrors;
+            app.list_selection.select(Some(0));
+        }
+        _ => {}
+    }
+}
+
+fn setup_custom_repo_events(app: &mut App, key: KeyEvent) {
+    // name, server, SigLevel, key file and Add
+    let total_list_item = 5;
+    let selection = app.list_selection.selected().unwrap_or(0);
+    match key.code {
+        KeyCode::Up => {
+            let index = if selection == 0 {
+                total_list_item - 1
+            } else {
+                selection - 1
+            };
+            app.list_selection.select(Some(index));
+        }
+        KeyCode::Down | KeyCode::Tab => {
+            app.list_selection.select(Some((selection + 1) % total_list_item));
+        }
+        KeyCode::Char(x) => match selection {
+            0 => app.repo_name.push(x),
+            1 => app.repo_server.push(x),
+            2 => app.repo_sig_level.push(x),
+            3 => app.repo_key_file.push(x),
+            _ => {}
+        },
+        KeyCode::Backspace => match selection {
+            0 => {
+                app.repo_name.pop();
+            }
+            1 => {
+                app.repo_server.pop();
+            }
+            2 => {
+                app.repo_sig_level.pop();
+            }
+            3 => {
+                app.repo_key_file.pop();
+            }
+            _ => {}
+        },
+        KeyCode::Enter if selection < total_list_item - 1 => {
+            app.list_selection.select(Some(selection + 1));
+        }
+        KeyCode::Enter => {
+            let optional = |x: &str| {
+                let x = x.trim();
+                if x.is_empty() {
+                    None
+                } else {
+                    Some(x.to_string())
+                }
+            };
+            let repo = Repo {
+                name: app.repo_name.trim().to_string(),
+                sig_level: optional(&app.repo_sig_level),
+                servers: optional(&app.repo_server).into_iter().collect(),
+                include: None,
+                key_file: optional(&app.repo_key_file),
+            };
+            if let Err(e) = repo.validate() {
+                app.error_console = e.to_string();
+                return;
+            }
+
+            let repos = &mut app.pacman.options.repos;
+            repos.retain(|x| x.name != repo.name);
+            repos.push(repo);
+            app.repo_name.clear();
+            app.repo_server.clear();
+            app.repo_sig_level.clear();
+            app.repo_key_file.clear();
+            app.error_console.clear();
+            app.current_sub_screen = SubScreens::SetupPacmanOptions;
+            app.list_selection.select(Some(0));
+        }
+        KeyCode::Esc => {
+            app.current_sub_screen = SubScreens::SetupPacmanOptions;
+            app.list_selection.select(Some(0));
+        }
+        _ => {}
+    }
+}
+
+fn setup_offline_source_events(app: &mut App, key: KeyEvent) {
+    // directory, kind, repository name, Use and Install from the mirrors
+    let total_list_item = 5;
+    let selection = app.list_selection.selected().unwrap_or(0);
+    match key.code {
+        KeyCode::Up => {
+            let index = if selection == 0 {
+                total_list_item - 1
+            } else {
+                selection - 1
+            };
+            app.list_selection.select(Some(index));
+        }
+        KeyCode::Down | KeyCode::Tab => {
+            app.list_selection.select(Some((selection + 1) % total_list_item));
+        }
+        KeyCode::Enter | KeyCode::Char(' ') if selection == 1 => {
+            app.offline_is_cache = !app.offline_is_cache;
+        }
+        KeyCode::Char(x) => match selection {
+            0 => app.offline_path.push(x),
+            2 => app.offline_repo_name.push(x),
+            _ => {}
+        },
+        KeyCode::Backspace => match selection {
+            0 => {
+                app.offline_path.pop();
+            }
+            2 => {
+                app.offline_repo_name.pop();
+            }
+            _ => {}
+        },
+        KeyCode::Enter if selection < 3 => {
+            app.list_selection.select(Some(selection + 1));
+        }
+        KeyCode::Enter => {
+            if selection == 3 {
+                let path = app.offline_path.trim().to_string();
+                let offline = if app.offline_is_cache {
+                    OfflineSource::Cache { path }
+                } else {
+                    OfflineSource::Repo {
+                        path,
+                        name: app.offline_repo_name.trim().to_string(),
+                    }
+                };
+                if let Err(e) = offline.validate() {
+                    app.error_console = e.to_string();
+                    return;
+                }
+                app.pacman.offline = Some(offline);
+            } else {
+                app.pacman.offline = None;
+            }
+
+            app.error_console.clear();
+            app.current_sub_screen = SubScreens::SetupPacmanOptions;
+            app.list_selection.select(Some(0));
+        }
+        KeyCode::Esc => {
+            app.current_sub_screen = SubScreens::SetupPacmanOptions;
+            app.list_selection.select(Some(0));
+        }
+        _ => {}
+    }
+}
+
+fn setup_archive_snapshot_events(app: &mut App, key: KeyEvent) {
+    // date, keep pinned, Pin and Install the current packages
+    let total_list_item = 4;
+    let selection = app.list_selection.selected().unwrap_or(0);
+    match key.code {
+        KeyCode::Up => {
+            let index = if selection == 0 {
+                total_list_item - 1
+            } else {
+                selection - 1
+            };
+            app.list_selection.select(Some(index));
+        }
+        KeyCode::Down | KeyCode::Tab => {
+            app.list_selection.select(Some((selection + 1) % total_list_item));
+        }
+        KeyCode::Enter | KeyCode::Char(' ') if selection == 1 => {
+            app.snapshot_keep_pinned = !app.snapshot_keep_pinned;
+        }
+        KeyCode::Char(x) if selection == 0 => app.snapshot_date.push(x),
+        KeyCode::Backspace if selection == 0 => {
+            app.snapshot_date.pop();
+        }
+        KeyCode::Enter if selection == 0 => {
+            app.list_selection.select(Some(1));
+        }
+        KeyCode::Enter => {
+            if selection == 2 {
+                match ArchiveSnapshot::new(&app.snapshot_date, app.snapshot_keep_pinned) {
+                    Ok(x) => app.pacman.snapshot = Some(x),
+                    Err(e) => {
+                        app.error_console = e.to_string();
+                        return;
+                    }
+                }
+            } else {
+                app.pacman.snapshot = None;
+            }
+
+            app.error_console.clear();
+            app.current_sub_screen = SubScreens::SetupPacmanOptions;
+            app.list_selection.select(Some(0));
+        }
+        KeyCode::Esc => {
+            app.current_sub_screen = SubScreens::SetupPacmanOptions;
+            app.list_selection.select(Some(0));
+        }
+        _ => {}
+    }
+}
@@ -5,13 +5,235 @@ use ratatui::{
     Frame,
 };
 
-use crate::app::App;
+use installer::pacman::CacheSharing;
+
+use crate::app::{App, SubScreens};
 
 pub fn pacman_setup_ui(f: &mut Frame, chunk: Rect, app: &mut App) {
-    let list = List::new(app.reflector_countries.to_vec())
+    match app.current_sub_screen {
+        SubScreens::SetupMirrors => setup_mirrors_ui(f, chunk, app),
+        SubScreens::SetupPacmanOptions => setup_pacman_options_ui(f, chunk, app),
+        SubScreens::SetupCustomRepo => setup_custom_repo_ui(f, chunk, app),
+        SubScreens::SetupOfflineSource => setup_offline_source_ui(f, chunk, app),
+        SubScreens::SetupArchiveSnapshot => setup_archive_snapshot_ui(f, chunk, app),
+        _ => select_mirror_country_ui(f, chunk, app),
+    }
+}
+
+fn select_mirror_country_ui(f: &mut Frame, chunk: Rect, app: &mut App) {
+    let countries = &app.pacman.mirrors.countries;
+    let mut items = vec![format!(
+        "Continue ({})",
+        if countries.is_empty() {
+            "every country".to_string()
+        } else {
+            countries.join(", ")
+        }
+    )];
+    items.extend(app.reflector_countries.iter().map(|x| {
+        format!(
+            "[{}] {}",
+            if countries.contains(x) { "x" } else { " " },
+            x
+        )
+    }));
+
+    let list = List::new(items)
+        .block(
+            Block::default()
+                .title("Select the countries to get mirrors from ")
+                .borders(Borders::ALL),
+        )
+        .highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+        .highlight_symbol(">>")
+        .repeat_highlight_symbol(true);
+
+    f.render_stateful_widget(list, chunk, &mut app.list_selection);
+}
+
+fn setup_mirrors_ui(f: &mut Frame, chunk: Rect, app: &mut App) {
+    let mirrors = &app.pacman.mirrors;
+    let mut items = vec![
+        format!("[{}] HTTPS only", if mirrors.https_only { "x" } else { " " }),
+        format!(
+            "Synced within: {}",
+            match mirrors.max_age {
+                Some(x) => format!("{} hours", x),
+                None => "any time".to_string(),
+            }
+        ),
+        format!(
+            "Mirrors kept: {}",
+            match mirrors.count {
+                Some(x) => format!("fastest {}", x),
+                None => "all".to_string(),
+            }
+        ),
+    ];
+    items.extend(
+        mirrors
+            .servers
+            .iter()
+            .map(|x| format!("Server = {} (enter to remove)", x)),
+    );
+    items.push(format!("New server: {}", app.mirror_server));
+    items.push("Continue".to_string());
+
+    let list = List::new(items)
+        .block(
+            Block::default()
+                .title("Mirrors, servers given by hand go before the ones reflector picks: ")
+                .borders(Borders::ALL),
+        )
+        .highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+        .highlight_symbol(">>")
+        .repeat_highlight_symbol(true);
+
+    f.render_stateful_widget(list, chunk, &mut app.list_selection);
+}
+
+fn setup_pacman_options_ui(f: &mut Frame, chunk: Rect, app: &mut App) {
+    let options = &app.pacman.options;
+    let toggle = |x: bool| if x { "[x]" } else { "[ ]" };
+    let mut items = vec![
+        format!("{} Color", toggle(options.color)),
+        format!(
+            "ParallelDownloads: {}",
+            match options.parallel_downloads {
+                Some(x) => x.to_string(),
+                None => "unchanged".to_string(),
+            }
+        ),
+        format!("{} VerbosePkgLists", toggle(options.verbose_pkg_lists)),
+        format!("{} ILoveCandy", toggle(options.candy)),
+        format!("{} multilib repository", toggle(options.multilib)),
+        format!(
+            "Package cache: {}",
+            match app.pacman.cache_sharing {
+                CacheSharing::Separate => "separate",
+                CacheSharing::Copy => "copy the live cache into the new system",
+                CacheSharing::Bind => "use the live cache for the whole install",
+            }
+        ),
+        format!(
+            "{} Download every package before installing",
+            toggle(app.pacman.prefetch)
+        ),
+        format!(
+            "{} Update archlinux-keyring before installing if it is outdated",
+            toggle(app.pacman.refresh_keyring)
+        ),
+        format!(
+            "Archive snapshot: {}",
+            match &app.pacman.snapshot {
+                Some(x) if x.keep_pinned => format!("{}, kept pinned", x.date),
+                Some(x) => x.date.clone(),
+                None => "off".to_string(),
+            }
+        ),
+    ];
+    items.extend(
+        options
+            .repos
+            .iter()
+            .map(|x| format!("[{}] (enter to remove)", x.name)),
+    );
+    items.push("Add repository".to_string());
+    items.push(format!(
+        "Offline install: {}",
+        match &app.pacman.offline {
+            Some(x) => x.path().to_string(),
+            None => "off".to_string(),
+        }
+    ));
+    items.push("Continue".to_string());
+
+    let list = List::new(items)
+        .block(
+            Block::default()
+                .title("pacman.conf options, for the live system and the installed one ")
+                .borders(Borders::ALL),
+        )
+        .highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+        .highlight_symbol(">>")
+        .repeat_highlight_symbol(true);
+
+    f.render_stateful_widget(list, chunk, &mut app.list_selection);
+}
+
+fn setup_custom_repo_ui(f: &mut Frame, chunk: Rect, app: &mut App) {
+    let items = vec![
+        format!("Name:      {}", app.repo_name),
+        format!("Server:    {}", app.repo_server),
+        format!("SigLevel:  {}", app.repo_sig_level),
+        format!("Key file:  {}", app.repo_key_file),
+        "Add".to_string(),
+    ];
+
+    let list = List::new(items)
+        .block(
+            Block::default()
+                .title("Repository, $repo and $arch are allowed in the server, the key file is optional: ")
+                .borders(Borders::ALL),
+        )
+        .highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+        .highlight_symbol(">>")
+        .repeat_highlight_symbol(true);
+
+    f.render_stateful_widget(list, chunk, &mut app.list_selection);
+}
+
+fn setup_offline_source_ui(f: &mut Frame, chunk: Rect, app: &mut App) {
+    let items = vec![
+        format!("Directory:        {}", app.offline_path),
+        format!(
+            "Kind:             {}",
+            if app.offline_is_cache {
+                "package cache, a database is generated"
+            } else {
+                "repository made with repo-add"
+            }
+        ),
+        format!(
+            "Repository name:  {}",
+            if app.offline_is_cache {
+                "not needed"
+            } else {
+                &app.offline_repo_name
+            }
+        ),
+        "Install from this directory".to_string(),
+        "Install from the mirrors".to_string(),
+    ];
+
+    let list = List::new(items)
+        .block(
+            Block::default()
+                .title("Offline install, every package has to be in the directory: ")
+                .borders(Borders::ALL),
+        )
+        .highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+        .highlight_symbol(">>")
+        .repeat_highlight_symbol(true);
+
+    f.render_stateful_widget(list, chunk, &mut app.list_selection);
+}
+
+fn setup_archive_snapshot_ui(f: &mut Frame, chunk: Rect, app: &mut App) {
+    let items = vec![
+        format!("Date (YYYY-MM-DD):  {}", app.snapshot_date),
+        format!(
+            "[{}] Keep the new system pinned after the install",
+            if app.snapshot_keep_pinned { "x" } else { " " }
+        ),
+        "Install the packages as of this date".to_string(),
+        "Install the current packages".to_string(),
+    ];
+
+    let list = List::new(items)
         .block(
             Block::default()
-                .title("Select a country to set the mirrorlist to ")
+                .title("Arch Linux Archive snapshot, replaces the mirrors from reflector: ")
                 .borders(Borders::ALL),
         )
         .highlight_style(Style::new().add_modifier(Modifier::REVERSED))
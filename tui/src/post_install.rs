@@ -11,7 +11,7 @@ pub fn run_post_install() {
     println!("Before starting, make sure you read through this:
        1. For installing extra packages from pacman, make a text file and enter the path when prompted (eg. packages.txt)
        2. For installing aur packages, do the same with another file (aur_packages.txt).
-       3. If you add the wrong name for any package, the installation will fail mid-way and leave a whole lot of mess. Make sure the package name is correct.
+       3. Package names are checked before anything is installed. Misspelled names stop the installation, with the closest names as suggestions. AUR packages belong in the AUR packages file.
        4. [IMPORTANT] If you are not ME, do not run the ParaPsychic-specific scripts. If you do not know what this is, then you should be good to go.
        5. The entire process will not involve a TUI like the install section because I'm having trouble figuring out doing some stuff completely in the background/unattended.
        6. Keep an eye out on the installation process.
@@ -53,6 +53,7 @@ pub fn run_post_install() {
         Ok(_) => {}
         Err(e) => {
             let _ = append_to_file("log.txt", &e.to_string());
+            println!("{}", e);
             println!("Installing packages has failed. Please check the log file");
         }
     }
@@ -68,14 +69,12 @@ pub fn run_post_install() {
     buffer.clear();
     stdin.read_line(&mut buffer).unwrap();
     let index: usize = buffer.trim().parse().unwrap_or(1);
-    let de: DesktopEnvironment;
-
-    match index {
-        1 => de = DesktopEnvironment::Gnome,
-        2 => de = DesktopEnvironment::KDE,
-        3 => de = DesktopEnvironment::Hyprland,
-        _ => de = DesktopEnvironment::Gnome,
-    }
+    let de = match index {
+        1 => DesktopEnvironment::Gnome,
+        2 => DesktopEnvironment::KDE,
+        3 => DesktopEnvironment::Hyprland,
+        _ => DesktopEnvironment::Gnome,
+    };
 
     match post_install.install_desktop(de) {
         Ok(_) => {}
@@ -1,4 +1,5 @@
 use crate::app::{App, Screens, SubScreens};
+use installer::estimate_size;
 use crossterm::event::KeyCode;
 use crossterm::event::KeyEvent;
 use std::rc::Rc;
@@ -9,12 +10,11 @@ pub fn start_screen_events(app: &mut App, key: KeyEvent) {
         KeyCode::Up | KeyCode::Char('k') => {
             match app.list_selection.selected() {
                 Some(x) => {
-                    let index: usize;
-                    if x == 0 {
-                        index = total_list_item - 1;
+                    let index = if x == 0 {
+                        total_list_item - 1
                     } else {
-                        index = x - 1;
-                    }
+                        x - 1
+                    };
                     app.list_selection.select(Some(index));
                 }
                 None => {
@@ -41,6 +41,7 @@ pub fn start_screen_events(app: &mut App, key: KeyEvent) {
                         .map(|x| x.to_string())
                         .collect::<Vec<String>>();
                     disks.push("Continue (to select boot disk)".to_string());
+                    disks.push("Install into a directory (container rootfs)".to_string());
                     app.filesystem_drives_list = Rc::new(disks);
                 }
 
@@ -49,19 +50,44 @@ pub fn start_screen_events(app: &mut App, key: KeyEvent) {
                 app.list_selection.select(Some(0));
             }
             1 => {
+                // falls back to the built-in list without network
+                if !app.reflector_countries_loaded {
+                    app.reflector_countries_loaded = true;
+                    if let Ok(countries) = app.pacman.list_countries() {
+                        if !countries.is_empty() {
+                            app.reflector_countries = Rc::new(countries);
+                        }
+                    }
+                }
                 app.current_screen = Screens::Pacman;
+                app.current_sub_screen = SubScreens::None;
                 app.list_selection.select(Some(0));
             }
             2 => {
                 app.current_screen = Screens::Essentials;
                 app.current_sub_screen = SubScreens::SetupSwap;
-                app.list_selection.select(Some(0));
+                let index = app
+                    .swap_sizes_list
+                    .iter()
+                    .position(|x| *x == app.swap_size)
+                    .unwrap_or(0);
+                app.list_selection.select(Some(index));
             }
             3 => {
                 if app.pacman_setup_complete
                     && app.filesystem_setup_complete
                     && app.essentials_setup_complete
                 {
+                    app.size_estimate = Some(
+                        estimate_size(
+                            &mut app.filesystem,
+                            &mut app.base_installer,
+                            &mut app.essentials,
+                            &mut app.pacman,
+                            app.swap_size,
+                        )
+                        .map_err(|e| e.to_string()),
+                    );
                     app.current_screen = Screens::Installing;
                     app.current_sub_screen = SubScreens::ConfirmInstallation;
                     app.list_selection.select(Some(0));
@@ -58,14 +58,24 @@ pub fn ui(f: &mut Frame, app: &mut App) {
     SubScreens::EraseEFI => Span::styled("Erase EFI ", Style::default().fg(Color::DarkGray)),
     SubScreens::EraseHome => Span::styled("Erase Home ", Style::default().fg(Color::DarkGray)),
     SubScreens::MountExtraPartition => Span::styled("Mount Extra Partitions ", Style::default().fg(Color::DarkGray)),
-    SubScreens::MountExtraPartition__Insert => Span::styled("Add New Partition", Style::default().fg(Color::DarkGray)),
+    SubScreens::MountExtraPartitionInsert => Span::styled("Add New Partition", Style::default().fg(Color::DarkGray)),
     SubScreens::ConfirmPartitions => Span::styled("Confirm Partitions ", Style::default().fg(Color::DarkGray)),
+    SubScreens::SetupTargetDirectory => Span::styled("Install Into Directory ", Style::default().fg(Color::DarkGray)),
+    SubScreens::SetupTargetRoot => Span::styled("Target Root ", Style::default().fg(Color::DarkGray)),
+    SubScreens::SetupMirrors => Span::styled("Mirrors ", Style::default().fg(Color::DarkGray)),
+    SubScreens::SetupPacmanOptions => Span::styled("Pacman Options ", Style::default().fg(Color::DarkGray)),
+    SubScreens::SetupCustomRepo => Span::styled("Add Repository ", Style::default().fg(Color::DarkGray)),
+    SubScreens::SetupOfflineSource => Span::styled("Offline Install ", Style::default().fg(Color::DarkGray)),
+    SubScreens::SetupArchiveSnapshot => Span::styled("Archive Snapshot ", Style::default().fg(Color::DarkGray)),
     SubScreens::SetupSwap => Span::styled("Setup Swap ", Style::default().fg(Color::DarkGray)),
     SubScreens::SelectTimezone => Span::styled("Select Timezone ", Style::default().fg(Color::DarkGray)),
     SubScreens::SelectLocale => Span::styled("Select Locale ", Style::default().fg(Color::DarkGray)),
     SubScreens::SetupHostname => Span::styled("Setup Hostname ", Style::default().fg(Color::DarkGray)),
     SubScreens::SetupRootPassword => Span::styled("Setup Root Password ", Style::default().fg(Color::DarkGray)),
     SubScreens::SetupBootloader => Span::styled("Setup Bootloader ", Style::default().fg(Color::DarkGray)),
+    SubScreens::SetupKernels => Span::styled("Select Kernels ", Style::default().fg(Color::DarkGray)),
+    SubScreens::SetupKernelCmdline => Span::styled("Kernel Parameters ", Style::default().fg(Color::DarkGray)),
+    SubScreens::SetupSecureBoot => Span::styled("Secure Boot ", Style::default().fg(Color::DarkGray)),
     SubScreens::SetupSuperUserUtility => Span::styled("Setup SuperUser Utility ", Style::default().fg(Color::DarkGray)),
     SubScreens::SetupUser => Span::styled("Setup User ", Style::default().fg(Color::DarkGray)),
     SubScreens::ConfirmInstallation => Span::styled("Confirm  Installation ", Style::default().fg(Color::DarkGray)),